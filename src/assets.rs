@@ -1,8 +1,23 @@
 use crate::{
-  tiles::{TILESET_HEIGHT, TILESET_WIDTH},
+  game::{
+    input::{Action, Actions},
+    net::{NetConfig, NetSession},
+    replay::ReplayViewer,
+    save::PendingLoad,
+    unit_defs::{UnitDefinitionsHandle, UNIT_DEFINITIONS_PATH},
+    validate, MatchConfig, StartMatch,
+  },
+  sprites::{SpriteManifestHandle, SPRITE_MANIFEST_PATH},
+  tiles::{LoadedTerrain, TerrainRegistryHandle, TERRAIN_REGISTRY_PATH},
   GlobalState,
 };
-use bevy::prelude::*;
+use bevy::{
+  app::AppExit,
+  asset::{
+    LoadState, RecursiveDependencyLoadState, UntypedAssetLoadFailedEvent,
+  },
+  prelude::*,
+};
 use bevy_ecs_ldtk::assets::LdtkProject;
 use std::sync::OnceLock;
 
@@ -12,13 +27,78 @@ pub struct LoadAssetsPlugin;
 
 impl Plugin for LoadAssetsPlugin {
   fn build(&self, app: &mut App) {
-    app.add_systems(Startup, load_assets).add_systems(
-      Update,
-      check_loading.run_if(in_state(GlobalState::Loading)),
-    );
+    app
+      .init_resource::<RequiredAssets>()
+      .add_systems(Startup, load_assets)
+      .add_systems(OnEnter(GlobalState::Loading), spawn_loading_screen)
+      .add_systems(OnExit(GlobalState::Loading), despawn_loading_screen)
+      .add_systems(
+        Update,
+        (update_loading_bar, check_loading)
+          .chain()
+          .run_if(in_state(GlobalState::Loading)),
+      )
+      .add_systems(OnEnter(GlobalState::LoadFailed), show_load_failure)
+      .add_systems(
+        Update,
+        quit_after_load_failure.run_if(in_state(GlobalState::LoadFailed)),
+      );
+  }
+}
+
+/// Every asset the game cannot start without. Loading waits for all of them,
+/// and for everything they depend on in turn.
+#[derive(Resource, Default)]
+pub struct RequiredAssets(Vec<UntypedHandle>);
+
+impl RequiredAssets {
+  pub fn add<A: Asset>(&mut self, handle: &Handle<A>) {
+    self.0.push(handle.clone().untyped());
   }
+
+  fn loaded_count(&self, server: &AssetServer) -> usize {
+    self
+      .0
+      .iter()
+      .filter(|handle| server.is_loaded_with_dependencies(handle.id()))
+      .count()
+  }
+
+  /// The path of the first required asset that failed to load, either itself
+  /// or through one of its dependencies.
+  fn failed_path(&self, server: &AssetServer) -> Option<String> {
+    self.0.iter().find_map(|handle| {
+      let failed = server.get_load_state(handle.id())
+        == Some(LoadState::Failed)
+        || server.get_recursive_dependency_load_state(handle.id())
+          == Some(RecursiveDependencyLoadState::Failed);
+      failed.then(|| {
+        server
+          .get_path(handle.id())
+          .map_or_else(|| format!("{:?}", handle.id()), |path| path.to_string())
+      })
+    })
+  }
+}
+
+/// Which asset stopped the game from loading, and why.
+#[derive(Resource)]
+pub struct LoadFailure {
+  path: String,
+  reason: String,
 }
 
+#[derive(Default, Component)]
+struct LoadingScreen;
+
+#[derive(Default, Component)]
+struct LoadingBarFill;
+
+/// While present, the game stays in [`GlobalState::Loading`] even once every
+/// asset has loaded.
+#[derive(Resource)]
+pub struct LoadingHold;
+
 #[derive(Resource, Clone)]
 pub struct AtlasInfo {
   pub image: Handle<Image>,
@@ -40,28 +120,215 @@ pub fn load_assets(
   mut commands: Commands,
   server: Res<AssetServer>,
   mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+  mut required_assets: ResMut<RequiredAssets>,
 ) {
+  // the layout is filled in from the sprite manifest once it loads
   let atlas_info = AtlasInfo {
     image: server.load("tilemap.png"),
-    layout: layouts.add(TextureAtlasLayout::from_grid(
-      Vec2 { x: 16.0, y: 16.0 },
-      TILESET_WIDTH,
-      TILESET_HEIGHT,
-      None,
-      None,
-    )),
+    layout: layouts.add(TextureAtlasLayout::new_empty(Vec2::ZERO)),
   };
+  let sprite_manifest = server.load(SPRITE_MANIFEST_PATH);
+  let ldtk_handle = server.load("holmium.ldtk");
+  let unit_definitions = server.load(UNIT_DEFINITIONS_PATH);
+  let terrain_registry = server.load(TERRAIN_REGISTRY_PATH);
+  required_assets.add(&atlas_info.image);
+  required_assets.add(&sprite_manifest);
+  required_assets.add(&ldtk_handle);
+  required_assets.add(&unit_definitions);
+  required_assets.add(&terrain_registry);
   let _ = ATLAS_INFO.set(atlas_info.clone());
   commands.insert_resource(atlas_info);
-  commands.insert_resource(LdtkWorldHandle(server.load("holmium.ldtk")));
+  commands.insert_resource(LdtkWorldHandle(ldtk_handle));
+  commands.insert_resource(UnitDefinitionsHandle(unit_definitions));
+  commands.insert_resource(TerrainRegistryHandle(terrain_registry));
+  commands.insert_resource(SpriteManifestHandle(sprite_manifest));
 }
 
+/// A bar across the middle of the screen, filled as the required assets
+/// load. It is drawn without any of them, so that it shows from the start.
+fn spawn_loading_screen(mut commands: Commands) {
+  commands.spawn((Camera2dBundle::default(), LoadingScreen));
+  commands
+    .spawn((
+      NodeBundle {
+        style: Style {
+          width: Val::Percent(100.0),
+          height: Val::Percent(100.0),
+          justify_content: JustifyContent::Center,
+          align_items: AlignItems::Center,
+          ..default()
+        },
+        ..default()
+      },
+      LoadingScreen,
+    ))
+    .with_children(|screen| {
+      screen
+        .spawn(NodeBundle {
+          style: Style {
+            width: Val::Px(240.0),
+            height: Val::Px(12.0),
+            border: UiRect::all(Val::Px(2.0)),
+            ..default()
+          },
+          background_color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+          border_color: Color::rgb_u8(247, 243, 183).into(),
+          ..default()
+        })
+        .with_children(|bar| {
+          bar.spawn((
+            NodeBundle {
+              style: Style {
+                width: Val::Percent(0.0),
+                height: Val::Percent(100.0),
+                ..default()
+              },
+              background_color: Color::rgb_u8(247, 243, 183).into(),
+              ..default()
+            },
+            LoadingBarFill,
+          ));
+        });
+    });
+}
+
+fn despawn_loading_screen(
+  mut commands: Commands,
+  loading_screens: Query<Entity, With<LoadingScreen>>,
+) {
+  for loading_screen in loading_screens.iter() {
+    commands.entity(loading_screen).despawn_recursive();
+  }
+}
+
+fn update_loading_bar(
+  server: Res<AssetServer>,
+  required_assets: Res<RequiredAssets>,
+  mut fills: Query<&mut Style, With<LoadingBarFill>>,
+) {
+  let total = required_assets.0.len().max(1);
+  let width = Val::Percent(
+    required_assets.loaded_count(&server) as f32 / total as f32 * 100.0,
+  );
+  for mut fill in fills.iter_mut() {
+    if fill.width != width {
+      fill.width = width;
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn check_loading(
+  mut commands: Commands,
   server: Res<AssetServer>,
-  atlas_info: Res<AtlasInfo>,
+  required_assets: Res<RequiredAssets>,
+  mut failed_events: EventReader<UntypedAssetLoadFailedEvent>,
+  ldtk_handle: Res<LdtkWorldHandle>,
+  projects: Res<Assets<LdtkProject>>,
+  loaded_terrain: LoadedTerrain,
+  match_config: Res<MatchConfig>,
+  loading_hold: Option<Res<LoadingHold>>,
+  start_match: Option<Res<StartMatch>>,
   mut next_global_state: ResMut<NextState<GlobalState>>,
 ) {
-  if server.is_loaded_with_dependencies(&atlas_info.image) {
-    next_global_state.set(GlobalState::Game);
+  // the event carries the asset's own error, where the load states only say
+  // that something went wrong
+  let load_failure = failed_events
+    .read()
+    .next()
+    .map(|failed| LoadFailure {
+      path: failed.path.to_string(),
+      reason: failed.error.to_string(),
+    })
+    .or_else(|| {
+      required_assets
+        .failed_path(&server)
+        .map(|path| LoadFailure {
+          path,
+          reason: "one of its dependencies could not be loaded".to_string(),
+        })
+    });
+  if let Some(load_failure) = load_failure {
+    error!(
+      "could not load {}: {}",
+      load_failure.path, load_failure.reason
+    );
+    commands.insert_resource(load_failure);
+    next_global_state.set(GlobalState::LoadFailed);
+    return;
+  }
+
+  if loading_hold.is_some()
+    || required_assets.loaded_count(&server) < required_assets.0.len()
+  {
+    return;
+  }
+
+  if start_match.is_none() {
+    next_global_state.set(GlobalState::MainMenu);
+    return;
+  }
+
+  // a level that cannot be played is turned away here, with the reasons
+  // shown on the main menu, rather than failing part way into the match
+  let level_problems = projects
+    .get(&ldtk_handle.0)
+    .zip(loaded_terrain.get())
+    .and_then(|(project, registry)| {
+      validate::check_match_level(project, registry, &match_config)
+    });
+  match level_problems {
+    Some(level_problems) => {
+      for problem in level_problems.problems.iter() {
+        error!("{}: {}", level_problems.level, problem);
+      }
+      commands.insert_resource(level_problems);
+      commands.remove_resource::<StartMatch>();
+      commands.remove_resource::<PendingLoad>();
+      commands.remove_resource::<NetSession>();
+      commands.remove_resource::<NetConfig>();
+      commands.remove_resource::<ReplayViewer>();
+      next_global_state.set(GlobalState::MainMenu);
+    }
+    None => next_global_state.set(GlobalState::Game),
+  }
+}
+
+/// Names the asset that failed to load in place of the game. The window
+/// assets may be the ones missing, so this sticks to Bevy's built-in font.
+fn show_load_failure(mut commands: Commands, load_failure: Res<LoadFailure>) {
+  commands.spawn(Camera2dBundle::default());
+  commands.spawn(
+    TextBundle::from_section(
+      format!(
+        "Could not load {}\n{}\n\nPress confirm or cancel to quit.",
+        load_failure.path, load_failure.reason
+      ),
+      TextStyle {
+        font_size: 18.0,
+        color: Color::WHITE,
+        ..default()
+      },
+    )
+    .with_background_color(Color::rgba(0.3, 0.0, 0.0, 0.85))
+    .with_style(Style {
+      position_type: PositionType::Absolute,
+      left: Val::Px(16.0),
+      top: Val::Px(16.0),
+      right: Val::Px(16.0),
+      padding: UiRect::all(Val::Px(8.0)),
+      ..default()
+    }),
+  );
+}
+
+fn quit_after_load_failure(
+  actions: Res<Actions>,
+  mut app_exits: EventWriter<AppExit>,
+) {
+  if actions.any_just_pressed(Action::Confirm)
+    || actions.any_just_pressed(Action::Cancel)
+  {
+    app_exits.send(AppExit);
   }
 }
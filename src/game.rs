@@ -1,6 +1,8 @@
 use crate::{
   assets::{LdtkWorldHandle, ATLAS_INFO},
   game::units::UnitSpawnQueues,
+  tiles::LoadedTerrain,
+  windows::WindowFocus,
   GlobalState,
 };
 use bevy::{prelude::*, render::camera::ScalingMode};
@@ -10,24 +12,93 @@ use bevy_ecs_tilemap::{
   tiles::TileStorage,
   TilemapBundle,
 };
+use serde::{Deserialize, Serialize};
 
 use self::{
-  input::MovementInput,
-  units::{Unit, UnitAssociations, UnitSpawnLocationBundle},
+  autosave::{AutosaveSettings, RecoveryOffer},
+  camera::CameraView,
+  checksum::{Desync, TurnChecksums},
+  commands::{
+    AppliedGameCommand, CommandRequest, GameCommand, HealthChanged, MatchOver,
+    PhaseStarted,
+  },
+  danger::DangerZone,
+  input::{Actions, GamepadAssignments, MovementInput, PointerInput},
+  mapgen::{GeneratedMap, GeneratedSpawns},
+  net::{NetConfig, NetSession},
+  phase_banner::PhaseBanner,
+  replay::{ReplayRecorder, ReplayViewer},
+  rng::MatchRng,
+  save::PendingLoad,
+  units::{SpawnLayout, Unit, UnitAssociations, UnitSpawnLocationBundle},
 };
 
 pub mod arrows;
+pub mod autosave;
+pub mod autotile;
+pub mod camera;
+pub mod checksum;
+pub mod commands;
 pub mod cursor;
+pub mod danger;
+#[cfg(test)]
+mod fixtures;
+pub mod health;
+pub mod info_panel;
 pub mod input;
+pub mod mapgen;
+pub mod net;
+pub mod phase_banner;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod unit_defs;
 pub mod units;
+pub mod validate;
 
-#[derive(Default, Debug, PartialEq, Eq, Hash, Clone, Copy, States)]
+#[derive(
+  Default,
+  Debug,
+  PartialEq,
+  Eq,
+  Hash,
+  Clone,
+  Copy,
+  States,
+  Serialize,
+  Deserialize,
+)]
 pub enum TurnState {
   #[default]
   Player1,
   Player2,
 }
 
+impl TurnState {
+  pub fn next(&self) -> TurnState {
+    match self {
+      TurnState::Player1 => TurnState::Player2,
+      TurnState::Player2 => TurnState::Player1,
+    }
+  }
+
+  /// The name of the faction playing this turn, as shown to players.
+  pub fn faction_name(&self) -> &'static str {
+    match self {
+      TurnState::Player1 => "Blue",
+      TurnState::Player2 => "Red",
+    }
+  }
+
+  /// The faction's colour, as painted on the faction's `backdrop` sprite.
+  pub fn faction_color(&self) -> Color {
+    match self {
+      TurnState::Player1 => Color::rgb_u8(51, 136, 222),
+      TurnState::Player2 => Color::rgb_u8(236, 39, 63),
+    }
+  }
+}
+
 #[derive(Default, Debug, PartialEq, Eq, Hash, Clone, Copy, States)]
 pub enum GameState {
   #[default]
@@ -43,46 +114,235 @@ impl Plugin for GamePlugin {
       .init_state::<TurnState>()
       .init_state::<GameState>()
       .add_event::<MovementInput>()
-      .register_ldtk_entity::<UnitSpawnLocationBundle>("BLUE_SPAWN")
-      .register_ldtk_entity::<UnitSpawnLocationBundle>("RED_SPAWN")
+      .add_event::<PointerInput>()
+      .add_event::<CommandRequest>()
+      .add_event::<GameCommand>()
+      .add_event::<AppliedGameCommand>()
+      .add_event::<HealthChanged>()
+      .add_event::<MatchOver>()
+      .add_event::<PhaseStarted>()
+      .init_asset::<unit_defs::UnitDefinitions>()
+      .init_asset_loader::<unit_defs::UnitDefinitionsLoader>()
+      .register_ldtk_entity::<UnitSpawnLocationBundle>(units::BLUE_SPAWN)
+      .register_ldtk_entity::<UnitSpawnLocationBundle>(units::RED_SPAWN)
       .insert_resource(UnitAssociations::default())
+      .init_resource::<MatchConfig>()
+      .init_resource::<AutosaveSettings>()
+      .init_resource::<DangerZone>()
+      .init_resource::<Actions>()
+      .init_resource::<GamepadAssignments>()
+      .add_systems(
+        Startup,
+        (
+          input::load_key_bindings,
+          input::load_gamepad_bindings,
+          replay::load_replay,
+          autosave::check_previous_session,
+          net::start_network_session.run_if(resource_exists::<NetConfig>),
+        ),
+      )
+      // a match set up in game may host one
+      .add_systems(
+        OnExit(GlobalState::MatchSetup),
+        net::start_network_session.run_if(
+          resource_exists::<NetConfig>
+            .and_then(not(resource_exists::<NetSession>)),
+        ),
+      )
+      .add_systems(
+        PreUpdate,
+        (input::assign_gamepads, input::read_actions)
+          .chain()
+          .after(bevy::input::InputSystem),
+      )
+      .add_systems(Last, autosave::clear_session_lock)
+      .add_systems(Update, validate::log_level_problems)
       .add_systems(
         OnEnter(GlobalState::Game),
-        (init_world, cursor::init_cursor).chain(),
+        (
+          init_world,
+          cursor::init_cursor,
+          info_panel::spawn_info_panel,
+          replay::start_recording,
+        )
+          .chain()
+          // resuming from the pause menu finds the world already there
+          .run_if(not(resource_exists::<GlobalCamera>)),
       )
       .add_systems(
-        OnEnter(GameState::ArrowMovement),
-        arrows::calculate_moveable_region,
+        OnEnter(GlobalState::Loading),
+        teardown_world.run_if(resource_exists::<GlobalCamera>),
       )
-      .add_systems(OnExit(GameState::ArrowMovement), arrows::clear_drawn_arrows)
       .add_systems(
-        OnTransition {
-          from: TurnState::Player2,
-          to: TurnState::Player1,
-        },
-        units::refresh_units,
+        OnEnter(GlobalState::MainMenu),
+        teardown_world.run_if(resource_exists::<GlobalCamera>),
       )
+      .add_systems(
+        OnEnter(GameState::ArrowMovement),
+        (danger::clear_danger_zone, arrows::calculate_moveable_region).chain(),
+      )
+      .add_systems(OnExit(GameState::ArrowMovement), arrows::clear_drawn_arrows)
       .add_systems(
         Update,
         (
           crate::tiles::cache_tile_types,
+          autotile::paint_terrain
+            .run_if(resource_exists_and_changed::<crate::tiles::TileTypes>),
+          units::relocate_stranded_units
+            .run_if(resource_exists_and_changed::<crate::tiles::TileTypes>),
           units::fill_unit_spawn_locations,
+          units::fill_generated_spawns
+            .run_if(resource_added::<GeneratedSpawns>),
+          save::restore_pending_load.run_if(
+            resource_exists::<PendingLoad>
+              .and_then(resource_added::<SpawnLayout>),
+          ),
           units::update_unit_associations_resource,
           input::movement_events,
-          cursor::move_cursor.run_if(in_state(GameState::CursorMovement)),
-          arrows::move_arrow_head.run_if(in_state(GameState::ArrowMovement)),
+          input::pointer_events,
+          cursor::move_cursor
+            .run_if(in_state(GameState::CursorMovement))
+            .run_if(accepts_player_input),
+          danger::danger_zone_controls
+            .run_if(in_state(GameState::CursorMovement))
+            .run_if(accepts_player_input),
+          arrows::move_arrow_head
+            .run_if(in_state(GameState::ArrowMovement))
+            .run_if(accepts_player_input),
+          commands::forward_command_requests
+            .run_if(not(resource_exists::<NetSession>)),
+          net::route_command_requests.run_if(resource_exists::<NetSession>),
+          (replay::replay_controls, replay::rebuild_replay_board)
+            .chain()
+            .run_if(resource_exists::<ReplayViewer>)
+            .run_if(not(resource_exists::<Desync>)),
+          commands::apply_game_commands
+            .run_if(resource_exists::<crate::tiles::TileTypes>),
           update_grid_coord_positions,
           units::update_backdrop_positions,
+          info_panel::update_info_panel,
+          danger::draw_danger_zone
+            .run_if(in_state(GameState::CursorMovement))
+            .run_if(resource_exists::<crate::tiles::TileTypes>),
+        )
+          .chain()
+          .run_if(in_state(GlobalState::Game)),
+      )
+      .add_systems(
+        Update,
+        (
+          camera::zoom_camera,
+          camera::follow_cursor,
+          camera::edge_scroll,
+          camera::move_camera,
+        )
+          .chain()
+          .after(cursor::move_cursor)
+          .run_if(resource_exists::<CameraView>)
+          .run_if(in_state(GlobalState::Game)),
+      )
+      .add_systems(
+        Update,
+        phase_banner::animate_phase_banner.run_if(in_state(GlobalState::Game)),
+      )
+      .add_systems(
+        Update,
+        (
+          health::spawn_health_bars,
+          health::update_health_bars,
+          health::show_health_changes,
+          health::animate_floating_numbers,
         )
           .chain()
+          .after(commands::apply_game_commands)
+          .run_if(in_state(GlobalState::Game)),
+      )
+      .add_systems(
+        Update,
+        (
+          replay::record_spawn_layout
+            .run_if(resource_exists_and_changed::<SpawnLayout>),
+          replay::record_applied_commands,
+          replay::write_replay_on_match_over,
+        )
+          .after(commands::apply_game_commands)
+          .run_if(resource_exists::<ReplayRecorder>)
+          .run_if(in_state(GlobalState::Game)),
+      )
+      .add_systems(
+        Update,
+        net::poll_network
+          .before(commands::apply_game_commands)
+          .run_if(resource_exists::<NetSession>)
+          // messages wait in the link's queue until the game is resumed
+          .run_if(not(in_state(GlobalState::Paused))),
+      )
+      .add_systems(
+        Update,
+        (
+          save::quicksave_controls
+            .run_if(accepts_player_input)
+            .run_if(not(resource_exists::<NetSession>)),
+          (
+            commands::detect_phase_start,
+            (
+              autosave::autosave_turn_start
+                .run_if(not(resource_exists::<ReplayViewer>))
+                .run_if(not(resource_exists::<RecoveryOffer>)),
+              checksum::record_turn_checksum,
+              phase_banner::show_phase_banner,
+            ),
+          )
+            .chain()
+            .after(commands::apply_game_commands)
+            .run_if(resource_exists::<SpawnLayout>)
+            .run_if(resource_exists::<TurnNumber>)
+            .run_if(not(resource_exists::<PendingLoad>)),
+        )
+          .run_if(in_state(GlobalState::Game))
+          .run_if(resource_exists::<MatchRng>),
+      )
+      .add_systems(
+        Update,
+        (
+          checksum::check_remote_checksums
+            .after(checksum::record_turn_checksum)
+            .run_if(resource_exists::<NetSession>),
+          checksum::show_desync_report.run_if(resource_added::<Desync>),
+        )
           .run_if(in_state(GlobalState::Game)),
       );
   }
 }
 
+/// Marks everything that belongs to the current match, so that it can all be
+/// despawned when the match is torn down.
 #[derive(Default, Component)]
 struct GameEntity;
 
+/// Whether the local player is currently in control of the match, as opposed
+/// to watching a replay, using a menu, waiting on a remote player, looking at
+/// a desync report or waiting for a new phase to be announced.
+fn accepts_player_input(
+  replay_viewer: Option<Res<ReplayViewer>>,
+  recovery_offer: Option<Res<RecoveryOffer>>,
+  net_session: Option<Res<NetSession>>,
+  desync: Option<Res<Desync>>,
+  window_focus: Res<WindowFocus>,
+  phase_banners: Query<(), With<PhaseBanner>>,
+) -> bool {
+  replay_viewer.is_none()
+    && recovery_offer.is_none()
+    && desync.is_none()
+    && window_focus.is_empty()
+    && phase_banners.is_empty()
+    && net_session.is_none_or(|session| session.local_turn())
+}
+
+/// The terrain as painted by [`autotile::paint_terrain`], over whatever the
+/// level itself draws.
+#[derive(Default, Component, Clone, Copy)]
+pub struct TerrainMap;
 #[derive(Default, Component, Clone, Copy)]
 pub struct ZoneMap;
 #[derive(Default, Component, Clone, Copy)]
@@ -103,6 +363,58 @@ impl std::ops::Deref for GlobalCamera {
   }
 }
 
+/// While present, loading goes straight into a match rather than stopping at
+/// the main menu.
+#[derive(Resource)]
+pub struct StartMatch;
+
+/// How the next match should be set up.
+#[derive(Resource, Clone)]
+pub struct MatchConfig {
+  pub level: usize,
+  pub seed: u64,
+  /// Set to play on a level built from the seed rather than one of the
+  /// project's, in which case `level` is ignored.
+  pub generated: Option<GeneratedMap>,
+}
+
+impl Default for MatchConfig {
+  fn default() -> Self {
+    MatchConfig {
+      level: 0,
+      seed: std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default(),
+      generated: None,
+    }
+  }
+}
+
+/// The number of full rounds played so far, starting from 1.
+#[derive(Resource)]
+pub struct TurnNumber(u32);
+
+impl Default for TurnNumber {
+  fn default() -> Self {
+    TurnNumber(1)
+  }
+}
+
+impl std::ops::Deref for TurnNumber {
+  type Target = u32;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+impl std::ops::DerefMut for TurnNumber {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.0
+  }
+}
+
 #[derive(Resource)]
 pub struct LevelSize {
   px_hei: i32,
@@ -112,7 +424,19 @@ pub struct LevelSize {
 }
 
 impl LevelSize {
-  fn as_tilemap_size(&self) -> TilemapSize {
+  pub fn contains(&self, grid_coords: GridCoords) -> bool {
+    (0..self.tile_wid as i32).contains(&grid_coords.x)
+      && (0..self.tile_hei as i32).contains(&grid_coords.y)
+  }
+
+  pub fn clamp(&self, grid_coords: GridCoords) -> GridCoords {
+    GridCoords {
+      x: grid_coords.x.clamp(0, self.tile_wid as i32 - 1),
+      y: grid_coords.y.clamp(0, self.tile_hei as i32 - 1),
+    }
+  }
+
+  pub fn as_tilemap_size(&self) -> TilemapSize {
     TilemapSize {
       x: self.tile_wid as u32,
       y: self.tile_hei as u32,
@@ -124,45 +448,82 @@ fn init_world(
   mut commands: Commands,
   ldtk_handle: Res<LdtkWorldHandle>,
   projects: Res<Assets<LdtkProject>>,
+  loaded_terrain: LoadedTerrain,
+  match_config: Res<MatchConfig>,
 ) {
   info!("Initialising game world");
 
-  let level_selection = LevelSelection::index(0);
+  commands.remove_resource::<StartMatch>();
+
+  let unit_spawn_queues = UnitSpawnQueues::default();
+
+  let level_size = match match_config.generated {
+    // a generated level has no LDtk world; its terrain is painted straight
+    // from the tile types and its units placed by `fill_generated_spawns`
+    Some(map) => {
+      let level = mapgen::generate(
+        map,
+        match_config.seed,
+        &unit_spawn_queues,
+        loaded_terrain.get().unwrap(),
+      )
+      .expect("the terrain was checked before the match started");
+      commands.insert_resource(level.tile_types);
+      commands.insert_resource(level.spawns);
+
+      LevelSize {
+        px_hei: map.height as i32 * 16,
+        px_wid: map.width as i32 * 16,
+        tile_hei: map.height,
+        tile_wid: map.width,
+      }
+    }
+    None => {
+      let level_selection = LevelSelection::index(match_config.level);
 
-  let project = projects.get(ldtk_handle.clone()).unwrap();
-  let level = project
-    .data()
-    .as_standalone()
-    .find_loaded_level_by_level_selection(&level_selection)
-    .unwrap();
+      let project = projects.get(ldtk_handle.clone()).unwrap();
+      let level = project
+        .data()
+        .as_standalone()
+        .find_loaded_level_by_level_selection(&level_selection)
+        .unwrap();
 
-  let level_size = LevelSize {
-    px_hei: *level.px_hei(),
-    px_wid: *level.px_wid(),
-    tile_hei: *level.px_hei() as usize / 16,
-    tile_wid: *level.px_wid() as usize / 16,
+      let level_size = LevelSize {
+        px_hei: *level.px_hei(),
+        px_wid: *level.px_wid(),
+        tile_hei: *level.px_hei() as usize / 16,
+        tile_wid: *level.px_wid() as usize / 16,
+      };
+
+      commands.insert_resource(level_selection);
+
+      commands.spawn((
+        LdtkWorldBundle {
+          ldtk_handle: ldtk_handle.clone(),
+          ..default()
+        },
+        GameEntity,
+      ));
+
+      level_size
+    }
   };
 
+  // levels bigger than the view are scrolled around by the camera systems
+  let level_centre =
+    Vec2::new(level_size.px_wid as f32, level_size.px_hei as f32) / 2.0;
   let mut camera = Camera2dBundle::default();
   camera.projection.scaling_mode = ScalingMode::AutoMin {
-    min_width: *level.px_wid() as f32,
-    min_height: *level.px_hei() as f32,
+    min_width: (level_size.px_wid as f32).min(camera::VIEW_SIZE.x),
+    min_height: (level_size.px_hei as f32).min(camera::VIEW_SIZE.y),
   };
-  camera.transform.translation.x = *level.px_wid() as f32 / 2.0;
-  camera.transform.translation.y = *level.px_hei() as f32 / 2.0;
-  let camera_id = commands.spawn(camera).id();
+  camera.transform.translation.x = level_centre.x;
+  camera.transform.translation.y = level_centre.y;
+  let camera_id = commands.spawn((camera, GameEntity)).id();
   commands.insert_resource(GlobalCamera(camera_id));
+  commands.insert_resource(CameraView::new(level_centre));
 
-  commands.insert_resource(level_selection);
-
-  commands.spawn((
-    LdtkWorldBundle {
-      ldtk_handle: ldtk_handle.clone(),
-      ..default()
-    },
-    GameEntity,
-  ));
-
+  commands.spawn(create_tilemap(4.0, &level_size, TerrainMap));
   commands.spawn(create_tilemap(5.0, &level_size, ZoneMap));
   commands.spawn(create_tilemap(20.0, &level_size, ArrowMap));
   commands.spawn(create_tilemap(15.0, &level_size, BackdropMap));
@@ -170,7 +531,56 @@ fn init_world(
 
   commands.insert_resource(level_size);
 
-  commands.insert_resource(UnitSpawnQueues::default());
+  commands.insert_resource(unit_spawn_queues);
+  commands.insert_resource(TurnNumber::default());
+  commands.insert_resource(MatchRng::new(match_config.seed));
+  commands.insert_resource(TurnChecksums::default());
+}
+
+/// Despawns everything belonging to the current match and removes its
+/// resources, leaving the app ready for [`init_world`] to start a new one.
+fn teardown_world(
+  mut commands: Commands,
+  global_state: Res<State<GlobalState>>,
+  game_entities: Query<Entity, With<GameEntity>>,
+  mut next_turn_state: ResMut<NextState<TurnState>>,
+  mut next_game_state: ResMut<NextState<GameState>>,
+) {
+  info!("Tearing down game world");
+
+  for game_entity in game_entities.iter() {
+    commands.entity(game_entity).despawn_recursive();
+  }
+
+  commands.remove_resource::<GlobalCamera>();
+  commands.remove_resource::<CameraView>();
+  commands.remove_resource::<LevelSelection>();
+  commands.remove_resource::<LevelSize>();
+  commands.remove_resource::<crate::tiles::TileTypes>();
+  commands.remove_resource::<UnitSpawnQueues>();
+  commands.remove_resource::<SpawnLayout>();
+  commands.remove_resource::<GeneratedSpawns>();
+  commands.remove_resource::<TurnNumber>();
+  commands.remove_resource::<MatchRng>();
+  commands.remove_resource::<ReplayRecorder>();
+  commands.remove_resource::<TurnChecksums>();
+  commands.remove_resource::<Desync>();
+  commands.remove_resource::<arrows::ArrowHead>();
+  commands.remove_resource::<arrows::ArrowTarget>();
+  commands.remove_resource::<arrows::MoveableRegion>();
+  commands.insert_resource(UnitAssociations::default());
+  commands.insert_resource(DangerZone::default());
+
+  // a load or a restart carries these through loading into the next world,
+  // so only leaving for the main menu is the end of them
+  if *global_state.get() == GlobalState::MainMenu {
+    commands.remove_resource::<ReplayViewer>();
+    commands.remove_resource::<PendingLoad>();
+    commands.remove_resource::<RecoveryOffer>();
+  }
+
+  next_turn_state.set(TurnState::default());
+  next_game_state.set(GameState::default());
 }
 
 fn create_tilemap<T: Bundle>(
@@ -206,6 +616,7 @@ fn create_tilemap<T: Bundle>(
       ..default()
     },
     attachments,
+    GameEntity,
   )
 }
 
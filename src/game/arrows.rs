@@ -3,14 +3,13 @@ use bevy::{
     bundle::Bundle,
     component::Component,
     entity::Entity,
-    event::EventReader,
-    query::{With, Without},
-    schedule::{NextState, State},
+    event::{EventReader, EventWriter},
+    query::{Or, With, Without},
+    schedule::State,
     system::{Commands, Query, Res, ResMut, Resource},
   },
   hierarchy::DespawnRecursiveExt,
-  input::{keyboard::KeyCode, ButtonInput},
-  sprite::{SpriteSheetBundle, TextureAtlas},
+  sprite::SpriteSheetBundle,
   utils::HashMap,
 };
 use bevy_ecs_ldtk::{GridCoords, LdtkEntity};
@@ -21,19 +20,16 @@ use bevy_ecs_tilemap::{
 use itertools::Itertools;
 
 use crate::{
-  assets::ATLAS_INFO,
-  tiles::{
-    TileTypes, ARROW_BODY_DL, ARROW_BODY_DR, ARROW_BODY_H, ARROW_BODY_UL,
-    ARROW_BODY_UR, ARROW_BODY_V, ARROW_HEAD_D, ARROW_HEAD_L, ARROW_HEAD_R,
-    ARROW_HEAD_U, ARROW_RED_OFFSET, ZONE_MOVE,
-  },
+  sprites::Sprites,
+  tiles::{MovementClass, TileTypes},
 };
 
 use super::{
+  commands::{CommandRequest, GameCommand},
   cursor::{Cursor, Targeted},
-  input::MovementInput,
-  units::Unit,
-  ArrowMap, GameEntity, GameState, TurnState, UnitMap, ZoneMap,
+  input::{Action, Actions, MovementInput, PointerInput},
+  units::{Unit, UnitAssociation},
+  ArrowMap, GameEntity, TurnState, UnitMap, ZoneMap,
 };
 
 #[derive(Default, Component)]
@@ -52,33 +48,8 @@ pub struct ArrowChunkBundle {
 #[derive(Default, Component)]
 pub struct MovementZone;
 
-#[derive(Bundle, LdtkEntity)]
-pub struct MovementZoneBundle {
-  movement_zone: MovementZone,
-  game_entity: GameEntity,
-  #[sprite_sheet_bundle]
-  pub sprite_bundle: SpriteSheetBundle,
-  #[grid_coords]
-  pub grid_coords: GridCoords,
-}
-
-impl Default for MovementZoneBundle {
-  fn default() -> Self {
-    let mut sprite_bundle = SpriteSheetBundle::default();
-    sprite_bundle.texture = ATLAS_INFO.get().unwrap().image.clone();
-    sprite_bundle.atlas = TextureAtlas {
-      layout: ATLAS_INFO.get().unwrap().layout.clone(),
-      index: ZONE_MOVE,
-    };
-    sprite_bundle.transform.translation.z = 15.0;
-    MovementZoneBundle {
-      movement_zone: MovementZone,
-      game_entity: GameEntity,
-      sprite_bundle,
-      grid_coords: GridCoords::default(),
-    }
-  }
-}
+#[derive(Default, Component)]
+pub struct TargetZone;
 
 #[derive(Resource)]
 pub struct ArrowHead(pub GridCoords);
@@ -97,6 +68,23 @@ impl std::ops::DerefMut for ArrowHead {
   }
 }
 
+#[derive(Resource)]
+pub struct ArrowTarget(pub Option<GridCoords>);
+
+impl std::ops::Deref for ArrowTarget {
+  type Target = Option<GridCoords>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+impl std::ops::DerefMut for ArrowTarget {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.0
+  }
+}
+
 pub fn create_arrow_chunk(
   arrow_index: usize,
   grid_coords: GridCoords,
@@ -113,84 +101,250 @@ pub fn create_arrow_chunk(
       ..Default::default()
     },
     ArrowChunk,
+    GameEntity,
   )
 }
 
-#[inline]
-pub fn arrow_index_turn_parity(
-  arrow_index: usize,
-  turn_state: &TurnState,
-) -> usize {
-  arrow_index
-    + ((*turn_state == TurnState::Player2) as usize * ARROW_RED_OFFSET)
-}
-
 pub fn move_arrow_head(
   mut commands: Commands,
   arrow_chunks: Query<Entity, With<ArrowChunk>>,
   arrow_map: Query<Entity, With<ArrowMap>>,
   mut arrow_head: ResMut<ArrowHead>,
-  keys: Res<ButtonInput<KeyCode>>,
+  mut arrow_target: ResMut<ArrowTarget>,
+  actions: Res<Actions>,
   moveable_region: Res<MoveableRegion>,
   current_turn_state: Res<State<TurnState>>,
-  mut targeted_unit: Query<(Entity, &mut TilePos, &mut Unit), With<Targeted>>,
-  mut unit_map: Query<&mut TileStorage, With<UnitMap>>,
-  mut next_game_state: ResMut<NextState<GameState>>,
-  mut movement_events: EventReader<MovementInput>,
+  targeted_unit: Query<(&TilePos, &Unit), With<Targeted>>,
+  units: Query<&UnitAssociation, With<Unit>>,
+  target_zones: Query<(Entity, &TilePos), With<TargetZone>>,
+  mut zone_map: Query<(Entity, &mut TileStorage), With<ZoneMap>>,
+  unit_map: Query<&TileStorage, (With<UnitMap>, Without<ZoneMap>)>,
+  (mut movement_events, mut pointer_events): (
+    EventReader<MovementInput>,
+    EventReader<PointerInput>,
+  ),
+  mut command_requests: EventWriter<CommandRequest>,
+  sprites: Sprites,
 ) {
-  if keys.just_pressed(KeyCode::Enter) {
-    let destination = crate::util::grid_to_tile(**arrow_head);
-    let mut unit_map = unit_map.single_mut();
-    let mut targeted_unit = targeted_unit.single_mut();
-    targeted_unit.2.moved = true;
-    unit_map.remove(&targeted_unit.1);
-    unit_map.set(&destination, targeted_unit.0);
-    *targeted_unit.1 = destination;
-
-    commands.entity(targeted_unit.0).remove::<Targeted>();
-
-    next_game_state.set(GameState::CursorMovement);
-    return;
+  let player_actions = actions.player(**current_turn_state);
+
+  let mut moved = !movement_events.is_empty();
+  let mut destination = **arrow_head;
+  for movement_event in movement_events.read() {
+    destination += movement_event.as_grid_coords();
   }
 
-  if movement_events.is_empty() {
+  let mut cancelled = player_actions.just_pressed(Action::Cancel);
+  let mut clicked = None;
+  for pointer_event in pointer_events.read() {
+    match pointer_event {
+      PointerInput::Hover(tile) => {
+        moved = true;
+        destination = *tile;
+      }
+      PointerInput::Click(tile) => {
+        moved = true;
+        destination = *tile;
+        clicked = Some(*tile);
+      }
+      PointerInput::Cancel => cancelled = true,
+    }
+  }
+
+  if cancelled {
+    command_requests.send(CommandRequest(GameCommand::Deselect));
     return;
   }
 
-  let mut destination = **arrow_head;
-  for movement_event in movement_events.read() {
-    destination += movement_event.as_grid_coords();
+  let (origin, unit) = targeted_unit.single();
+  let origin = crate::util::tile_to_grid(*origin);
+  let target_turn_at = |target: GridCoords| {
+    unit_map
+      .single()
+      .checked_get(&crate::util::grid_to_tile(target))
+      .and_then(|entity| units.get(entity).ok())
+      .map(|association| association.turn)
+  };
+  let command_on = |from: GridCoords, target: GridCoords| {
+    target_turn_at(target).and_then(|target_turn| {
+      target_command(unit, **current_turn_state, from, target, target_turn)
+    })
+  };
+  let mut new_target = None;
+  if moved {
+    if moveable_region.contains_key(&destination) || destination == origin {
+      if arrow_target.is_some() {
+        clear_target_zones(&mut commands, &target_zones, &mut zone_map);
+        **arrow_target = None;
+      }
+
+      **arrow_head = destination;
+      draw_arrow(
+        &mut commands,
+        &arrow_chunks,
+        arrow_map.single(),
+        **arrow_head,
+        &moveable_region,
+        &current_turn_state,
+        &sprites,
+      );
+    } else if destination != origin
+      && command_on(**arrow_head, destination).is_some()
+    {
+      // stepping onto another unit marks it as the target of an attack or
+      // heal, to be carried out from wherever the arrow head currently is
+      new_target = Some(destination);
+    }
   }
 
-  if !(moveable_region.contains_key(&destination)
-    || destination == **arrow_head)
-  {
+  // the keyboard and gamepad only step one tile from the arrow head, so they
+  // reach anything further away by cycling through every target in range
+  if player_actions.just_pressed(Action::NextUnit) {
+    let targets = targets_in_range(
+      unit,
+      **current_turn_state,
+      **arrow_head,
+      &target_turn_at,
+    )
+    .into_iter()
+    .filter(|target| *target != origin)
+    .collect::<Vec<_>>();
+    new_target = next_target(&targets, **arrow_target);
+  }
+
+  if let Some(target) = new_target {
+    clear_target_zones(&mut commands, &target_zones, &mut zone_map);
+    let tile_pos = crate::util::grid_to_tile(target);
+    let tile = commands
+      .spawn((
+        TileBundle {
+          position: tile_pos,
+          texture_index: TileTextureIndex(sprites.index("zone/melee") as u32),
+          tilemap_id: TilemapId(zone_map.single().0),
+          ..Default::default()
+        },
+        TargetZone,
+        GameEntity,
+      ))
+      .id();
+    zone_map.single_mut().1.set(&tile_pos, tile);
+    **arrow_target = Some(target);
+  }
+
+  // a click only confirms once it has landed on the arrow head or its target,
+  // so clicking an unreachable tile does nothing
+  let confirmed = player_actions.just_pressed(Action::Confirm)
+    || clicked
+      .is_some_and(|tile| tile == **arrow_head || Some(tile) == **arrow_target);
+  if !confirmed {
     return;
   }
 
-  **arrow_head = destination;
+  let path = moveable_region.path_to(**arrow_head);
+  if !path.is_empty() {
+    command_requests
+      .send(CommandRequest(GameCommand::MoveUnit { unit: origin, path }));
+  }
+
+  match (**arrow_target).and_then(|target| command_on(**arrow_head, target)) {
+    Some(game_command) => {
+      command_requests.send(CommandRequest(game_command));
+    }
+    None => {
+      command_requests
+        .send(CommandRequest(GameCommand::Wait { unit: **arrow_head }));
+    }
+  }
+}
+
+/// What `unit`, standing on `from` on `turn`'s side, would do to a unit of
+/// `target_turn` standing on `target`: attack an enemy or heal an ally. A
+/// unit with the target beyond its attack range, or an ally when it cannot
+/// heal, has nothing to do to it.
+pub fn target_command(
+  unit: &Unit,
+  turn: TurnState,
+  from: GridCoords,
+  target: GridCoords,
+  target_turn: TurnState,
+) -> Option<GameCommand> {
+  let distance = target - from;
+  let distance = (distance.x.abs() + distance.y.abs()) as usize;
+  if distance == 0 || distance > unit.attack_range {
+    return None;
+  }
+
+  if target_turn != turn {
+    Some(GameCommand::Attack { unit: from, target })
+  } else if unit.heal > 0 {
+    Some(GameCommand::Heal { unit: from, target })
+  } else {
+    None
+  }
+}
+
+/// Every tile within `unit`'s attack range of `from` holding a unit it would
+/// attack or heal, ordered by column and then row. `target_turn_at` gives the
+/// side of the unit on a tile, if there is one.
+pub fn targets_in_range(
+  unit: &Unit,
+  turn: TurnState,
+  from: GridCoords,
+  target_turn_at: impl Fn(GridCoords) -> Option<TurnState>,
+) -> Vec<GridCoords> {
+  let range = unit.attack_range as i32;
+  (-range..=range)
+    .flat_map(|dx| (-range..=range).map(move |dy| GridCoords::new(dx, dy)))
+    .map(|offset| from + offset)
+    .filter(|target| {
+      target_turn_at(*target).is_some_and(|target_turn| {
+        target_command(unit, turn, from, *target, target_turn).is_some()
+      })
+    })
+    .collect()
+}
+
+/// The target after `current` in `targets`, wrapping round to the first.
+fn next_target(
+  targets: &[GridCoords],
+  current: Option<GridCoords>,
+) -> Option<GridCoords> {
+  let next = current
+    .and_then(|current| targets.iter().position(|target| *target == current))
+    .map_or(0, |index| index + 1);
+  targets.get(next).or(targets.first()).copied()
+}
 
+fn draw_arrow(
+  commands: &mut Commands,
+  arrow_chunks: &Query<Entity, With<ArrowChunk>>,
+  arrow_map: Entity,
+  arrow_head: GridCoords,
+  moveable_region: &MoveableRegion,
+  current_turn_state: &TurnState,
+  sprites: &Sprites,
+) {
   for arrow_chunk in arrow_chunks.iter() {
     commands.entity(arrow_chunk).despawn();
   }
 
-  let mut current_coords = **arrow_head;
+  let mut current_coords = arrow_head;
 
   if let Some(first_parent_coords) = moveable_region.get(&current_coords) {
     let distance = current_coords - *first_parent_coords;
     commands.spawn(create_arrow_chunk(
-      arrow_index_turn_parity(
+      sprites.faction_index(
         match (distance.x, distance.y) {
-          (-1, 0) => ARROW_HEAD_L,
-          (1, 0) => ARROW_HEAD_R,
-          (0, -1) => ARROW_HEAD_D,
-          (0, 1) => ARROW_HEAD_U,
+          (-1, 0) => "arrow/head_left",
+          (1, 0) => "arrow/head_right",
+          (0, -1) => "arrow/head_down",
+          (0, 1) => "arrow/head_up",
           _ => unreachable!(),
         },
-        &current_turn_state,
+        *current_turn_state,
       ),
       current_coords,
-      arrow_map.single(),
+      arrow_map,
     ));
   }
 
@@ -207,33 +361,48 @@ pub fn move_arrow_head(
     let current_distance = current_coords - *target_coords;
     let next_distance = *next_coords - *target_coords;
 
-    let arrow_index = match (
+    let arrow_sprite = match (
       (current_distance.x, current_distance.y),
       (next_distance.x, next_distance.y),
     ) {
-      ((-1, 0), (1, 0)) | ((1, 0), (-1, 0)) => ARROW_BODY_H,
-      ((0, -1), (0, 1)) | ((0, 1), (0, -1)) => ARROW_BODY_V,
-      ((-1, 0), (0, -1)) | ((0, -1), (-1, 0)) => ARROW_BODY_UR,
-      ((-1, 0), (0, 1)) | ((0, 1), (-1, 0)) => ARROW_BODY_DR,
-      ((1, 0), (0, -1)) | ((0, -1), (1, 0)) => ARROW_BODY_UL,
-      ((1, 0), (0, 1)) | ((0, 1), (1, 0)) => ARROW_BODY_DL,
+      ((-1, 0), (1, 0)) | ((1, 0), (-1, 0)) => "arrow/body_horizontal",
+      ((0, -1), (0, 1)) | ((0, 1), (0, -1)) => "arrow/body_vertical",
+      ((-1, 0), (0, -1)) | ((0, -1), (-1, 0)) => "arrow/body_up_right",
+      ((-1, 0), (0, 1)) | ((0, 1), (-1, 0)) => "arrow/body_down_right",
+      ((1, 0), (0, -1)) | ((0, -1), (1, 0)) => "arrow/body_up_left",
+      ((1, 0), (0, 1)) | ((0, 1), (1, 0)) => "arrow/body_down_left",
       _ => unreachable!(),
     };
 
     commands.spawn(create_arrow_chunk(
-      arrow_index_turn_parity(arrow_index, &current_turn_state),
+      sprites.faction_index(arrow_sprite, *current_turn_state),
       *target_coords,
-      arrow_map.single(),
+      arrow_map,
     ));
 
     current_coords = *target_coords;
   }
 }
 
+fn clear_target_zones(
+  commands: &mut Commands,
+  target_zones: &Query<(Entity, &TilePos), With<TargetZone>>,
+  zone_map: &mut Query<(Entity, &mut TileStorage), With<ZoneMap>>,
+) {
+  for (target_zone, position) in target_zones.iter() {
+    commands.entity(target_zone).despawn_recursive();
+    zone_map.single_mut().1.remove(position);
+  }
+}
+
+#[allow(clippy::type_complexity)]
 pub fn clear_drawn_arrows(
   mut commands: Commands,
   arrow_chunks: Query<Entity, With<ArrowChunk>>,
-  movement_zones: Query<(Entity, &TilePos), With<MovementZone>>,
+  movement_zones: Query<
+    (Entity, &TilePos),
+    Or<(With<MovementZone>, With<TargetZone>)>,
+  >,
   mut zone_map: Query<&mut TileStorage, With<ZoneMap>>,
 ) {
   for dead_entity in arrow_chunks.iter() {
@@ -249,6 +418,21 @@ pub fn clear_drawn_arrows(
 #[derive(Resource)]
 pub struct MoveableRegion(HashMap<GridCoords, GridCoords>);
 
+impl MoveableRegion {
+  /// Walks the parent links back from `destination`, returning every step of
+  /// the path in travel order (excluding the starting tile).
+  pub fn path_to(&self, destination: GridCoords) -> Vec<GridCoords> {
+    let mut path = Vec::new();
+    let mut current = destination;
+    while let Some(parent) = self.get(&current) {
+      path.push(current);
+      current = *parent;
+    }
+    path.reverse();
+    path
+  }
+}
+
 impl std::ops::Deref for MoveableRegion {
   type Target = HashMap<GridCoords, GridCoords>;
 
@@ -270,23 +454,21 @@ pub fn calculate_moveable_region(
   targeted_unit: Query<&Unit, With<Targeted>>,
   tile_types: Res<TileTypes>,
   unit_storage: Query<&TileStorage, (With<UnitMap>, Without<ZoneMap>)>,
+  sprites: Sprites,
 ) {
-  let max_move_cost = targeted_unit.single().max_move_cost;
-  let moveable = HashMap::from_iter(
-    pathfinding::directed::dijkstra::dijkstra_reach(
+  // a unit that has already moved may only act from where it stands
+  let targeted_unit = targeted_unit.single();
+  let moveable = if targeted_unit.moved {
+    HashMap::default()
+  } else {
+    reachable_tiles(
+      &tile_types,
+      unit_storage.single(),
       cursor.single(),
-      |node, cost| {
-        node_neighbours_with_cost(
-          &*tile_types,
-          unit_storage.single(),
-          node,
-          cost,
-          max_move_cost,
-        )
-      },
+      targeted_unit.max_move_cost,
+      targeted_unit.movement,
     )
-    .filter_map(|item| item.parent.map(|parent| (item.node, parent))),
-  );
+  };
 
   for moveable_coord in moveable.keys() {
     let tile_pos = TilePos {
@@ -297,17 +479,60 @@ pub fn calculate_moveable_region(
       .spawn((
         TileBundle {
           position: tile_pos,
-          texture_index: TileTextureIndex(ZONE_MOVE as u32),
+          texture_index: TileTextureIndex(sprites.index("zone/move") as u32),
           tilemap_id: TilemapId(zone_map.single().0),
           ..Default::default()
         },
         MovementZone,
+        GameEntity,
       ))
       .id();
     zone_map.single_mut().1.set(&tile_pos, tile);
   }
 
   commands.insert_resource(MoveableRegion(moveable));
+  commands.insert_resource(ArrowTarget(None));
+}
+
+/// Every tile a unit standing on `start` could move to, mapped to the tile it
+/// would step from to get there. The start itself is left out.
+pub fn reachable_tiles(
+  tile_types: &TileTypes,
+  unit_positions: &TileStorage,
+  start: &GridCoords,
+  max_move_cost: usize,
+  movement: MovementClass,
+) -> HashMap<GridCoords, GridCoords> {
+  HashMap::from_iter(
+    pathfinding::directed::dijkstra::dijkstra_reach(start, |node, cost| {
+      node_neighbours_with_cost(
+        tile_types,
+        unit_positions,
+        node,
+        cost,
+        max_move_cost,
+        movement,
+      )
+    })
+    .filter_map(|item| item.parent.map(|parent| (item.node, parent))),
+  )
+}
+
+pub fn tile_move_cost(
+  tile_types: &TileTypes,
+  node: &GridCoords,
+  movement: MovementClass,
+) -> Option<usize> {
+  tile_types
+    .terrain_at(node)
+    .and_then(|terrain| terrain.move_cost(movement))
+}
+
+/// Damage taken off attacks on a unit standing at `node`.
+pub fn tile_defence(tile_types: &TileTypes, node: &GridCoords) -> i32 {
+  tile_types
+    .terrain_at(node)
+    .map_or(0, |terrain| terrain.defence)
 }
 
 fn node_neighbours_with_cost(
@@ -316,6 +541,7 @@ fn node_neighbours_with_cost(
   node: &GridCoords,
   total_cost: usize,
   maximum_cost: usize,
+  movement: MovementClass,
 ) -> impl IntoIterator<Item = (GridCoords, usize)> {
   crate::util::neighbours(node)
     .into_iter()
@@ -325,14 +551,215 @@ fn node_neighbours_with_cost(
         .is_some()
       {
         None
-      } else if tile_types.grassy.contains(&node) {
-        Some((node, 1))
-      } else if tile_types.forested.contains(&node) {
-        Some((node, 2))
       } else {
-        None
+        tile_move_cost(tile_types, &node, movement).map(|cost| (node, cost))
       }
     })
     .filter(|(_, cost)| total_cost + cost <= maximum_cost)
     .collect_vec()
 }
+
+#[cfg(test)]
+mod tests {
+  use bevy_ecs_tilemap::map::TilemapSize;
+
+  use super::*;
+  use crate::game::fixtures::{tile_types, unit};
+
+  fn no_units() -> TileStorage {
+    TileStorage::empty(TilemapSize { x: 5, y: 5 })
+  }
+
+  #[test]
+  fn enemies_next_to_the_unit_are_attacked() {
+    let from = GridCoords::new(2, 2);
+    let target = GridCoords::new(3, 2);
+    assert_eq!(
+      target_command(
+        &unit(),
+        TurnState::Player1,
+        from,
+        target,
+        TurnState::Player2
+      ),
+      Some(GameCommand::Attack { unit: from, target })
+    );
+  }
+
+  #[test]
+  fn allies_are_only_targeted_by_healers() {
+    let from = GridCoords::new(2, 2);
+    let target = GridCoords::new(2, 3);
+    assert_eq!(
+      target_command(
+        &unit(),
+        TurnState::Player1,
+        from,
+        target,
+        TurnState::Player1
+      ),
+      None
+    );
+    assert_eq!(
+      target_command(
+        &Unit { heal: 10, ..unit() },
+        TurnState::Player1,
+        from,
+        target,
+        TurnState::Player1
+      ),
+      Some(GameCommand::Heal { unit: from, target })
+    );
+  }
+
+  #[test]
+  fn units_out_of_reach_are_not_targeted() {
+    assert_eq!(
+      target_command(
+        &unit(),
+        TurnState::Player1,
+        GridCoords::new(2, 2),
+        GridCoords::new(3, 3),
+        TurnState::Player2
+      ),
+      None
+    );
+  }
+
+  #[test]
+  fn ranged_units_target_anything_within_their_range() {
+    let archer = Unit {
+      attack_range: 2,
+      ..unit()
+    };
+    let from = GridCoords::new(2, 2);
+    let target = GridCoords::new(3, 3);
+    assert_eq!(
+      target_command(
+        &archer,
+        TurnState::Player1,
+        from,
+        target,
+        TurnState::Player2
+      ),
+      Some(GameCommand::Attack { unit: from, target })
+    );
+    assert_eq!(
+      target_command(
+        &archer,
+        TurnState::Player1,
+        from,
+        GridCoords::new(4, 3),
+        TurnState::Player2
+      ),
+      None
+    );
+  }
+
+  #[test]
+  fn keyboard_targeting_cycles_through_everything_in_range() {
+    let archer = Unit {
+      attack_range: 2,
+      ..unit()
+    };
+    let target_turn_at =
+      |grid_coords: GridCoords| match (grid_coords.x, grid_coords.y) {
+        (3, 3) | (0, 2) | (4, 4) => Some(TurnState::Player2),
+        (2, 3) => Some(TurnState::Player1),
+        _ => None,
+      };
+
+    // the ally cannot be healed and the enemy at (4, 4) is out of range
+    let targets = targets_in_range(
+      &archer,
+      TurnState::Player1,
+      GridCoords::new(2, 2),
+      target_turn_at,
+    );
+    assert_eq!(targets, vec![GridCoords::new(0, 2), GridCoords::new(3, 3)]);
+
+    assert_eq!(next_target(&targets, None), Some(targets[0]));
+    assert_eq!(next_target(&targets, Some(targets[0])), Some(targets[1]));
+    assert_eq!(next_target(&targets, Some(targets[1])), Some(targets[0]));
+    assert_eq!(next_target(&[], None), None);
+  }
+
+  #[test]
+  fn open_ground_is_reachable_up_to_the_move_cost() {
+    let tile_types = tile_types(&[".....", ".....", ".....", ".....", "....."]);
+    let start = GridCoords::new(2, 2);
+    let reachable =
+      reachable_tiles(&tile_types, &no_units(), &start, 2, MovementClass::Foot);
+
+    assert_eq!(reachable.len(), 12);
+    assert!(!reachable.contains_key(&start));
+    assert!(reachable.keys().all(|tile| {
+      let distance = *tile - start;
+      distance.x.abs() + distance.y.abs() <= 2
+    }));
+  }
+
+  #[test]
+  fn water_and_other_units_are_walked_around() {
+    let tile_types = tile_types(&["..~..", "..~..", "..~..", "..~..", "..~.."]);
+    let mut units = no_units();
+    units.set(&TilePos { x: 1, y: 2 }, Entity::from_raw(1));
+    let reachable = reachable_tiles(
+      &tile_types,
+      &units,
+      &GridCoords::new(0, 2),
+      10,
+      MovementClass::Foot,
+    );
+
+    assert!(reachable.keys().all(|tile| tile.x < 2));
+    assert!(!reachable.contains_key(&GridCoords::new(1, 2)));
+    assert!(reachable.contains_key(&GridCoords::new(1, 3)));
+  }
+
+  #[test]
+  fn forest_costs_depend_on_the_movement_class() {
+    let tile_types = tile_types(&[".ff"]);
+    let start = GridCoords::new(0, 0);
+
+    let on_foot =
+      reachable_tiles(&tile_types, &no_units(), &start, 4, MovementClass::Foot);
+    assert_eq!(on_foot.len(), 2);
+
+    let mounted = reachable_tiles(
+      &tile_types,
+      &no_units(),
+      &start,
+      4,
+      MovementClass::Mounted,
+    );
+    assert_eq!(mounted.len(), 1);
+    assert!(mounted.contains_key(&GridCoords::new(1, 0)));
+  }
+
+  #[test]
+  fn paths_step_from_the_start_to_the_destination() {
+    let tile_types = tile_types(&[".....", ".~~~.", ".....", ".~~~.", "....."]);
+    let start = GridCoords::new(0, 0);
+    let destination = GridCoords::new(4, 4);
+    let moveable_region = MoveableRegion(reachable_tiles(
+      &tile_types,
+      &no_units(),
+      &start,
+      8,
+      MovementClass::Foot,
+    ));
+
+    let path = moveable_region.path_to(destination);
+    assert_eq!(path.len(), 8);
+    assert_eq!(path.last(), Some(&destination));
+    let mut previous = start;
+    for step in path.iter() {
+      let distance = *step - previous;
+      assert_eq!(distance.x.abs() + distance.y.abs(), 1);
+      previous = *step;
+    }
+
+    assert!(moveable_region.path_to(start).is_empty());
+  }
+}
@@ -0,0 +1,378 @@
+use bevy::{
+  app::AppExit,
+  ecs::{
+    component::Component,
+    entity::Entity,
+    event::EventReader,
+    query::With,
+    schedule::NextState,
+    system::{Commands, Query, Res, ResMut, Resource},
+  },
+  hierarchy::DespawnRecursiveExt,
+  log::{error, info, warn},
+  ui::{PositionType, Style, Val},
+};
+use bevy_ecs_tilemap::tiles::TilePos;
+use std::{
+  fs::{File, TryLockError},
+  path::{Path, PathBuf},
+};
+
+use crate::{
+  windows::{
+    spawn_list_menu, MenuCancelled, MenuChosen, WindowAssets, WindowFocus,
+  },
+  GlobalState,
+};
+
+use super::{
+  commands::PhaseStarted,
+  input::{Action, KeyBindings},
+  rng::MatchRng,
+  save::{capture_save_game, request_load, SaveGame},
+  units::{Unit, UnitAssociation, UnitKind},
+  MatchConfig, TurnNumber,
+};
+
+const AUTOSAVE_DIRECTORY: &str = "saves/autosave";
+const SESSION_DIRECTORY: &str = "saves/sessions";
+
+#[derive(Resource)]
+pub struct AutosaveSettings {
+  /// How many turns' worth of autosaves to keep before the oldest are
+  /// deleted. Each turn holds an autosave for every player's phase.
+  pub limit: usize,
+}
+
+impl Default for AutosaveSettings {
+  fn default() -> Self {
+    AutosaveSettings { limit: 8 }
+  }
+}
+
+/// The autosave the player may resume from, found at startup when an earlier
+/// session never reached a clean exit.
+#[derive(Resource)]
+pub struct RecoveryOffer {
+  pub save: PathBuf,
+  /// The autosave directories of the sessions that did not exit cleanly,
+  /// removed once the offer is answered.
+  sessions: Vec<PathBuf>,
+}
+
+#[derive(Component)]
+pub struct RecoveryPrompt;
+
+/// This session's lock file, kept open and locked for as long as the process
+/// runs. The operating system releases the lock however the process ends, so
+/// a lock file nobody holds was left behind by a session that crashed.
+#[derive(Resource)]
+pub struct SessionLock(Option<File>);
+
+/// Each running instance autosaves to its own directory so that one never
+/// prunes the autosaves of another.
+fn session_autosave_directory(pid: u32) -> PathBuf {
+  PathBuf::from(AUTOSAVE_DIRECTORY).join(pid.to_string())
+}
+
+/// The lock is named after the process that created it, and removed on a
+/// clean exit.
+fn session_lock_path(pid: u32) -> PathBuf {
+  PathBuf::from(SESSION_DIRECTORY).join(format!("{}.lock", pid))
+}
+
+fn autosave_paths(directory: &Path) -> Vec<PathBuf> {
+  let Ok(entries) = std::fs::read_dir(directory) else {
+    return Vec::new();
+  };
+
+  // file names start with a zero-padded timestamp, so sorting by name sorts
+  // them oldest first
+  let mut paths = entries
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.extension().is_some_and(|ext| ext == "ron"))
+    .collect::<Vec<_>>();
+  paths.sort();
+  paths
+}
+
+/// Whether nothing holds the lock at `path` any more. A lock another running
+/// instance holds belongs to it, along with its autosaves, and is left alone.
+fn lock_abandoned(path: &Path) -> bool {
+  let Ok(file) = File::open(path) else {
+    return false;
+  };
+  match file.try_lock() {
+    Ok(()) => true,
+    Err(TryLockError::WouldBlock) => false,
+    Err(TryLockError::Error(err)) => {
+      warn!("could not check {}: {}", path.display(), err);
+      false
+    }
+  }
+}
+
+/// The sessions whose lock is still there but no longer held, meaning they
+/// crashed or were killed.
+fn stale_sessions(own_pid: u32) -> Vec<u32> {
+  let Ok(entries) = std::fs::read_dir(SESSION_DIRECTORY) else {
+    return Vec::new();
+  };
+
+  entries
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.extension().is_some_and(|ext| ext == "lock"))
+    .filter_map(|path| {
+      let pid = path.file_stem()?.to_str()?.parse::<u32>().ok()?;
+      (pid != own_pid && lock_abandoned(&path)).then_some(pid)
+    })
+    .collect()
+}
+
+/// Creates and locks this session's lock file.
+fn acquire_session_lock(own_pid: u32) -> std::io::Result<SessionLock> {
+  let lock_path = session_lock_path(own_pid);
+  if let Some(parent) = lock_path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let file = File::create(&lock_path)?;
+  file.lock()?;
+  Ok(SessionLock(Some(file)))
+}
+
+/// Takes this session's lock, and if a lock was left behind by a session
+/// that is no longer running, offers to resume from its newest autosave.
+pub fn check_previous_session(mut commands: Commands) {
+  let own_pid = std::process::id();
+
+  // lock first, so that another instance starting up at the same time never
+  // mistakes this session for a crashed one
+  match acquire_session_lock(own_pid) {
+    Ok(session_lock) => commands.insert_resource(session_lock),
+    Err(err) => error!(
+      "could not lock {}: {}",
+      session_lock_path(own_pid).display(),
+      err
+    ),
+  }
+
+  let mut newest: Option<PathBuf> = None;
+  let mut sessions = Vec::new();
+  for pid in stale_sessions(own_pid) {
+    let _ = std::fs::remove_file(session_lock_path(pid));
+    let directory = session_autosave_directory(pid);
+    if let Some(path) = autosave_paths(&directory).pop() {
+      if newest
+        .as_ref()
+        .is_none_or(|newest| path.file_name() > newest.file_name())
+      {
+        newest = Some(path);
+      }
+    }
+    sessions.push(directory);
+  }
+
+  if !sessions.is_empty() {
+    match newest {
+      Some(save) => {
+        warn!(
+          "a previous session did not exit cleanly; offering to resume from {}",
+          save.display()
+        );
+        commands.insert_resource(RecoveryOffer { save, sessions });
+      }
+      None => {
+        warn!("a previous session did not exit cleanly");
+        remove_session_autosaves(&sessions);
+      }
+    }
+  }
+}
+
+fn remove_session_autosaves(sessions: &[PathBuf]) {
+  for directory in sessions {
+    if let Err(err) = std::fs::remove_dir_all(directory) {
+      if err.kind() != std::io::ErrorKind::NotFound {
+        warn!("could not remove {}: {}", directory.display(), err);
+      }
+    }
+  }
+}
+
+/// Removes this session's lock and its autosaves, which are only kept for
+/// recovering from a crash.
+pub fn clear_session_lock(
+  mut app_exits: EventReader<AppExit>,
+  session_lock: Option<ResMut<SessionLock>>,
+) {
+  if app_exits.read().next().is_some() {
+    // closing the file releases the lock, and lets it be removed where open
+    // files cannot be
+    if let Some(mut session_lock) = session_lock {
+      session_lock.0.take();
+    }
+    let own_pid = std::process::id();
+    let _ = std::fs::remove_file(session_lock_path(own_pid));
+    remove_session_autosaves(&[session_autosave_directory(own_pid)]);
+  }
+}
+
+pub fn autosave_turn_start(
+  mut phase_started_events: EventReader<PhaseStarted>,
+  settings: Res<AutosaveSettings>,
+  match_config: Res<MatchConfig>,
+  turn_number: Res<TurnNumber>,
+  rng: Res<MatchRng>,
+  units: Query<(&UnitKind, &UnitAssociation, &TilePos, &Unit)>,
+) {
+  let Some(phase_started) = phase_started_events.read().last().copied() else {
+    return;
+  };
+
+  let save_game = capture_save_game(
+    &match_config,
+    phase_started.turn,
+    &turn_number,
+    &rng,
+    &units,
+  );
+
+  let timestamp = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|duration| duration.as_millis())
+    .unwrap_or_default();
+  let directory = session_autosave_directory(std::process::id());
+  let path = directory.join(format!(
+    "{:016}-turn{}-{:?}.ron",
+    timestamp, phase_started.turn_number, phase_started.turn
+  ));
+
+  match save_game.write(&path) {
+    Ok(()) => info!("autosaved to {}", path.display()),
+    Err(err) => {
+      error!("{}", err);
+      return;
+    }
+  }
+
+  for old_path in excess_autosaves(autosave_paths(&directory), settings.limit) {
+    if let Err(err) = std::fs::remove_file(&old_path) {
+      warn!("could not remove {}: {}", old_path.display(), err);
+    }
+  }
+}
+
+pub fn show_recovery_prompt(
+  mut commands: Commands,
+  window_assets: Res<WindowAssets>,
+  mut window_focus: ResMut<WindowFocus>,
+  key_bindings: Res<KeyBindings>,
+) {
+  let title = format!(
+    "The last session ended unexpectedly.\n\
+     Press {} to start afresh.",
+    key_bindings.player1.describe(Action::Cancel),
+  );
+  spawn_list_menu(
+    &mut commands,
+    &window_assets,
+    &mut window_focus,
+    Style {
+      position_type: PositionType::Absolute,
+      left: Val::Px(16.0),
+      top: Val::Px(16.0),
+      ..Default::default()
+    },
+    Some(&title),
+    &[
+      "Resume from the latest autosave".to_string(),
+      "Start afresh".to_string(),
+    ],
+    RecoveryPrompt,
+  );
+}
+
+pub fn answer_recovery_prompt(
+  mut commands: Commands,
+  mut chosen_events: EventReader<MenuChosen>,
+  mut cancelled_events: EventReader<MenuCancelled>,
+  offer: Res<RecoveryOffer>,
+  prompts: Query<Entity, With<RecoveryPrompt>>,
+  mut next_global_state: ResMut<NextState<GlobalState>>,
+) {
+  let Ok(prompt) = prompts.get_single() else {
+    return;
+  };
+  let chosen = chosen_events
+    .read()
+    .find(|chosen| chosen.menu == prompt)
+    .map(|chosen| chosen.index);
+  let cancelled = cancelled_events
+    .read()
+    .any(|cancelled| cancelled.menu == prompt);
+  if chosen.is_none() && !cancelled {
+    return;
+  }
+
+  commands.entity(prompt).despawn_recursive();
+  commands.remove_resource::<RecoveryOffer>();
+
+  if chosen == Some(0) {
+    match SaveGame::load(&offer.save) {
+      Ok(save_game) => {
+        info!("resuming from {}", offer.save.display());
+        request_load(&mut commands, save_game, &mut next_global_state);
+      }
+      Err(err) => error!("{}", err),
+    }
+  }
+  remove_session_autosaves(&offer.sessions);
+}
+
+/// The turn an autosave was taken on, read back from its file name.
+fn autosave_turn(path: &Path) -> Option<u32> {
+  let (_, rest) = path.file_stem()?.to_str()?.split_once("-turn")?;
+  rest.split('-').next()?.parse().ok()
+}
+
+/// The autosaves in `paths`, sorted oldest first, that fall outside the
+/// newest `limit` turns.
+fn excess_autosaves(paths: Vec<PathBuf>, limit: usize) -> Vec<PathBuf> {
+  let mut kept_turns = Vec::new();
+  for turn in paths.iter().rev().filter_map(|path| autosave_turn(path)) {
+    if kept_turns.len() < limit && !kept_turns.contains(&turn) {
+      kept_turns.push(turn);
+    }
+  }
+  paths
+    .into_iter()
+    .filter(|path| {
+      autosave_turn(path).is_some_and(|turn| !kept_turns.contains(&turn))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn autosave(timestamp: u64, turn: u32, player: &str) -> PathBuf {
+    PathBuf::from(format!("{:016}-turn{}-{}.ron", timestamp, turn, player))
+  }
+
+  #[test]
+  fn pruning_keeps_every_phase_of_the_newest_turns() {
+    let paths = vec![
+      autosave(1, 1, "Player1"),
+      autosave(2, 1, "Player2"),
+      autosave(3, 2, "Player1"),
+      autosave(4, 2, "Player2"),
+      autosave(5, 3, "Player1"),
+    ];
+
+    assert_eq!(
+      excess_autosaves(paths, 2),
+      vec![autosave(1, 1, "Player1"), autosave(2, 1, "Player2")]
+    );
+  }
+}
@@ -0,0 +1,212 @@
+use bevy::ecs::{
+  entity::Entity,
+  query::With,
+  system::{Commands, Query, Res},
+};
+use bevy_ecs_ldtk::GridCoords;
+use bevy_ecs_tilemap::{
+  map::TilemapId,
+  tiles::{TileBundle, TileStorage, TileTextureIndex},
+};
+
+use crate::{
+  sprites::Sprites,
+  tiles::{TerrainLook, TileTypes},
+};
+
+use super::{GameEntity, LevelSize, TerrainMap};
+
+/// Which of a water cell's eight neighbours are water too. Cells beyond the
+/// edge of the level count as water, so that lakes run off the map rather
+/// than growing a shore along it.
+#[derive(Clone, Copy)]
+struct WaterNeighbours {
+  up: bool,
+  down: bool,
+  left: bool,
+  right: bool,
+  up_left: bool,
+  up_right: bool,
+  down_left: bool,
+  down_right: bool,
+}
+
+impl WaterNeighbours {
+  fn of(
+    tile_types: &TileTypes,
+    level_size: &LevelSize,
+    grid_coords: GridCoords,
+  ) -> Self {
+    let water = |x: i32, y: i32| {
+      let neighbour = grid_coords + GridCoords::new(x, y);
+      !level_size.contains(neighbour)
+        || tile_types
+          .terrain_at(&neighbour)
+          .is_some_and(|terrain| terrain.look == TerrainLook::Water)
+    };
+
+    // grid coordinates grow upwards
+    WaterNeighbours {
+      up: water(0, 1),
+      down: water(0, -1),
+      left: water(-1, 0),
+      right: water(1, 0),
+      up_left: water(-1, 1),
+      up_right: water(1, 1),
+      down_left: water(-1, -1),
+      down_right: water(1, -1),
+    }
+  }
+
+  /// Picks the water sprite that joins up with these neighbours. The rules
+  /// are tried from the narrowest shape to the widest, so a river bend wins
+  /// over a lake edge, and an edge over an inner corner.
+  fn sprite(&self) -> &'static str {
+    let WaterNeighbours {
+      up,
+      down,
+      left,
+      right,
+      up_left,
+      up_right,
+      down_left,
+      down_right,
+    } = *self;
+
+    // river bends
+    if !up && !left && right && down && !down_right {
+      "water/river_up_left"
+    } else if !up && !right && left && down && !down_left {
+      "water/river_up_right"
+    } else if !down && !left && right && up && !up_right {
+      "water/river_down_left"
+    } else if !down && !right && left && up && !up_left {
+      "water/river_down_right"
+    // straight rivers, and their ends
+    } else if !up && !down {
+      "water/river_horizontal"
+    } else if !left && !right {
+      "water/river_vertical"
+    // the outer corners and edges of lakes
+    } else if !up && !left {
+      "water/lake_up_left"
+    } else if !up && !right {
+      "water/lake_up_right"
+    } else if !down && !left {
+      "water/lake_down_left"
+    } else if !down && !right {
+      "water/lake_down_right"
+    } else if !up {
+      "water/lake_up"
+    } else if !down {
+      "water/lake_down"
+    } else if !left {
+      "water/lake_left"
+    } else if !right {
+      "water/lake_right"
+    // where a river flows into a lake, land on both sides of it
+    } else if !up_left && !up_right {
+      "water/river_mouth_up"
+    } else if !down_left && !down_right {
+      "water/river_mouth_down"
+    } else if !up_left && !down_left {
+      "water/river_mouth_left"
+    } else if !up_right && !down_right {
+      "water/river_mouth_right"
+    // inner corners
+    } else if !up_left {
+      "water/lake_corner_up_left"
+    } else if !up_right {
+      "water/lake_corner_up_right"
+    } else if !down_left {
+      "water/lake_corner_down_left"
+    } else if !down_right {
+      "water/lake_corner_down_right"
+    } else {
+      "water/lake"
+    }
+  }
+}
+
+/// Scatters a terrain's variations over a level. Only the look of a tile
+/// depends on it, so it hashes the tile's position rather than drawing from
+/// the match's RNG, and a level looks the same every time it is played.
+fn scattered_sprite(
+  grid_coords: GridCoords,
+  sprites: &[(String, u32)],
+) -> &str {
+  let mut hash = (grid_coords.x as u32).wrapping_mul(0x9e37_79b1)
+    ^ (grid_coords.y as u32).wrapping_mul(0x85eb_ca77);
+  hash ^= hash >> 15;
+  hash = hash.wrapping_mul(0x2c1b_3c6d);
+  hash ^= hash >> 12;
+
+  let total = sprites.iter().map(|(_, weight)| weight).sum::<u32>().max(1);
+  let mut roll = hash % total;
+  for (sprite, weight) in sprites.iter() {
+    if roll < *weight {
+      return sprite;
+    }
+    roll -= weight;
+  }
+  sprites.last().map_or("", |(sprite, _)| sprite)
+}
+
+fn terrain_sprite<'a>(
+  tile_types: &'a TileTypes,
+  level_size: &LevelSize,
+  grid_coords: GridCoords,
+) -> Option<&'a str> {
+  Some(match &tile_types.terrain_at(&grid_coords)?.look {
+    TerrainLook::Tile(sprite) => sprite,
+    TerrainLook::Scattered(sprites) => scattered_sprite(grid_coords, sprites),
+    TerrainLook::Water => {
+      WaterNeighbours::of(tile_types, level_size, grid_coords).sprite()
+    }
+  })
+}
+
+/// Paints the terrain layer from the level's [`TileTypes`], so that a level
+/// only needs its IntGrid filled in to look finished.
+pub fn paint_terrain(
+  mut commands: Commands,
+  tile_types: Res<TileTypes>,
+  level_size: Res<LevelSize>,
+  sprites: Sprites,
+  mut terrain_map: Query<(Entity, &mut TileStorage), With<TerrainMap>>,
+) {
+  let Ok((terrain_map, mut terrain_storage)) = terrain_map.get_single_mut()
+  else {
+    return;
+  };
+
+  for tile in terrain_storage.iter_mut() {
+    if let Some(tile) = tile.take() {
+      commands.entity(tile).despawn();
+    }
+  }
+
+  for x in 0..terrain_storage.size.x {
+    for y in 0..terrain_storage.size.y {
+      let grid_coords = GridCoords::new(x as i32, y as i32);
+      let Some(sprite) = terrain_sprite(&tile_types, &level_size, grid_coords)
+      else {
+        continue;
+      };
+
+      let tile_pos = crate::util::grid_to_tile(grid_coords);
+      let tile = commands
+        .spawn((
+          TileBundle {
+            position: tile_pos,
+            texture_index: TileTextureIndex(sprites.index(sprite) as u32),
+            tilemap_id: TilemapId(terrain_map),
+            ..Default::default()
+          },
+          GameEntity,
+        ))
+        .id();
+      terrain_storage.set(&tile_pos, tile);
+    }
+  }
+}
@@ -0,0 +1,181 @@
+use bevy::{
+  ecs::{
+    event::EventReader,
+    query::{Changed, With},
+    system::{Query, Res, ResMut, Resource},
+  },
+  input::mouse::MouseWheel,
+  math::{IVec2, Vec2},
+  render::camera::OrthographicProjection,
+  time::Time,
+  transform::components::Transform,
+  window::{PrimaryWindow, Window},
+};
+use bevy_ecs_ldtk::GridCoords;
+
+use super::{
+  cursor::Cursor,
+  input::{Action, Actions},
+  GlobalCamera, LevelSize,
+};
+
+/// The most of a level shown at once at the default zoom, in pixels. Levels
+/// no bigger than this are shown whole, as before.
+pub const VIEW_SIZE: Vec2 = Vec2::new(512.0, 288.0);
+
+/// Projection scales to step through, from closest to furthest.
+const ZOOM_LEVELS: [f32; 5] = [0.5, 0.75, 1.0, 1.5, 2.0];
+const DEFAULT_ZOOM: usize = 2;
+
+/// How close the cursor may come to the edge of the view, in pixels, before
+/// the camera moves to follow it.
+const FOLLOW_MARGIN: f32 = 48.0;
+/// How close the mouse has to be to the edge of the window, in logical
+/// pixels, to scroll the view.
+const EDGE_SCROLL_MARGIN: f32 = 12.0;
+const EDGE_SCROLL_SPEED: f32 = 320.0;
+/// The fraction of the remaining distance the camera covers per second.
+const CAMERA_EASING: f32 = 12.0;
+
+/// Where the camera is heading and how far it is zoomed out.
+#[derive(Resource)]
+pub struct CameraView {
+  pub target: Vec2,
+  zoom: usize,
+}
+
+impl CameraView {
+  pub fn new(target: Vec2) -> Self {
+    CameraView {
+      target,
+      zoom: DEFAULT_ZOOM,
+    }
+  }
+
+  fn scale(&self) -> f32 {
+    ZOOM_LEVELS[self.zoom]
+  }
+}
+
+pub fn zoom_camera(
+  actions: Res<Actions>,
+  mut mouse_wheel_events: EventReader<MouseWheel>,
+  mut camera_view: ResMut<CameraView>,
+) {
+  let scrolled = mouse_wheel_events
+    .read()
+    .map(|mouse_wheel| mouse_wheel.y)
+    .sum::<f32>();
+
+  if (actions.any_just_pressed(Action::ZoomIn) || scrolled > 0.0)
+    && camera_view.zoom > 0
+  {
+    camera_view.zoom -= 1;
+  }
+  if (actions.any_just_pressed(Action::ZoomOut) || scrolled < 0.0)
+    && camera_view.zoom < ZOOM_LEVELS.len() - 1
+  {
+    camera_view.zoom += 1;
+  }
+}
+
+/// Keeps the cursor at least [`FOLLOW_MARGIN`] away from the edges of the
+/// view, moving the camera no further than needed.
+pub fn follow_cursor(
+  cursor: Query<&GridCoords, (With<Cursor>, Changed<GridCoords>)>,
+  global_camera: Res<GlobalCamera>,
+  projections: Query<&OrthographicProjection>,
+  mut camera_view: ResMut<CameraView>,
+) {
+  let Ok(grid_coords) = cursor.get_single() else {
+    return;
+  };
+  let Ok(projection) = projections.get(**global_camera) else {
+    return;
+  };
+
+  let cursor_position = bevy_ecs_ldtk::utils::grid_coords_to_translation(
+    *grid_coords,
+    IVec2::splat(16),
+  );
+  let half_view =
+    (projection.area.size() / 2.0 - FOLLOW_MARGIN).max(Vec2::ZERO);
+  let target = camera_view.target;
+  camera_view.target =
+    target.clamp(cursor_position - half_view, cursor_position + half_view);
+}
+
+pub fn edge_scroll(
+  windows: Query<&Window, With<PrimaryWindow>>,
+  time: Res<Time>,
+  mut camera_view: ResMut<CameraView>,
+) {
+  let Ok(window) = windows.get_single() else {
+    return;
+  };
+  if !window.focused {
+    return;
+  }
+  let Some(mouse_position) = window.cursor_position() else {
+    return;
+  };
+
+  // window coordinates grow downwards, the world's upwards
+  let mut direction = Vec2::ZERO;
+  if mouse_position.x < EDGE_SCROLL_MARGIN {
+    direction.x -= 1.0;
+  }
+  if mouse_position.x > window.width() - EDGE_SCROLL_MARGIN {
+    direction.x += 1.0;
+  }
+  if mouse_position.y < EDGE_SCROLL_MARGIN {
+    direction.y += 1.0;
+  }
+  if mouse_position.y > window.height() - EDGE_SCROLL_MARGIN {
+    direction.y -= 1.0;
+  }
+
+  // scroll at the same speed on screen whatever the zoom
+  let speed = EDGE_SCROLL_SPEED * camera_view.scale();
+  camera_view.target += direction * speed * time.delta_seconds();
+}
+
+/// Eases the camera towards its target, keeping the view inside the level
+/// wherever the level is big enough to fill it.
+pub fn move_camera(
+  time: Res<Time>,
+  level_size: Res<LevelSize>,
+  global_camera: Res<GlobalCamera>,
+  mut camera_view: ResMut<CameraView>,
+  mut cameras: Query<(&mut Transform, &mut OrthographicProjection)>,
+) {
+  let Ok((mut transform, mut projection)) = cameras.get_mut(**global_camera)
+  else {
+    return;
+  };
+
+  let scale = camera_view.scale();
+  if projection.scale != scale {
+    projection.scale = scale;
+  }
+
+  let level = Vec2::new(level_size.px_wid as f32, level_size.px_hei as f32);
+  let half_view = projection.area.size() / 2.0;
+  let clamp_axis = |target: f32, half_view: f32, level: f32| {
+    if half_view * 2.0 >= level {
+      level / 2.0
+    } else {
+      target.clamp(half_view, level - half_view)
+    }
+  };
+  camera_view.target = Vec2::new(
+    clamp_axis(camera_view.target.x, half_view.x, level.x),
+    clamp_axis(camera_view.target.y, half_view.y, level.y),
+  );
+
+  let position = transform.translation.truncate();
+  let step = (CAMERA_EASING * time.delta_seconds()).min(1.0);
+  let position = position.lerp(camera_view.target, step);
+  transform.translation.x = position.x;
+  transform.translation.y = position.y;
+}
@@ -0,0 +1,418 @@
+use bevy::{
+  ecs::{
+    event::EventReader,
+    query::With,
+    system::{Commands, Query, Res, ResMut, Resource},
+  },
+  log::error,
+  render::color::Color,
+  text::TextStyle,
+  ui::{node_bundles::TextBundle, PositionType, Style, Val},
+};
+use bevy_ecs_ldtk::GridCoords;
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use crate::windows::WindowAssets;
+
+use super::{
+  commands::PhaseStarted,
+  net::NetSession,
+  replay::ReplayViewer,
+  rng::MatchRng,
+  save::{SaveGame, SavedUnit},
+  units::{Unit, UnitAssociation, UnitKind},
+  GameEntity, MatchConfig, TurnState, UnitMap,
+};
+
+/// The state of the board at the start of a phase, along with its hash.
+///
+/// The full snapshot travels with the hash so that, when two of them
+/// disagree, the report can say exactly where.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TurnChecksum {
+  pub turn: TurnState,
+  pub turn_number: u32,
+  pub checksum: u64,
+  pub snapshot: SaveGame,
+}
+
+/// Every checksum computed locally during the current match.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct TurnChecksums(pub Vec<TurnChecksum>);
+
+impl TurnChecksums {
+  pub fn find(
+    &self,
+    turn: TurnState,
+    turn_number: u32,
+  ) -> Option<&TurnChecksum> {
+    self.0.iter().rev().find(|turn_checksum| {
+      turn_checksum.turn == turn && turn_checksum.turn_number == turn_number
+    })
+  }
+
+  /// Keeps one checksum per phase, replacing any taken earlier for the same
+  /// one, as when a replay is stepped back past it and played forward again.
+  pub fn record(&mut self, turn_checksum: TurnChecksum) {
+    self.0.retain(|recorded| {
+      recorded.turn != turn_checksum.turn
+        || recorded.turn_number != turn_checksum.turn_number
+    });
+    self.0.push(turn_checksum);
+  }
+}
+
+/// Present once the match has been stopped because two views of it
+/// disagreed; holds the report shown to the player.
+#[derive(Resource)]
+pub struct Desync(pub String);
+
+/// Reads the board in storage order, so that the snapshot does not depend on
+/// the order in which entities happen to be iterated.
+pub fn snapshot_board(
+  match_config: &MatchConfig,
+  phase_started: PhaseStarted,
+  rng: &MatchRng,
+  unit_storage: &TileStorage,
+  units: &Query<(&UnitKind, &UnitAssociation, &Unit)>,
+) -> SaveGame {
+  let mut saved_units = Vec::new();
+  for x in 0..unit_storage.size.x {
+    for y in 0..unit_storage.size.y {
+      let Some((unit_type, association, unit)) = unit_storage
+        .get(&TilePos { x, y })
+        .and_then(|entity| units.get(entity).ok())
+      else {
+        continue;
+      };
+
+      saved_units.push(SavedUnit {
+        unit_type: unit_type.clone(),
+        association: association.turn,
+        grid_coords: GridCoords {
+          x: x as i32,
+          y: y as i32,
+        },
+        health: unit.health,
+        moved: unit.moved,
+        acted: unit.acted,
+      });
+    }
+  }
+
+  SaveGame {
+    level: match_config.level,
+    seed: match_config.seed,
+    generated: match_config.generated,
+    turn: phase_started.turn,
+    turn_number: phase_started.turn_number,
+    rng: *rng,
+    units: saved_units,
+  }
+}
+
+/// A 64-bit FNV-1a hash over a fixed little-endian encoding of the snapshot,
+/// so the result is the same on every platform and build.
+pub fn board_checksum(snapshot: &SaveGame) -> u64 {
+  let mut hash = 0xcbf2_9ce4_8422_2325u64;
+  let mut feed = |bytes: &[u8]| {
+    for byte in bytes {
+      hash ^= *byte as u64;
+      hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+  };
+
+  feed(&(snapshot.level as u64).to_le_bytes());
+  feed(&snapshot.seed.to_le_bytes());
+  // left out for levels from the project, keeping their checksums as they
+  // were before levels could be generated
+  if let Some(map) = snapshot.generated {
+    feed(&(map.width as u64).to_le_bytes());
+    feed(&(map.height as u64).to_le_bytes());
+  }
+  feed(&[snapshot.turn as u8]);
+  feed(&snapshot.turn_number.to_le_bytes());
+  feed(&snapshot.rng.state().to_le_bytes());
+  for saved_unit in snapshot.units.iter() {
+    feed(&saved_unit.grid_coords.x.to_le_bytes());
+    feed(&saved_unit.grid_coords.y.to_le_bytes());
+    // prefixed with its length, so that one id cannot run into the next
+    feed(&(saved_unit.unit_type.0.len() as u64).to_le_bytes());
+    feed(saved_unit.unit_type.0.as_bytes());
+    feed(&[
+      saved_unit.association as u8,
+      saved_unit.moved as u8,
+      saved_unit.acted as u8,
+    ]);
+    feed(&saved_unit.health.to_le_bytes());
+  }
+
+  hash
+}
+
+/// Lists every field on which two snapshots disagree, one per line.
+pub fn describe_differences(local: &SaveGame, remote: &SaveGame) -> String {
+  let mut report = String::new();
+
+  if local.level != remote.level {
+    let _ = writeln!(report, "level: {} vs {}", local.level, remote.level);
+  }
+  if local.generated != remote.generated {
+    let _ = writeln!(
+      report,
+      "generated: {:?} vs {:?}",
+      local.generated, remote.generated
+    );
+  }
+  if local.seed != remote.seed {
+    let _ = writeln!(report, "seed: {} vs {}", local.seed, remote.seed);
+  }
+  if local.turn != remote.turn {
+    let _ = writeln!(report, "turn: {:?} vs {:?}", local.turn, remote.turn);
+  }
+  if local.turn_number != remote.turn_number {
+    let _ = writeln!(
+      report,
+      "turn number: {} vs {}",
+      local.turn_number, remote.turn_number
+    );
+  }
+  if local.rng != remote.rng {
+    let _ = writeln!(
+      report,
+      "rng state: {:#x} vs {:#x}",
+      local.rng.state(),
+      remote.rng.state()
+    );
+  }
+
+  let find = |units: &[SavedUnit], grid_coords: GridCoords| {
+    units
+      .iter()
+      .find(|saved_unit| saved_unit.grid_coords == grid_coords)
+      .cloned()
+  };
+  for saved_unit in local.units.iter() {
+    let (x, y) = (saved_unit.grid_coords.x, saved_unit.grid_coords.y);
+    match find(&remote.units, saved_unit.grid_coords) {
+      None => {
+        let _ = writeln!(
+          report,
+          "({}, {}): {:?} {} only present locally",
+          x, y, saved_unit.association, saved_unit.unit_type
+        );
+      }
+      Some(remote_unit) if remote_unit != *saved_unit => {
+        let _ = writeln!(
+          report,
+          "({}, {}): {:?} vs {:?}",
+          x, y, saved_unit, remote_unit
+        );
+      }
+      Some(_) => {}
+    }
+  }
+  for remote_unit in remote.units.iter() {
+    if find(&local.units, remote_unit.grid_coords).is_none() {
+      let _ = writeln!(
+        report,
+        "({}, {}): {:?} {} only present remotely",
+        remote_unit.grid_coords.x,
+        remote_unit.grid_coords.y,
+        remote_unit.association,
+        remote_unit.unit_type
+      );
+    }
+  }
+
+  report
+}
+
+fn compare(local: &TurnChecksum, remote: &TurnChecksum) -> Option<String> {
+  if local.checksum == remote.checksum {
+    return None;
+  }
+
+  Some(format!(
+    "desync at the start of turn {} ({:?}): checksum {:#018x} vs {:#018x}\n{}",
+    local.turn_number,
+    local.turn,
+    local.checksum,
+    remote.checksum,
+    describe_differences(&local.snapshot, &remote.snapshot)
+  ))
+}
+
+fn halt_on_desync(commands: &mut Commands, report: String) {
+  error!("{}", report);
+  commands.remove_resource::<NetSession>();
+  commands.insert_resource(Desync(report));
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn record_turn_checksum(
+  mut commands: Commands,
+  mut phase_started_events: EventReader<PhaseStarted>,
+  match_config: Res<MatchConfig>,
+  rng: Res<MatchRng>,
+  unit_map: Query<&TileStorage, With<UnitMap>>,
+  units: Query<(&UnitKind, &UnitAssociation, &Unit)>,
+  mut turn_checksums: ResMut<TurnChecksums>,
+  net_session: Option<Res<NetSession>>,
+  replay_viewer: Option<ResMut<ReplayViewer>>,
+) {
+  let Some(phase_started) = phase_started_events.read().last().copied() else {
+    return;
+  };
+
+  let snapshot = snapshot_board(
+    &match_config,
+    phase_started,
+    &rng,
+    unit_map.single(),
+    &units,
+  );
+  let turn_checksum = TurnChecksum {
+    turn: phase_started.turn,
+    turn_number: phase_started.turn_number,
+    checksum: board_checksum(&snapshot),
+    snapshot,
+  };
+
+  if let Some(net_session) = net_session {
+    net_session.send_checksum(&turn_checksum);
+  }
+
+  if let Some(mut replay_viewer) = replay_viewer {
+    let recorded = replay_viewer
+      .recorded_checksum(turn_checksum.turn, turn_checksum.turn_number)
+      .cloned();
+    if let Some(report) =
+      recorded.and_then(|recorded| compare(&turn_checksum, &recorded))
+    {
+      replay_viewer.pause();
+      halt_on_desync(&mut commands, report);
+    }
+  }
+
+  turn_checksums.record(turn_checksum);
+}
+
+pub fn check_remote_checksums(
+  mut commands: Commands,
+  mut net_session: ResMut<NetSession>,
+  turn_checksums: Res<TurnChecksums>,
+) {
+  for remote in net_session.take_comparable_checksums(&turn_checksums) {
+    let local = turn_checksums
+      .find(remote.turn, remote.turn_number)
+      .unwrap();
+    if let Some(report) = compare(local, &remote) {
+      halt_on_desync(&mut commands, report);
+      return;
+    }
+  }
+}
+
+pub fn show_desync_report(
+  mut commands: Commands,
+  window_assets: Res<WindowAssets>,
+  desync: Res<Desync>,
+) {
+  commands.spawn((
+    TextBundle::from_section(
+      format!("The match has been stopped.\n{}", desync.0),
+      TextStyle {
+        font: window_assets.font.clone(),
+        font_size: 18.0,
+        color: Color::WHITE,
+      },
+    )
+    .with_background_color(Color::rgba(0.3, 0.0, 0.0, 0.85))
+    .with_style(Style {
+      position_type: PositionType::Absolute,
+      left: Val::Px(16.0),
+      top: Val::Px(16.0),
+      ..Default::default()
+    }),
+    GameEntity,
+  ));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn snapshot() -> SaveGame {
+    SaveGame {
+      level: 0,
+      seed: 7,
+      generated: None,
+      turn: TurnState::Player1,
+      turn_number: 3,
+      rng: MatchRng::new(7),
+      units: vec![
+        SavedUnit {
+          unit_type: UnitKind("knight".to_string()),
+          association: TurnState::Player1,
+          grid_coords: GridCoords::new(1, 2),
+          health: 20,
+          moved: false,
+          acted: false,
+        },
+        SavedUnit {
+          unit_type: UnitKind("archer".to_string()),
+          association: TurnState::Player2,
+          grid_coords: GridCoords::new(4, 4),
+          health: 12,
+          moved: true,
+          acted: true,
+        },
+      ],
+    }
+  }
+
+  #[test]
+  fn checksum_is_stable() {
+    assert_eq!(board_checksum(&snapshot()), board_checksum(&snapshot()));
+  }
+
+  #[test]
+  fn checksum_changes_with_every_field() {
+    let original = board_checksum(&snapshot());
+    let changes: [fn(&mut SaveGame); 9] = [
+      |snapshot| snapshot.level = 1,
+      |snapshot| snapshot.seed = 8,
+      |snapshot| snapshot.turn = TurnState::Player2,
+      |snapshot| snapshot.turn_number = 4,
+      |snapshot| {
+        snapshot.rng.next_u64();
+      },
+      |snapshot| snapshot.units[0].grid_coords = GridCoords::new(2, 1),
+      |snapshot| snapshot.units[0].health = 19,
+      |snapshot| snapshot.units[1].moved = false,
+      |snapshot| snapshot.units[1].unit_type = UnitKind("knight".to_string()),
+    ];
+
+    for (index, change) in changes.iter().enumerate() {
+      let mut changed = snapshot();
+      change(&mut changed);
+      assert_ne!(board_checksum(&changed), original, "change {}", index);
+    }
+  }
+
+  #[test]
+  fn differences_name_the_unit_that_differs() {
+    let mut remote = snapshot();
+    remote.units[1].health = 10;
+    remote.units.remove(0);
+
+    let report = describe_differences(&snapshot(), &remote);
+    assert_eq!(report.lines().count(), 2);
+    assert!(report.contains("(1, 2): Player1 knight only present locally"));
+    assert!(report.contains("(4, 4):"));
+    assert!(describe_differences(&snapshot(), &snapshot()).is_empty());
+  }
+}
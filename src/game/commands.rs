@@ -0,0 +1,671 @@
+use bevy::{
+  ecs::{
+    entity::Entity,
+    event::{Event, EventReader, EventWriter},
+    query::With,
+    schedule::{NextState, State},
+    system::{Commands, Local, Query, Res, ResMut},
+  },
+  hierarchy::DespawnRecursiveExt,
+  log::{info, warn},
+};
+use bevy_ecs_ldtk::GridCoords;
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
+use serde::{Deserialize, Serialize};
+
+use crate::tiles::TileTypes;
+
+use super::{
+  arrows::{tile_defence, tile_move_cost, ArrowHead},
+  cursor::Targeted,
+  replay::ReplayViewer,
+  rng::MatchRng,
+  units::{Unit, UnitAssociation, UnitAssociations},
+  GameState, MatchConfig, TurnNumber, TurnState, UnitMap,
+};
+
+/// Every action a player can take during a match.
+///
+/// Units are referred to by the tile they stand on rather than by entity, so
+/// that a command means the same thing no matter which machine or which run
+/// of the game it is applied on. Input, AI, network and replay all produce
+/// these; only [`apply_game_commands`] ever acts on them.
+#[derive(Event, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameCommand {
+  SelectUnit {
+    #[serde(with = "crate::util::serde_grid_coords")]
+    unit: GridCoords,
+  },
+  Deselect,
+  /// `path` holds every tile stepped onto, in order, ending at the
+  /// destination; the unit's starting tile is not included.
+  MoveUnit {
+    #[serde(with = "crate::util::serde_grid_coords")]
+    unit: GridCoords,
+    #[serde(with = "crate::util::serde_grid_coords::vec")]
+    path: Vec<GridCoords>,
+  },
+  Attack {
+    #[serde(with = "crate::util::serde_grid_coords")]
+    unit: GridCoords,
+    #[serde(with = "crate::util::serde_grid_coords")]
+    target: GridCoords,
+  },
+  Heal {
+    #[serde(with = "crate::util::serde_grid_coords")]
+    unit: GridCoords,
+    #[serde(with = "crate::util::serde_grid_coords")]
+    target: GridCoords,
+  },
+  Wait {
+    #[serde(with = "crate::util::serde_grid_coords")]
+    unit: GridCoords,
+  },
+  EndTurn,
+}
+
+impl GameCommand {
+  /// Whether applying this command changes the state of the board, as
+  /// opposed to only the local selection.
+  pub fn changes_board(&self) -> bool {
+    !matches!(self, GameCommand::SelectUnit { .. } | GameCommand::Deselect)
+  }
+}
+
+/// A command asked for by a local player (or AI), which still has to be
+/// routed before it is applied: straight through in a local match, or via the
+/// host in a networked one.
+#[derive(Event, Debug, Clone)]
+pub struct CommandRequest(pub GameCommand);
+
+/// Sent for every [`GameCommand`] that passed validation and was applied.
+#[derive(Event, Debug, Clone)]
+pub struct AppliedGameCommand(pub GameCommand);
+
+/// Sent once at the start of every phase, after the previous turn has been
+/// fully applied to the board and its state transition has landed.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseStarted {
+  pub turn: TurnState,
+  pub turn_number: u32,
+}
+
+/// Sent whenever an attack or heal changes a unit's health, including the
+/// blow that defeats it.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct HealthChanged {
+  pub grid_coords: GridCoords,
+  /// Positive for healing, negative for damage.
+  pub amount: i32,
+}
+
+/// Sent once one side has no units left on the board.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MatchOver {
+  pub winner: TurnState,
+}
+
+pub fn detect_phase_start(
+  match_config: Res<MatchConfig>,
+  turn_state: Res<State<TurnState>>,
+  next_turn_state: Res<NextState<TurnState>>,
+  turn_number: Res<TurnNumber>,
+  mut phase_started_events: EventWriter<PhaseStarted>,
+  mut last_phase: Local<Option<(u64, PhaseStarted)>>,
+  replay_viewer: Option<ResMut<ReplayViewer>>,
+) {
+  // an end of turn is applied to the board before the state transition
+  // lands, so wait for it rather than reporting a half-changed turn
+  if next_turn_state.0.is_some() {
+    return;
+  }
+
+  let phase_started = PhaseStarted {
+    turn: *turn_state.get(),
+    turn_number: **turn_number,
+  };
+
+  // a replay stepped back replays its commands up to the new position in one
+  // go, landing on a phase that has already started
+  if let Some(mut replay_viewer) = replay_viewer {
+    if replay_viewer.catching_up() {
+      *last_phase = Some((match_config.seed, phase_started));
+      replay_viewer.caught_up();
+      return;
+    }
+  }
+
+  // the seed tells apart two matches that reach the same phase
+  if *last_phase == Some((match_config.seed, phase_started)) {
+    return;
+  }
+  *last_phase = Some((match_config.seed, phase_started));
+
+  phase_started_events.send(phase_started);
+}
+
+pub fn forward_command_requests(
+  mut command_requests: EventReader<CommandRequest>,
+  mut game_commands: EventWriter<GameCommand>,
+) {
+  game_commands.send_batch(
+    command_requests
+      .read()
+      .map(|CommandRequest(game_command)| game_command.clone()),
+  );
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn apply_game_commands(
+  mut commands: Commands,
+  mut game_commands: EventReader<GameCommand>,
+  mut applied_game_commands: EventWriter<AppliedGameCommand>,
+  mut match_over_events: EventWriter<MatchOver>,
+  mut health_changed_events: EventWriter<HealthChanged>,
+  turn_state: Res<State<TurnState>>,
+  mut next_turn_state: ResMut<NextState<TurnState>>,
+  mut next_game_state: ResMut<NextState<GameState>>,
+  mut turn_number: ResMut<TurnNumber>,
+  mut rng: ResMut<MatchRng>,
+  tile_types: Res<TileTypes>,
+  mut unit_map: Query<&mut TileStorage, With<UnitMap>>,
+  mut units: Query<(&mut Unit, &mut TilePos, &UnitAssociation)>,
+  targeted_units: Query<Entity, With<Targeted>>,
+  mut unit_associations: ResMut<UnitAssociations>,
+) {
+  // a single frame may carry several commands (or a replay may feed a whole
+  // turn at once), so track the active player locally rather than waiting
+  // for the state transition to land
+  let mut active_turn = *turn_state.get();
+  let mut unit_map = unit_map.single_mut();
+
+  for game_command in game_commands.read() {
+    let result = match game_command {
+      GameCommand::SelectUnit { unit } => {
+        find_unit(&unit_map, &units, *unit, active_turn).and_then(|entity| {
+          let (unit_data, _, _) = units.get(entity).unwrap();
+          // a unit that has moved but not yet acted can still be selected,
+          // to attack or heal from where it stands
+          if unit_data.acted {
+            return Err("unit has already acted this turn");
+          }
+          for targeted in targeted_units.iter() {
+            commands.entity(targeted).remove::<Targeted>();
+          }
+          commands.entity(entity).insert(Targeted);
+          commands.insert_resource(ArrowHead(*unit));
+          next_game_state.set(GameState::ArrowMovement);
+          info!("targeting unit {:?}", entity);
+          Ok(())
+        })
+      }
+      GameCommand::Deselect => {
+        clear_selection(&mut commands, &targeted_units, &mut next_game_state);
+        Ok(())
+      }
+      GameCommand::MoveUnit { unit, path } => {
+        find_unit(&unit_map, &units, *unit, active_turn).and_then(|entity| {
+          let (mut unit_data, mut tile_pos, _) = units.get_mut(entity).unwrap();
+          if unit_data.moved || unit_data.acted {
+            return Err("unit has already moved this turn");
+          }
+
+          let mut total_cost = 0;
+          let mut previous = *unit;
+          for step in path.iter() {
+            let distance = *step - previous;
+            if distance.x.abs() + distance.y.abs() != 1 {
+              return Err("path is not contiguous");
+            }
+            if unit_map
+              .checked_get(&crate::util::grid_to_tile(*step))
+              .is_some()
+            {
+              return Err("path is blocked by another unit");
+            }
+            total_cost += tile_move_cost(&tile_types, step, unit_data.movement)
+              .ok_or("path crosses impassable terrain")?;
+            previous = *step;
+          }
+          if total_cost > unit_data.max_move_cost {
+            return Err("path is longer than the unit can move");
+          }
+
+          let destination = crate::util::grid_to_tile(previous);
+          unit_map.remove(&tile_pos);
+          unit_map.set(&destination, entity);
+          *tile_pos = destination;
+          unit_data.moved = true;
+
+          clear_selection(&mut commands, &targeted_units, &mut next_game_state);
+          Ok(())
+        })
+      }
+      GameCommand::Attack { unit, target }
+      | GameCommand::Heal { unit, target } => {
+        let healing = matches!(game_command, GameCommand::Heal { .. });
+        find_unit(&unit_map, &units, *unit, active_turn).and_then(|entity| {
+          let attack_range = units.get(entity).unwrap().0.attack_range;
+          let distance = *target - *unit;
+          let distance = (distance.x.abs() + distance.y.abs()) as usize;
+          if distance == 0 || distance > attack_range {
+            return Err("target is out of range");
+          }
+          let target_entity = unit_map
+            .checked_get(&crate::util::grid_to_tile(*target))
+            .ok_or("there is no unit to target")?;
+
+          let [(mut unit_data, _, _), (mut target_data, target_pos, target_association)] =
+            units
+              .get_many_mut([entity, target_entity])
+              .map_err(|_| "there is no unit to target")?;
+          if unit_data.acted {
+            return Err("unit has already acted this turn");
+          }
+
+          if healing {
+            if unit_data.heal <= 0 {
+              return Err("unit cannot heal");
+            }
+            if target_association.turn != active_turn {
+              return Err("cannot heal an enemy unit");
+            }
+            let healed = unit_data
+              .heal
+              .min(target_data.max_health - target_data.health)
+              .max(0);
+            target_data.health += healed;
+            health_changed_events.send(HealthChanged {
+              grid_coords: *target,
+              amount: healed,
+            });
+          } else {
+            if target_association.turn == active_turn {
+              return Err("cannot attack a friendly unit");
+            }
+            let damage = (unit_data.attack + rng.range(-2, 2)
+              - tile_defence(&tile_types, target))
+            .max(0);
+            target_data.health -= damage;
+            health_changed_events.send(HealthChanged {
+              grid_coords: *target,
+              amount: -damage,
+            });
+            if target_data.health <= 0 {
+              info!("unit {:?} was defeated", target_entity);
+              unit_map.remove(&target_pos);
+              unit_associations.remove(target_entity);
+              commands.entity(target_data.backdrop).despawn_recursive();
+              commands.entity(target_entity).despawn_recursive();
+
+              if unit_associations.remaining(target_association.turn) == 0 {
+                info!("{:?} has won the match", active_turn);
+                match_over_events.send(MatchOver {
+                  winner: active_turn,
+                });
+              }
+            }
+          }
+
+          unit_data.moved = true;
+          unit_data.acted = true;
+
+          clear_selection(&mut commands, &targeted_units, &mut next_game_state);
+          Ok(())
+        })
+      }
+      GameCommand::Wait { unit } => {
+        find_unit(&unit_map, &units, *unit, active_turn).map(|entity| {
+          let (mut unit_data, _, _) = units.get_mut(entity).unwrap();
+          unit_data.moved = true;
+          unit_data.acted = true;
+
+          clear_selection(&mut commands, &targeted_units, &mut next_game_state);
+        })
+      }
+      GameCommand::EndTurn => {
+        clear_selection(&mut commands, &targeted_units, &mut next_game_state);
+
+        active_turn = active_turn.next();
+        if active_turn == TurnState::Player1 {
+          **turn_number += 1;
+        }
+        for (mut unit, _, association) in units.iter_mut() {
+          if association.turn == active_turn {
+            unit.moved = false;
+            unit.acted = false;
+          }
+        }
+        next_turn_state.set(active_turn);
+        Ok(())
+      }
+    };
+
+    match result {
+      Ok(()) => {
+        info!("applied {:?}", game_command);
+        applied_game_commands.send(AppliedGameCommand(game_command.clone()));
+      }
+      Err(reason) => warn!("rejected {:?}: {}", game_command, reason),
+    }
+  }
+}
+
+fn find_unit(
+  unit_map: &TileStorage,
+  units: &Query<(&mut Unit, &mut TilePos, &UnitAssociation)>,
+  grid_coords: GridCoords,
+  active_turn: TurnState,
+) -> Result<Entity, &'static str> {
+  let entity = unit_map
+    .checked_get(&crate::util::grid_to_tile(grid_coords))
+    .ok_or("there is no unit on that tile")?;
+  let (_, _, association) = units
+    .get(entity)
+    .map_err(|_| "there is no unit on that tile")?;
+  if association.turn != active_turn {
+    return Err("unit does not belong to the active player");
+  }
+  Ok(entity)
+}
+
+fn clear_selection(
+  commands: &mut Commands,
+  targeted_units: &Query<Entity, With<Targeted>>,
+  next_game_state: &mut NextState<GameState>,
+) {
+  for targeted in targeted_units.iter() {
+    commands.entity(targeted).remove::<Targeted>();
+  }
+  next_game_state.set(GameState::CursorMovement);
+}
+
+#[cfg(test)]
+mod tests {
+  use bevy::{app::App, ecs::event::Events};
+
+  use super::*;
+  use crate::game::fixtures::{board, unit};
+
+  fn apply(app: &mut App, game_command: GameCommand) {
+    app.world.send_event(game_command);
+    app.update();
+  }
+
+  fn health_changes(app: &App) -> Vec<i32> {
+    let events = app.world.resource::<Events<HealthChanged>>();
+    events
+      .get_reader()
+      .read(events)
+      .map(|health_changed| health_changed.amount)
+      .collect()
+  }
+
+  #[test]
+  fn healing_stops_at_max_health() {
+    let healer = Unit { heal: 10, ..unit() };
+    let wounded = Unit {
+      health: 15,
+      ..unit()
+    };
+    let (mut app, entities) = board(vec![
+      (GridCoords::new(1, 1), TurnState::Player1, healer),
+      (GridCoords::new(1, 2), TurnState::Player1, wounded),
+    ]);
+
+    apply(
+      &mut app,
+      GameCommand::Heal {
+        unit: GridCoords::new(1, 1),
+        target: GridCoords::new(1, 2),
+      },
+    );
+
+    assert_eq!(app.world.get::<Unit>(entities[1]).unwrap().health, 20);
+    assert_eq!(health_changes(&app), vec![5]);
+    assert!(app.world.get::<Unit>(entities[0]).unwrap().acted);
+  }
+
+  #[test]
+  fn a_unit_that_cannot_heal_is_not_spent_by_trying() {
+    let (mut app, entities) = board(vec![
+      (GridCoords::new(1, 1), TurnState::Player1, unit()),
+      (GridCoords::new(1, 2), TurnState::Player1, unit()),
+    ]);
+
+    apply(
+      &mut app,
+      GameCommand::Heal {
+        unit: GridCoords::new(1, 1),
+        target: GridCoords::new(1, 2),
+      },
+    );
+
+    let healer = app.world.get::<Unit>(entities[0]).unwrap();
+    assert!(!healer.moved && !healer.acted);
+    assert_eq!(app.world.get::<Unit>(entities[1]).unwrap().health, 20);
+    assert!(health_changes(&app).is_empty());
+  }
+
+  fn applied(app: &App) -> Vec<GameCommand> {
+    let events = app.world.resource::<Events<AppliedGameCommand>>();
+    events
+      .get_reader()
+      .read(events)
+      .map(|AppliedGameCommand(game_command)| game_command.clone())
+      .collect()
+  }
+
+  fn tile_pos(app: &App, entity: Entity) -> GridCoords {
+    crate::util::tile_to_grid(*app.world.get::<TilePos>(entity).unwrap())
+  }
+
+  #[test]
+  fn units_move_along_a_valid_path() {
+    let (mut app, entities) =
+      board(vec![(GridCoords::new(0, 0), TurnState::Player1, unit())]);
+    let path = vec![
+      GridCoords::new(1, 0),
+      GridCoords::new(1, 1),
+      GridCoords::new(2, 1),
+    ];
+
+    apply(
+      &mut app,
+      GameCommand::MoveUnit {
+        unit: GridCoords::new(0, 0),
+        path,
+      },
+    );
+
+    assert_eq!(tile_pos(&app, entities[0]), GridCoords::new(2, 1));
+    assert!(app.world.get::<Unit>(entities[0]).unwrap().moved);
+    assert_eq!(applied(&app).len(), 1);
+  }
+
+  #[test]
+  fn invalid_paths_are_rejected() {
+    let short_legged = Unit {
+      max_move_cost: 2,
+      ..unit()
+    };
+    let (mut app, entities) = board(vec![
+      (GridCoords::new(0, 0), TurnState::Player1, short_legged),
+      (GridCoords::new(1, 1), TurnState::Player1, unit()),
+    ]);
+
+    let paths = [
+      // skips a tile
+      vec![GridCoords::new(2, 0)],
+      // walks through the other unit
+      vec![GridCoords::new(0, 1), GridCoords::new(1, 1)],
+      // costs more than the unit can move
+      vec![
+        GridCoords::new(1, 0),
+        GridCoords::new(2, 0),
+        GridCoords::new(3, 0),
+      ],
+    ];
+    for path in paths {
+      apply(
+        &mut app,
+        GameCommand::MoveUnit {
+          unit: GridCoords::new(0, 0),
+          path,
+        },
+      );
+      assert!(applied(&app).is_empty());
+    }
+
+    assert_eq!(tile_pos(&app, entities[0]), GridCoords::new(0, 0));
+    assert!(!app.world.get::<Unit>(entities[0]).unwrap().moved);
+  }
+
+  #[test]
+  fn the_inactive_player_cannot_act() {
+    let (mut app, entities) =
+      board(vec![(GridCoords::new(0, 0), TurnState::Player2, unit())]);
+
+    apply(
+      &mut app,
+      GameCommand::Wait {
+        unit: GridCoords::new(0, 0),
+      },
+    );
+
+    assert!(applied(&app).is_empty());
+    assert!(!app.world.get::<Unit>(entities[0]).unwrap().acted);
+  }
+
+  #[test]
+  fn attacks_only_land_on_enemies_in_range() {
+    let (mut app, entities) = board(vec![
+      (GridCoords::new(1, 1), TurnState::Player1, unit()),
+      (GridCoords::new(1, 2), TurnState::Player1, unit()),
+      (GridCoords::new(3, 1), TurnState::Player2, unit()),
+      (GridCoords::new(2, 1), TurnState::Player2, unit()),
+    ]);
+
+    for target in [GridCoords::new(1, 2), GridCoords::new(3, 1)] {
+      apply(
+        &mut app,
+        GameCommand::Attack {
+          unit: GridCoords::new(1, 1),
+          target,
+        },
+      );
+      assert!(applied(&app).is_empty());
+    }
+
+    apply(
+      &mut app,
+      GameCommand::Attack {
+        unit: GridCoords::new(1, 1),
+        target: GridCoords::new(2, 1),
+      },
+    );
+    let health = app.world.get::<Unit>(entities[3]).unwrap().health;
+    assert!((8..=12).contains(&(20 - health)));
+    assert_eq!(health_changes(&app), vec![health - 20]);
+    let attacker = app.world.get::<Unit>(entities[0]).unwrap();
+    assert!(attacker.moved && attacker.acted);
+  }
+
+  #[test]
+  fn units_that_have_moved_can_still_be_selected_to_attack() {
+    let moved = Unit {
+      moved: true,
+      ..unit()
+    };
+    let spent = Unit {
+      moved: true,
+      acted: true,
+      ..unit()
+    };
+    let (mut app, entities) = board(vec![
+      (GridCoords::new(1, 1), TurnState::Player1, moved),
+      (GridCoords::new(4, 4), TurnState::Player1, spent),
+      (GridCoords::new(2, 1), TurnState::Player2, unit()),
+    ]);
+
+    apply(
+      &mut app,
+      GameCommand::SelectUnit {
+        unit: GridCoords::new(4, 4),
+      },
+    );
+    assert!(applied(&app).is_empty());
+
+    apply(
+      &mut app,
+      GameCommand::SelectUnit {
+        unit: GridCoords::new(1, 1),
+      },
+    );
+    assert!(app.world.get::<Targeted>(entities[0]).is_some());
+
+    apply(
+      &mut app,
+      GameCommand::Attack {
+        unit: GridCoords::new(1, 1),
+        target: GridCoords::new(2, 1),
+      },
+    );
+    assert!(app.world.get::<Unit>(entities[0]).unwrap().acted);
+    assert!(app.world.get::<Unit>(entities[2]).unwrap().health < 20);
+  }
+
+  #[test]
+  fn defeating_the_last_enemy_ends_the_match() {
+    let frail = Unit {
+      health: 1,
+      ..unit()
+    };
+    let (mut app, entities) = board(vec![
+      (GridCoords::new(1, 1), TurnState::Player1, unit()),
+      (GridCoords::new(2, 1), TurnState::Player2, frail),
+    ]);
+
+    apply(
+      &mut app,
+      GameCommand::Attack {
+        unit: GridCoords::new(1, 1),
+        target: GridCoords::new(2, 1),
+      },
+    );
+
+    assert!(app.world.get_entity(entities[1]).is_none());
+    let events = app.world.resource::<Events<MatchOver>>();
+    let winners = events
+      .get_reader()
+      .read(events)
+      .map(|match_over| match_over.winner)
+      .collect::<Vec<_>>();
+    assert_eq!(winners, vec![TurnState::Player1]);
+  }
+
+  #[test]
+  fn ending_the_turn_readies_the_next_players_units() {
+    let spent = || Unit {
+      moved: true,
+      acted: true,
+      ..unit()
+    };
+    let (mut app, entities) = board(vec![
+      (GridCoords::new(0, 0), TurnState::Player1, spent()),
+      (GridCoords::new(5, 5), TurnState::Player2, spent()),
+    ]);
+
+    apply(&mut app, GameCommand::EndTurn);
+    app.update();
+
+    assert_eq!(
+      *app.world.resource::<State<TurnState>>().get(),
+      TurnState::Player2
+    );
+    assert!(app.world.get::<Unit>(entities[0]).unwrap().moved);
+    let readied = app.world.get::<Unit>(entities[1]).unwrap();
+    assert!(!readied.moved && !readied.acted);
+    assert_eq!(**app.world.resource::<TurnNumber>(), 1);
+  }
+}
@@ -7,31 +7,25 @@ use bevy::{
   ecs::{
     bundle::Bundle,
     component::Component,
-    entity::Entity,
-    event::EventReader,
+    event::{EventReader, EventWriter},
     query::With,
-    schedule::{NextState, State},
-    system::{Commands, Query, Res, ResMut, Resource},
+    schedule::State,
+    system::{Commands, Query, Res, ResMut},
   },
-  input::{keyboard::KeyCode, ButtonInput},
   log::info,
   math::Vec3,
   sprite::{SpriteSheetBundle, TextureAtlas},
 };
 use bevy_ecs_ldtk::{GridCoords, LdtkEntity};
-use bevy_ecs_tilemap::tiles::TileStorage;
-use std::ops::Deref;
+use bevy_ecs_tilemap::tiles::TilePos;
 
-use crate::{
-  assets::AtlasInfo,
-  tiles::{CURSOR, CURSOR_BLUE_OFFSET},
-};
+use crate::{assets::AtlasInfo, sprites::Sprites};
 
 use super::{
-  arrows::ArrowHead,
-  input::MovementInput,
-  units::{Unit, UnitAssociation, UnitAssociations},
-  GameEntity, GameState, TurnState, UnitMap,
+  commands::{CommandRequest, GameCommand},
+  input::{Action, Actions, MovementInput, PointerInput},
+  units::{Unit, UnitAssociation},
+  GameEntity, LevelSize, TurnState,
 };
 
 #[derive(Default, Component)]
@@ -52,6 +46,7 @@ pub struct CursorBundle {
 pub fn init_cursor(
   mut commands: Commands,
   atlas_info: Res<AtlasInfo>,
+  sprites: Sprites,
   mut animations: ResMut<Assets<AnimationClip>>,
 ) {
   info!("initialising cursor");
@@ -59,7 +54,7 @@ pub fn init_cursor(
   cursor_bundle.sprite_bundle.texture = atlas_info.image.clone();
   cursor_bundle.sprite_bundle.atlas = TextureAtlas {
     layout: atlas_info.layout.clone(),
-    index: CURSOR,
+    index: sprites.index("cursor"),
   };
   cursor_bundle.sprite_bundle.transform.translation.z = 25.0;
   let name = Name::new("cursor");
@@ -92,33 +87,145 @@ pub fn init_cursor(
 #[derive(Default, Component)]
 pub struct Targeted;
 
+/// The unit `turn` can still select that comes after `current`, wrapping
+/// round to the first. Units are visited in a fixed order so that repeated
+/// presses go through each of them in turn.
+fn next_ready_unit<'a>(
+  units: impl Iterator<Item = (GridCoords, TurnState, &'a Unit)>,
+  turn: TurnState,
+  current: GridCoords,
+) -> Option<GridCoords> {
+  // a unit that has moved can still be selected until it has acted
+  let mut ready_units = units
+    .filter(|(_, association, unit)| *association == turn && !unit.acted)
+    .map(|(grid_coords, _, _)| grid_coords)
+    .collect::<Vec<_>>();
+  ready_units.sort_by_key(|grid_coords| (grid_coords.x, grid_coords.y));
+
+  let current = (current.x, current.y);
+  ready_units
+    .iter()
+    .find(|grid_coords| (grid_coords.x, grid_coords.y) > current)
+    .or(ready_units.first())
+    .copied()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn move_cursor(
-  mut commands: Commands,
   mut cursor: Query<&mut GridCoords, With<Cursor>>,
-  keys: Res<ButtonInput<KeyCode>>,
-  mut movement_events: EventReader<MovementInput>,
+  actions: Res<Actions>,
   turn_state: Res<State<TurnState>>,
-  unit_associations: Res<UnitAssociations>,
-  mut next_game_state: ResMut<NextState<GameState>>,
-  units: Query<(&Unit, &UnitAssociation)>,
-  unit_storage: Query<&TileStorage, With<UnitMap>>,
+  units: Query<(&TilePos, &UnitAssociation, &Unit)>,
+  level_size: Res<LevelSize>,
+  mut movement_events: EventReader<MovementInput>,
+  mut pointer_events: EventReader<PointerInput>,
+  mut command_requests: EventWriter<CommandRequest>,
 ) {
   for movement_event in movement_events.read() {
-    *cursor.single_mut() += movement_event.as_grid_coords();
+    let mut cursor_coords = cursor.single_mut();
+    *cursor_coords =
+      level_size.clamp(*cursor_coords + movement_event.as_grid_coords());
   }
 
-  if keys.just_pressed(KeyCode::Enter) {
-    if let Some(entity) = unit_storage
-      .single()
-      .checked_get(&crate::util::grid_to_tile(*cursor.single()))
-    {
-      let (unit, association) = units.get(entity).unwrap();
-      if association.turn == *turn_state.get() && !unit.moved {
-        next_game_state.set(GameState::ArrowMovement);
-        commands.entity(entity).insert(Targeted);
-        commands.insert_resource(ArrowHead(cursor.single().clone()));
-        info!("targeting unit {:?}", entity);
+  for pointer_event in pointer_events.read() {
+    match pointer_event {
+      PointerInput::Hover(tile) => *cursor.single_mut() = *tile,
+      PointerInput::Click(tile) => {
+        *cursor.single_mut() = *tile;
+        command_requests
+          .send(CommandRequest(GameCommand::SelectUnit { unit: *tile }));
       }
+      PointerInput::Cancel => {}
+    }
+  }
+
+  let player_actions = actions.player(**turn_state);
+
+  if player_actions.just_pressed(Action::NextUnit) {
+    let mut cursor_coords = cursor.single_mut();
+    if let Some(next) = next_ready_unit(
+      units.iter().map(|(tile_pos, association, unit)| {
+        (crate::util::tile_to_grid(*tile_pos), association.turn, unit)
+      }),
+      **turn_state,
+      *cursor_coords,
+    ) {
+      *cursor_coords = next;
     }
   }
+
+  if player_actions.just_pressed(Action::Confirm) {
+    command_requests.send(CommandRequest(GameCommand::SelectUnit {
+      unit: *cursor.single(),
+    }));
+  }
+
+  if player_actions.just_pressed(Action::EndTurn) {
+    command_requests.send(CommandRequest(GameCommand::EndTurn));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::fixtures::unit;
+
+  #[test]
+  fn next_unit_skips_units_that_have_acted() {
+    let ready = unit();
+    let spent = Unit {
+      moved: true,
+      acted: true,
+      ..unit()
+    };
+    let enemy = unit();
+    let units = [
+      (GridCoords::new(0, 0), TurnState::Player1, &ready),
+      (GridCoords::new(1, 0), TurnState::Player1, &spent),
+      (GridCoords::new(2, 0), TurnState::Player2, &enemy),
+      (GridCoords::new(3, 0), TurnState::Player1, &ready),
+    ];
+    let next = |current| {
+      next_ready_unit(units.iter().copied(), TurnState::Player1, current)
+    };
+
+    assert_eq!(next(GridCoords::new(0, 0)), Some(GridCoords::new(3, 0)));
+    // wraps round to the first once past the last
+    assert_eq!(next(GridCoords::new(3, 0)), Some(GridCoords::new(0, 0)));
+  }
+
+  #[test]
+  fn next_unit_visits_units_that_moved_but_have_not_acted() {
+    let moved = Unit {
+      moved: true,
+      ..unit()
+    };
+    let units = [(GridCoords::new(2, 0), TurnState::Player1, &moved)];
+    assert_eq!(
+      next_ready_unit(
+        units.iter().copied(),
+        TurnState::Player1,
+        GridCoords::new(0, 0)
+      ),
+      Some(GridCoords::new(2, 0))
+    );
+  }
+
+  #[test]
+  fn next_unit_finds_nothing_once_every_unit_has_acted() {
+    let moved = Unit {
+      moved: true,
+      acted: true,
+      ..unit()
+    };
+    let units = [(GridCoords::new(0, 0), TurnState::Player1, &moved)];
+    assert_eq!(
+      next_ready_unit(
+        units.iter().copied(),
+        TurnState::Player1,
+        GridCoords::new(0, 0)
+      ),
+      None
+    );
+  }
 }
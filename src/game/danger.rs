@@ -0,0 +1,341 @@
+use bevy::{
+  ecs::{
+    component::Component,
+    entity::Entity,
+    query::{Changed, With, Without},
+    removal_detection::RemovedComponents,
+    schedule::State,
+    system::{Commands, Query, Res, ResMut, Resource},
+  },
+  hierarchy::DespawnRecursiveExt,
+  utils::HashSet,
+};
+use bevy_ecs_ldtk::GridCoords;
+use bevy_ecs_tilemap::{
+  map::TilemapId,
+  tiles::{TileBundle, TilePos, TileStorage, TileTextureIndex},
+};
+
+use crate::{
+  sprites::Sprites,
+  tiles::{MovementClass, TileTypes},
+};
+
+use super::{
+  arrows::{reachable_tiles, tile_move_cost},
+  cursor::Cursor,
+  input::{Action, Actions},
+  units::{Unit, UnitAssociation},
+  GameEntity, GameState, LevelSize, TurnState, UnitMap, ZoneMap,
+};
+
+/// Which enemies' threat is drawn on the zone map.
+#[derive(Resource, Default)]
+pub struct DangerZone {
+  /// Whether the threat of every enemy is shown.
+  pub enabled: bool,
+  /// Enemies whose threat is shown on its own, kept for each player so that
+  /// one player's pins never stand in for the other's. While a player has
+  /// any pinned, only theirs is shown, whether or not the whole zone is
+  /// enabled.
+  player1_pins: HashSet<Entity>,
+  player2_pins: HashSet<Entity>,
+}
+
+impl DangerZone {
+  fn pinned(&self, turn: TurnState) -> &HashSet<Entity> {
+    match turn {
+      TurnState::Player1 => &self.player1_pins,
+      TurnState::Player2 => &self.player2_pins,
+    }
+  }
+
+  fn pinned_mut(&mut self, turn: TurnState) -> &mut HashSet<Entity> {
+    match turn {
+      TurnState::Player1 => &mut self.player1_pins,
+      TurnState::Player2 => &mut self.player2_pins,
+    }
+  }
+
+  /// Pins `enemy` for `turn`'s player, or unpins it if it already was.
+  fn toggle_pin(&mut self, turn: TurnState, enemy: Entity) {
+    let pinned = self.pinned_mut(turn);
+    if !pinned.remove(&enemy) {
+      pinned.insert(enemy);
+    }
+  }
+
+  /// Whether the threat of `enemy` is drawn for `turn`'s player.
+  fn shows(&self, turn: TurnState, enemy: Entity) -> bool {
+    let pinned = self.pinned(turn);
+    if pinned.is_empty() {
+      self.enabled
+    } else {
+      pinned.contains(&enemy)
+    }
+  }
+}
+
+#[derive(Default, Component)]
+pub struct DangerTile;
+
+pub fn danger_zone_controls(
+  actions: Res<Actions>,
+  turn_state: Res<State<TurnState>>,
+  cursor: Query<&GridCoords, With<Cursor>>,
+  unit_map: Query<&TileStorage, With<UnitMap>>,
+  units: Query<&UnitAssociation, With<Unit>>,
+  mut danger_zone: ResMut<DangerZone>,
+) {
+  let player_actions = actions.player(**turn_state);
+
+  if player_actions.just_pressed(Action::DangerZone) {
+    danger_zone.enabled = !danger_zone.enabled;
+  }
+
+  if player_actions.just_pressed(Action::PinThreat) {
+    let enemy = cursor
+      .get_single()
+      .ok()
+      .zip(unit_map.get_single().ok())
+      .and_then(|(grid_coords, unit_map)| {
+        unit_map.checked_get(&crate::util::grid_to_tile(*grid_coords))
+      })
+      .filter(|unit| {
+        units
+          .get(*unit)
+          .is_ok_and(|association| association.turn != **turn_state)
+      });
+    if let Some(enemy) = enemy {
+      danger_zone.toggle_pin(**turn_state, enemy);
+    }
+  }
+}
+
+/// Every tile that `enemies` could attack next turn: anything within attack
+/// range of a tile they can move to, or of where they already stand.
+fn threatened_tiles<'a>(
+  tile_types: &TileTypes,
+  unit_positions: &TileStorage,
+  level_size: &LevelSize,
+  enemies: impl Iterator<Item = (&'a Unit, &'a TilePos)>,
+) -> HashSet<GridCoords> {
+  let mut threatened = HashSet::new();
+  for (unit, tile_pos) in enemies {
+    let start = crate::util::tile_to_grid(*tile_pos);
+    let reachable = reachable_tiles(
+      tile_types,
+      unit_positions,
+      &start,
+      unit.max_move_cost,
+      unit.movement,
+    );
+    let range = unit.attack_range as i32;
+
+    for origin in reachable.keys().chain(std::iter::once(&start)) {
+      for dx in -range..=range {
+        for dy in -range..=range {
+          let distance = dx.abs() + dy.abs();
+          if distance == 0 || distance > range {
+            continue;
+          }
+          let target = *origin + GridCoords::new(dx, dy);
+          // nothing can stand on impassable terrain to be attacked there
+          if level_size.contains(target)
+            && MovementClass::ALL.into_iter().any(|movement| {
+              tile_move_cost(tile_types, &target, movement).is_some()
+            })
+          {
+            threatened.insert(target);
+          }
+        }
+      }
+    }
+  }
+  threatened
+}
+
+fn remove_danger_tiles(
+  commands: &mut Commands,
+  danger_tiles: &Query<(Entity, &TilePos), With<DangerTile>>,
+  zone_storage: &mut TileStorage,
+) {
+  for (danger_tile, tile_pos) in danger_tiles.iter() {
+    commands.entity(danger_tile).despawn_recursive();
+    zone_storage.remove(tile_pos);
+  }
+}
+
+/// Redraws the danger zone whenever what it shows may have changed: a unit
+/// moved or fell, the turn passed, the player toggled or pinned a threat, or
+/// the level itself was reloaded.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_danger_zone(
+  mut commands: Commands,
+  mut danger_zone: ResMut<DangerZone>,
+  turn_state: Res<State<TurnState>>,
+  game_state: Res<State<GameState>>,
+  tile_types: Res<TileTypes>,
+  level_size: Res<LevelSize>,
+  units: Query<(Entity, &Unit, &TilePos, &UnitAssociation)>,
+  moved_units: Query<(), (With<Unit>, Changed<TilePos>)>,
+  mut removed_units: RemovedComponents<Unit>,
+  danger_tiles: Query<(Entity, &TilePos), With<DangerTile>>,
+  unit_map: Query<&TileStorage, (With<UnitMap>, Without<ZoneMap>)>,
+  mut zone_map: Query<(Entity, &mut TileStorage), With<ZoneMap>>,
+  sprites: Sprites,
+) {
+  let units_removed = removed_units.read().count() > 0;
+  if !(danger_zone.is_changed()
+    || turn_state.is_changed()
+    || game_state.is_changed()
+    || tile_types.is_changed()
+    || units_removed
+    || !moved_units.is_empty())
+  {
+    return;
+  }
+  let (Ok(unit_positions), Ok((zone_map, mut zone_storage))) =
+    (unit_map.get_single(), zone_map.get_single_mut())
+  else {
+    return;
+  };
+
+  if units_removed {
+    for turn in [TurnState::Player1, TurnState::Player2] {
+      danger_zone
+        .pinned_mut(turn)
+        .retain(|pinned| units.contains(*pinned));
+    }
+  }
+  remove_danger_tiles(&mut commands, &danger_tiles, &mut zone_storage);
+
+  let enemies = units
+    .iter()
+    .filter(|(entity, _, _, association)| {
+      association.turn != **turn_state
+        && danger_zone.shows(**turn_state, *entity)
+    })
+    .map(|(_, unit, tile_pos, _)| (unit, tile_pos));
+  let threatened =
+    threatened_tiles(&tile_types, unit_positions, &level_size, enemies);
+
+  for grid_coords in threatened {
+    let tile_pos = crate::util::grid_to_tile(grid_coords);
+    let tile = commands
+      .spawn((
+        TileBundle {
+          position: tile_pos,
+          texture_index: TileTextureIndex(sprites.index("zone/melee") as u32),
+          tilemap_id: TilemapId(zone_map),
+          ..Default::default()
+        },
+        DangerTile,
+        GameEntity,
+      ))
+      .id();
+    zone_storage.set(&tile_pos, tile);
+  }
+}
+
+/// Makes way on the zone map for a selected unit's movement zone.
+pub fn clear_danger_zone(
+  mut commands: Commands,
+  danger_tiles: Query<(Entity, &TilePos), With<DangerTile>>,
+  mut zone_map: Query<&mut TileStorage, With<ZoneMap>>,
+) {
+  if let Ok(mut zone_storage) = zone_map.get_single_mut() {
+    remove_danger_tiles(&mut commands, &danger_tiles, &mut zone_storage);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bevy_ecs_tilemap::map::TilemapSize;
+
+  use super::*;
+  use crate::game::fixtures::{terrain, tile_types, unit};
+
+  /// A 5×5 field of grass with mountains on `mountains`.
+  fn field(mountains: &[GridCoords]) -> (TileTypes, LevelSize) {
+    let mut tile_types = tile_types(&["....."; 5]);
+    let mountain = terrain().by_id("mountains").unwrap().value;
+    for grid_coords in mountains {
+      tile_types.set(grid_coords, mountain);
+    }
+    let level_size = LevelSize {
+      px_hei: 5 * 16,
+      px_wid: 5 * 16,
+      tile_hei: 5,
+      tile_wid: 5,
+    };
+    (tile_types, level_size)
+  }
+
+  fn enemy(max_move_cost: usize, attack_range: usize) -> Unit {
+    Unit {
+      max_move_cost,
+      attack_range,
+      ..unit()
+    }
+  }
+
+  fn threatened(
+    mountains: &[GridCoords],
+    enemies: &[(Unit, TilePos)],
+  ) -> HashSet<GridCoords> {
+    let (tile_types, level_size) = field(mountains);
+    threatened_tiles(
+      &tile_types,
+      &TileStorage::empty(TilemapSize { x: 5, y: 5 }),
+      &level_size,
+      enemies.iter().map(|(unit, tile_pos)| (unit, tile_pos)),
+    )
+  }
+
+  #[test]
+  fn threat_covers_attack_range_from_every_reachable_tile() {
+    let threatened = threatened(&[], &[(enemy(1, 1), TilePos { x: 0, y: 0 })]);
+    let expected = [(0, 0), (1, 0), (0, 1), (2, 0), (1, 1), (0, 2)]
+      .into_iter()
+      .map(|(x, y)| GridCoords::new(x, y))
+      .collect::<HashSet<_>>();
+    assert_eq!(threatened, expected);
+  }
+
+  #[test]
+  fn threat_skips_impassable_terrain_and_the_level_edge() {
+    let mountain = GridCoords::new(2, 2);
+    // a unit that cannot move still threatens around where it stands
+    let threatened =
+      threatened(&[mountain], &[(enemy(0, 2), TilePos { x: 2, y: 1 })]);
+    assert!(!threatened.contains(&mountain));
+    assert!(threatened.contains(&GridCoords::new(2, 3)));
+    assert!(threatened.contains(&GridCoords::new(4, 1)));
+    // the twelve tiles within range, less the mountain and the one below
+    // the level
+    assert_eq!(threatened.len(), 10);
+  }
+
+  #[test]
+  fn pins_belong_to_the_player_who_made_them() {
+    let mut danger_zone = DangerZone {
+      enabled: true,
+      ..Default::default()
+    };
+    let (red, other_red, blue) = (
+      Entity::from_raw(1),
+      Entity::from_raw(2),
+      Entity::from_raw(3),
+    );
+
+    danger_zone.toggle_pin(TurnState::Player1, red);
+    assert!(danger_zone.shows(TurnState::Player1, red));
+    assert!(!danger_zone.shows(TurnState::Player1, other_red));
+    // blue's pin leaves red's view of the enabled zone alone
+    assert!(danger_zone.shows(TurnState::Player2, blue));
+
+    danger_zone.toggle_pin(TurnState::Player1, red);
+    assert!(danger_zone.shows(TurnState::Player1, other_red));
+  }
+}
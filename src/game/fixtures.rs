@@ -0,0 +1,106 @@
+//! Boards and units shared by the game's unit tests.
+
+use bevy::{
+  app::{App, Update},
+  ecs::{entity::Entity, schedule::IntoSystemConfigs},
+};
+use bevy_ecs_ldtk::GridCoords;
+use bevy_ecs_tilemap::{map::TilemapSize, tiles::TileStorage};
+
+use crate::tiles::{MovementClass, TerrainRegistry, TileTypes};
+
+use super::{
+  commands::{
+    apply_game_commands, AppliedGameCommand, GameCommand, HealthChanged,
+    MatchOver,
+  },
+  rng::MatchRng,
+  units::{
+    update_unit_associations_resource, Unit, UnitAssociation, UnitAssociations,
+  },
+  GameState, TurnNumber, TurnState, UnitMap,
+};
+
+const TERRAIN: &[u8] = include_bytes!("../../assets/holmium.terrain.ron");
+
+pub fn terrain() -> TerrainRegistry {
+  TerrainRegistry::from_bytes(TERRAIN).unwrap()
+}
+
+/// Lays terrain out from `rows`, written top row first: `.` for grass, `~`
+/// for water, `f` for forest and `^` for mountains.
+pub fn tile_types(rows: &[&str]) -> TileTypes {
+  let registry = terrain();
+  let height = rows.len();
+  let mut tile_types = TileTypes::new(rows[0].len(), height, &registry);
+  for (row, cells) in rows.iter().enumerate() {
+    for (x, cell) in cells.chars().enumerate() {
+      let id = match cell {
+        '.' => "grass",
+        '~' => "water",
+        'f' => "forest",
+        '^' => "mountains",
+        _ => panic!("no terrain is drawn as {:?}", cell),
+      };
+      tile_types.set(
+        &GridCoords::new(x as i32, (height - 1 - row) as i32),
+        registry.by_id(id).unwrap().value,
+      );
+    }
+  }
+  tile_types
+}
+
+/// A fresh foot soldier at full health, adjusted per test with struct update
+/// syntax.
+pub fn unit() -> Unit {
+  Unit {
+    health: 20,
+    max_health: 20,
+    max_move_cost: 6,
+    movement: MovementClass::Foot,
+    attack_range: 1,
+    attack: 10,
+    heal: 0,
+    moved: false,
+    acted: false,
+    backdrop: Entity::PLACEHOLDER,
+  }
+}
+
+/// A 6×6 field of grass with `units` standing on it, which applies any
+/// command sent to it on the next update.
+pub fn board(units: Vec<(GridCoords, TurnState, Unit)>) -> (App, Vec<Entity>) {
+  let mut app = App::new();
+  app
+    .init_state::<TurnState>()
+    .init_state::<GameState>()
+    .add_event::<GameCommand>()
+    .add_event::<AppliedGameCommand>()
+    .add_event::<MatchOver>()
+    .add_event::<HealthChanged>()
+    .insert_resource(tile_types(&["......"; 6]))
+    .insert_resource(TurnNumber::default())
+    .insert_resource(MatchRng::new(0))
+    .init_resource::<UnitAssociations>()
+    .add_systems(
+      Update,
+      (update_unit_associations_resource, apply_game_commands).chain(),
+    );
+
+  let mut unit_storage = TileStorage::empty(TilemapSize { x: 6, y: 6 });
+  let mut entities = Vec::new();
+  for (grid_coords, turn, mut unit) in units {
+    unit.backdrop = app.world.spawn_empty().id();
+    let tile_pos = crate::util::grid_to_tile(grid_coords);
+    let entity = app
+      .world
+      .spawn((unit, tile_pos, UnitAssociation { turn }))
+      .id();
+    unit_storage.set(&tile_pos, entity);
+    entities.push(entity);
+  }
+  app.world.spawn((unit_storage, UnitMap));
+
+  (app, entities)
+}
@@ -0,0 +1,211 @@
+use bevy::{
+  ecs::{
+    component::Component,
+    entity::Entity,
+    event::EventReader,
+    query::{Added, With, Without},
+    system::{Commands, Query, Res},
+  },
+  hierarchy::{BuildChildren, Children, DespawnRecursiveExt},
+  math::{IVec2, Vec2, Vec3},
+  render::color::Color,
+  sprite::{Anchor, Sprite, SpriteBundle},
+  text::{Text, Text2dBundle, TextStyle},
+  time::Time,
+  transform::components::Transform,
+};
+use bevy_ecs_tilemap::tiles::TilePos;
+
+use crate::windows::WindowAssets;
+
+use super::{commands::HealthChanged, units::Unit, GameEntity};
+
+const BAR_WIDTH: f32 = 14.0;
+const BAR_HEIGHT: f32 = 2.0;
+/// Where a bar sits relative to the centre of its unit's tile, along the
+/// bottom edge.
+const BAR_OFFSET: Vec3 = Vec3::new(0.0, -7.0, 0.0);
+/// Just above the units' tilemap, and below the arrows'.
+const BAR_Z: f32 = 19.0;
+const NUMBER_Z: f32 = 25.0;
+const NUMBER_SECONDS: f32 = 0.9;
+/// How far a number rises over its lifetime, in pixels.
+const NUMBER_RISE: f32 = 10.0;
+
+/// Follows `unit` around the board, showing its remaining health.
+#[derive(Component)]
+pub struct HealthBar {
+  unit: Entity,
+}
+
+#[derive(Default, Component)]
+struct HealthBarFill;
+
+/// A damage or healing amount drifting up from the tile it happened on.
+#[derive(Component)]
+pub struct FloatingNumber {
+  elapsed: f32,
+  color: Color,
+}
+
+/// The centre of the tile at `tile_pos`, matching where the unit tilemap
+/// draws it.
+fn tile_centre(tile_pos: TilePos) -> Vec3 {
+  bevy_ecs_ldtk::utils::grid_coords_to_translation(
+    crate::util::tile_to_grid(tile_pos),
+    IVec2::splat(16),
+  )
+  .extend(0.0)
+}
+
+fn bar_color(fraction: f32) -> Color {
+  if fraction > 0.5 {
+    Color::rgb_u8(56, 183, 100)
+  } else if fraction > 0.25 {
+    Color::rgb_u8(255, 205, 117)
+  } else {
+    Color::rgb_u8(177, 62, 83)
+  }
+}
+
+pub fn spawn_health_bars(
+  mut commands: Commands,
+  units: Query<(Entity, &TilePos), Added<Unit>>,
+) {
+  for (unit, tile_pos) in units.iter() {
+    commands
+      .spawn((
+        SpriteBundle {
+          sprite: Sprite {
+            color: Color::rgba(0.0, 0.0, 0.0, 0.8),
+            custom_size: Some(Vec2::new(BAR_WIDTH + 2.0, BAR_HEIGHT + 2.0)),
+            ..Default::default()
+          },
+          transform: Transform::from_translation(
+            tile_centre(*tile_pos) + BAR_OFFSET + Vec3::Z * BAR_Z,
+          ),
+          ..Default::default()
+        },
+        HealthBar { unit },
+        GameEntity,
+      ))
+      .with_children(|bar| {
+        bar.spawn((
+          SpriteBundle {
+            sprite: Sprite {
+              custom_size: Some(Vec2::new(BAR_WIDTH, BAR_HEIGHT)),
+              anchor: Anchor::CenterLeft,
+              ..Default::default()
+            },
+            transform: Transform::from_xyz(-BAR_WIDTH / 2.0, 0.0, 0.1),
+            ..Default::default()
+          },
+          HealthBarFill,
+        ));
+      });
+  }
+}
+
+/// Keeps every bar under its unit and sized to its health, and despawns the
+/// bars of defeated units.
+pub fn update_health_bars(
+  mut commands: Commands,
+  units: Query<(&Unit, &TilePos)>,
+  mut bars: Query<
+    (Entity, &HealthBar, &Children, &mut Transform),
+    Without<HealthBarFill>,
+  >,
+  mut fills: Query<&mut Sprite, With<HealthBarFill>>,
+) {
+  for (bar, health_bar, children, mut transform) in bars.iter_mut() {
+    let Ok((unit, tile_pos)) = units.get(health_bar.unit) else {
+      commands.entity(bar).despawn_recursive();
+      continue;
+    };
+
+    let translation = tile_centre(*tile_pos) + BAR_OFFSET + Vec3::Z * BAR_Z;
+    if transform.translation != translation {
+      transform.translation = translation;
+    }
+
+    let fraction =
+      (unit.health as f32 / unit.max_health as f32).clamp(0.0, 1.0);
+    for child in children.iter() {
+      let Ok(mut fill) = fills.get_mut(*child) else {
+        continue;
+      };
+      let size = Some(Vec2::new(BAR_WIDTH * fraction, BAR_HEIGHT));
+      let color = bar_color(fraction);
+      if fill.custom_size != size || fill.color != color {
+        fill.custom_size = size;
+        fill.color = color;
+      }
+    }
+  }
+}
+
+pub fn show_health_changes(
+  mut commands: Commands,
+  mut health_changed_events: EventReader<HealthChanged>,
+  window_assets: Res<WindowAssets>,
+) {
+  for health_changed in health_changed_events.read() {
+    let (value, color) = if health_changed.amount >= 0 {
+      (
+        format!("+{}", health_changed.amount),
+        Color::rgb_u8(56, 183, 100),
+      )
+    } else {
+      (
+        health_changed.amount.to_string(),
+        Color::rgb_u8(247, 243, 183),
+      )
+    };
+
+    let position = bevy_ecs_ldtk::utils::grid_coords_to_translation(
+      health_changed.grid_coords,
+      IVec2::splat(16),
+    );
+    commands.spawn((
+      Text2dBundle {
+        text: Text::from_section(
+          value,
+          TextStyle {
+            font: window_assets.font.clone(),
+            font_size: 12.0,
+            color,
+          },
+        ),
+        transform: Transform::from_translation(position.extend(NUMBER_Z)),
+        ..Default::default()
+      },
+      FloatingNumber {
+        elapsed: 0.0,
+        color,
+      },
+      GameEntity,
+    ));
+  }
+}
+
+/// Lifts each number off its tile while fading it out, then despawns it.
+pub fn animate_floating_numbers(
+  mut commands: Commands,
+  time: Res<Time>,
+  mut numbers: Query<(Entity, &mut FloatingNumber, &mut Transform, &mut Text)>,
+) {
+  for (entity, mut number, mut transform, mut text) in numbers.iter_mut() {
+    let delta = time.delta_seconds();
+    number.elapsed += delta;
+    if number.elapsed >= NUMBER_SECONDS {
+      commands.entity(entity).despawn_recursive();
+      continue;
+    }
+
+    let progress = number.elapsed / NUMBER_SECONDS;
+    transform.translation.y += NUMBER_RISE / NUMBER_SECONDS * delta;
+    for section in text.sections.iter_mut() {
+      section.style.color = number.color.with_a(1.0 - progress * progress);
+    }
+  }
+}
@@ -0,0 +1,123 @@
+use bevy::{
+  ecs::{
+    component::Component,
+    query::With,
+    system::{Commands, Query, Res},
+  },
+  text::Text,
+  ui::{PositionType, Style, Val},
+};
+use bevy_ecs_ldtk::GridCoords;
+use bevy_ecs_tilemap::tiles::TileStorage;
+
+use crate::{
+  tiles::{MovementClass, TileTypes},
+  windows::{spawn_window, text, WindowAssets},
+};
+
+use super::{
+  cursor::Cursor,
+  unit_defs::UnitRoster,
+  units::{Unit, UnitAssociation, UnitKind},
+  GameEntity, UnitMap,
+};
+
+#[derive(Default, Component)]
+pub struct InfoPanel;
+
+#[derive(Default, Component)]
+struct InfoPanelText;
+
+pub fn spawn_info_panel(
+  mut commands: Commands,
+  window_assets: Res<WindowAssets>,
+) {
+  spawn_window(
+    &mut commands,
+    &window_assets,
+    Style {
+      position_type: PositionType::Absolute,
+      right: Val::Px(16.0),
+      top: Val::Px(16.0),
+      min_width: Val::Px(180.0),
+      ..Default::default()
+    },
+    (InfoPanel, GameEntity),
+    |contents| {
+      contents.spawn((text(&window_assets, "", 20.0), InfoPanelText));
+    },
+  );
+}
+
+/// Describes the terrain under the cursor, and the unit standing on it if
+/// there is one.
+pub fn update_info_panel(
+  cursor: Query<&GridCoords, With<Cursor>>,
+  tile_types: Option<Res<TileTypes>>,
+  unit_map: Query<&TileStorage, With<UnitMap>>,
+  unit_roster: UnitRoster,
+  units: Query<(&Unit, &UnitKind, &UnitAssociation)>,
+  mut panel_text: Query<&mut Text, With<InfoPanelText>>,
+) {
+  let (Ok(grid_coords), Some(tile_types), Ok(mut panel_text)) =
+    (cursor.get_single(), tile_types, panel_text.get_single_mut())
+  else {
+    return;
+  };
+
+  let unit = unit_map
+    .get_single()
+    .ok()
+    .and_then(|unit_map| {
+      unit_map.checked_get(&crate::util::grid_to_tile(*grid_coords))
+    })
+    .and_then(|entity| units.get(entity).ok());
+  // the cost is the one for the unit standing here, if there is one
+  let movement = unit.map_or(MovementClass::Foot, |(unit, _, _)| unit.movement);
+
+  let mut lines = Vec::new();
+  match tile_types.terrain_at(grid_coords) {
+    Some(terrain) => {
+      lines.push(terrain.name.clone());
+      lines.push(match terrain.move_cost(movement) {
+        Some(cost) => format!("Move cost: {}", cost),
+        None => "Impassable".to_string(),
+      });
+      lines.push(format!("Defence: {}", terrain.defence));
+      if terrain.blocks_sight {
+        lines.push("Blocks sight".to_string());
+      }
+      if terrain.sight_bonus != 0 {
+        lines.push(format!("Sight: {:+}", terrain.sight_bonus));
+      }
+    }
+    None => lines.push("Unknown terrain".to_string()),
+  }
+
+  if let Some((unit, unit_type, association)) = unit {
+    lines.push(String::new());
+    lines.push(format!(
+      "{} {}",
+      association.turn.faction_name(),
+      unit_roster
+        .get(unit_type)
+        .map_or(unit_type.0.as_str(), |definition| definition.name.as_str())
+    ));
+    lines.push(format!("Health: {}", unit.health));
+    lines.push(format!("Move: {}", unit.max_move_cost));
+    lines.push(
+      if unit.moved {
+        "Has moved"
+      } else {
+        "Ready to move"
+      }
+      .to_string(),
+    );
+  }
+
+  let value = lines.join("\n");
+  // only touch the text when it changes, so it is not laid out every frame
+  if panel_text.sections[0].value != value {
+    panel_text.sections[0].value = value;
+  }
+}
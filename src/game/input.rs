@@ -1,15 +1,528 @@
 use bevy::{
   ecs::{
-    event::{Event, EventWriter},
+    change_detection::Ref,
+    event::{Event, EventReader, EventWriter},
+    query::With,
     schedule::State,
-    system::Res,
+    system::{Commands, Local, Query, Res, ResMut, Resource},
   },
-  input::{keyboard::KeyCode, ButtonInput},
-  math::Vec2,
+  input::{
+    gamepad::{
+      Gamepad, GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType,
+      GamepadConnectionEvent,
+    },
+    keyboard::KeyCode,
+    mouse::MouseButton,
+    Axis, ButtonInput,
+  },
+  log::{error, info},
+  math::{IVec2, Vec2},
+  render::camera::Camera,
+  time::Time,
+  transform::components::GlobalTransform,
+  utils::{HashMap, HashSet},
+  window::CursorMoved,
 };
 use bevy_ecs_ldtk::GridCoords;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use super::{cursor::Cursor, GlobalCamera, LevelSize, TurnState};
+
+const KEY_BINDINGS_PATH: &str = "config/bindings.ron";
+const GAMEPAD_BINDINGS_PATH: &str = "config/gamepad.ron";
+
+/// Everything a player can ask of the game, independent of the key pressed
+/// to ask for it.
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub enum Action {
+  Up,
+  Down,
+  Left,
+  Right,
+  Confirm,
+  Cancel,
+  EndTurn,
+  NextUnit,
+  QuickSave,
+  QuickLoad,
+  ZoomIn,
+  ZoomOut,
+  Pause,
+  DangerZone,
+  PinThreat,
+}
+
+impl Action {
+  pub const ALL: [Action; 15] = [
+    Action::Up,
+    Action::Down,
+    Action::Left,
+    Action::Right,
+    Action::Confirm,
+    Action::Cancel,
+    Action::EndTurn,
+    Action::NextUnit,
+    Action::QuickSave,
+    Action::QuickLoad,
+    Action::ZoomIn,
+    Action::ZoomOut,
+    Action::Pause,
+    Action::DangerZone,
+    Action::PinThreat,
+  ];
+}
+
+/// The keys bound to each action for one player.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BindingSet(BTreeMap<Action, Vec<KeyCode>>);
+
+impl BindingSet {
+  fn new(bindings: [(Action, Vec<KeyCode>); 15]) -> Self {
+    BindingSet(bindings.into_iter().collect())
+  }
+
+  pub fn keys(&self, action: Action) -> &[KeyCode] {
+    self.0.get(&action).map_or(&[], |keys| keys.as_slice())
+  }
+
+  /// Names the keys bound to `action`, for use in prompts.
+  pub fn describe(&self, action: Action) -> String {
+    let keys = self.keys(action);
+    if keys.is_empty() {
+      return "(unbound)".to_string();
+    }
+    keys
+      .iter()
+      .map(|key| format!("{:?}", key))
+      .collect::<Vec<_>>()
+      .join("/")
+  }
+
+  /// Gives any action the file does not mention its default keys, so that a
+  /// config written by an older version keeps working.
+  fn fill_missing(&mut self, defaults: &BindingSet) {
+    for (action, keys) in defaults.0.iter() {
+      self.0.entry(*action).or_insert_with(|| keys.clone());
+    }
+  }
+}
 
-use super::TurnState;
+/// How a held direction keeps moving the cursor or arrow, for keys and
+/// gamepads alike.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AutoRepeat {
+  /// Seconds a direction has to be held before it starts repeating.
+  pub delay: f32,
+  /// Steps per second once it is repeating.
+  pub rate: f32,
+}
+
+impl Default for AutoRepeat {
+  fn default() -> Self {
+    AutoRepeat {
+      delay: 0.3,
+      rate: 12.0,
+    }
+  }
+}
+
+impl AutoRepeat {
+  /// Whether holding a direction from `held_for` to `held_for + delta`
+  /// seconds crosses into another repeat.
+  fn repeats(&self, held_for: f32, delta: f32) -> bool {
+    let steps = |time: f32| ((time - self.delay) * self.rate).floor();
+    held_for + delta >= self.delay
+      && (held_for < self.delay || steps(held_for + delta) > steps(held_for))
+  }
+}
+
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct KeyBindings {
+  pub player1: BindingSet,
+  pub player2: BindingSet,
+  #[serde(default)]
+  pub auto_repeat: AutoRepeat,
+}
+
+impl Default for KeyBindings {
+  fn default() -> Self {
+    use KeyCode::*;
+
+    KeyBindings {
+      player1: BindingSet::new([
+        (Action::Up, vec![KeyW, ArrowUp]),
+        (Action::Down, vec![KeyS, ArrowDown]),
+        (Action::Left, vec![KeyA, ArrowLeft]),
+        (Action::Right, vec![KeyD, ArrowRight]),
+        (Action::Confirm, vec![Enter]),
+        (Action::Cancel, vec![Escape]),
+        (Action::EndTurn, vec![Space]),
+        (Action::NextUnit, vec![Tab]),
+        (Action::QuickSave, vec![F5]),
+        (Action::QuickLoad, vec![F9]),
+        (Action::ZoomIn, vec![Equal]),
+        (Action::ZoomOut, vec![Minus]),
+        (Action::Pause, vec![KeyP, Pause]),
+        (Action::DangerZone, vec![KeyQ]),
+        (Action::PinThreat, vec![KeyE]),
+      ]),
+      player2: BindingSet::new([
+        (Action::Up, vec![KeyI]),
+        (Action::Down, vec![KeyK]),
+        (Action::Left, vec![KeyJ]),
+        (Action::Right, vec![KeyL]),
+        (Action::Confirm, vec![Enter]),
+        (Action::Cancel, vec![Escape]),
+        (Action::EndTurn, vec![Space]),
+        (Action::NextUnit, vec![Tab]),
+        (Action::QuickSave, vec![F5]),
+        (Action::QuickLoad, vec![F9]),
+        (Action::ZoomIn, vec![Equal]),
+        (Action::ZoomOut, vec![Minus]),
+        (Action::Pause, vec![KeyP, Pause]),
+        (Action::DangerZone, vec![KeyU]),
+        (Action::PinThreat, vec![KeyO]),
+      ]),
+      auto_repeat: AutoRepeat::default(),
+    }
+  }
+}
+
+impl KeyBindings {
+  pub fn load(path: &Path) -> Result<KeyBindings, String> {
+    let contents = std::fs::read_to_string(path)
+      .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let mut key_bindings: KeyBindings = ron::from_str(&contents)
+      .map_err(|err| format!("could not parse {}: {}", path.display(), err))?;
+
+    let defaults = KeyBindings::default();
+    key_bindings.player1.fill_missing(&defaults.player1);
+    key_bindings.player2.fill_missing(&defaults.player2);
+    Ok(key_bindings)
+  }
+
+  pub fn write(&self, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent).map_err(|err| {
+        format!("could not create {}: {}", parent.display(), err)
+      })?;
+    }
+    let contents =
+      ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+        .map_err(|err| format!("could not serialise key bindings: {}", err))?;
+    std::fs::write(path, contents)
+      .map_err(|err| format!("could not write {}: {}", path.display(), err))
+  }
+
+  pub fn for_player(&self, turn: TurnState) -> &BindingSet {
+    match turn {
+      TurnState::Player1 => &self.player1,
+      TurnState::Player2 => &self.player2,
+    }
+  }
+}
+
+/// The buttons bound to each action, shared by every gamepad.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadBindings {
+  pub buttons: BTreeMap<Action, Vec<GamepadButtonType>>,
+  /// How far the left stick has to be pushed before it counts as a press.
+  pub stick_deadzone: f32,
+  /// Hands the gamepad it is pressed on over to the other faction.
+  pub switch_faction: GamepadButtonType,
+}
+
+impl Default for GamepadBindings {
+  fn default() -> Self {
+    use GamepadButtonType::*;
+
+    GamepadBindings {
+      buttons: [
+        (Action::Up, vec![DPadUp]),
+        (Action::Down, vec![DPadDown]),
+        (Action::Left, vec![DPadLeft]),
+        (Action::Right, vec![DPadRight]),
+        (Action::Confirm, vec![South]),
+        (Action::Cancel, vec![East]),
+        (Action::EndTurn, vec![Start]),
+        (Action::NextUnit, vec![RightTrigger]),
+        (Action::ZoomIn, vec![RightTrigger2]),
+        (Action::ZoomOut, vec![LeftTrigger2]),
+        (Action::Pause, vec![Mode]),
+        (Action::DangerZone, vec![North]),
+        (Action::PinThreat, vec![West]),
+      ]
+      .into_iter()
+      .collect(),
+      stick_deadzone: 0.5,
+      switch_faction: Select,
+    }
+  }
+}
+
+impl GamepadBindings {
+  pub fn load(path: &Path) -> Result<GamepadBindings, String> {
+    let contents = std::fs::read_to_string(path)
+      .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    ron::from_str(&contents)
+      .map_err(|err| format!("could not parse {}: {}", path.display(), err))
+  }
+
+  pub fn write(&self, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent).map_err(|err| {
+        format!("could not create {}: {}", parent.display(), err)
+      })?;
+    }
+    let contents =
+      ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+        .map_err(|err| {
+          format!("could not serialise gamepad bindings: {}", err)
+        })?;
+    std::fs::write(path, contents)
+      .map_err(|err| format!("could not write {}: {}", path.display(), err))
+  }
+
+  fn buttons(&self, action: Action) -> &[GamepadButtonType] {
+    self
+      .buttons
+      .get(&action)
+      .map_or(&[], |buttons| buttons.as_slice())
+  }
+}
+
+/// Which faction each connected gamepad plays for.
+#[derive(Resource, Debug, Default)]
+pub struct GamepadAssignments(HashMap<Gamepad, TurnState>);
+
+impl GamepadAssignments {
+  fn assign(&mut self, gamepad: Gamepad, turn: TurnState) {
+    info!("gamepad {} now plays as {:?}", gamepad.id, turn);
+    self.0.insert(gamepad, turn);
+  }
+}
+
+/// The actions one player is holding and has just pressed this frame.
+#[derive(Debug, Clone, Default)]
+pub struct PlayerActions {
+  pressed: HashSet<Action>,
+  just_pressed: HashSet<Action>,
+}
+
+impl PlayerActions {
+  pub fn pressed(&self, action: Action) -> bool {
+    self.pressed.contains(&action)
+  }
+
+  pub fn just_pressed(&self, action: Action) -> bool {
+    self.just_pressed.contains(&action)
+  }
+}
+
+/// This frame's input, translated through [`KeyBindings`]. Game systems read
+/// this rather than the keyboard.
+#[derive(Resource, Debug, Default)]
+pub struct Actions {
+  player1: PlayerActions,
+  player2: PlayerActions,
+}
+
+impl Actions {
+  pub fn player(&self, turn: TurnState) -> &PlayerActions {
+    match turn {
+      TurnState::Player1 => &self.player1,
+      TurnState::Player2 => &self.player2,
+    }
+  }
+
+  fn player_mut(&mut self, turn: TurnState) -> &mut PlayerActions {
+    match turn {
+      TurnState::Player1 => &mut self.player1,
+      TurnState::Player2 => &mut self.player2,
+    }
+  }
+
+  /// For controls that belong to neither side, such as the replay viewer.
+  pub fn any_just_pressed(&self, action: Action) -> bool {
+    self.player1.just_pressed(action) || self.player2.just_pressed(action)
+  }
+}
+
+/// Loads the key bindings, writing out the defaults on first run so that
+/// there is a file to edit.
+pub fn load_key_bindings(mut commands: Commands) {
+  let path = Path::new(KEY_BINDINGS_PATH);
+
+  let key_bindings = if path.exists() {
+    KeyBindings::load(path).unwrap_or_else(|err| {
+      error!("{}; using the default key bindings", err);
+      KeyBindings::default()
+    })
+  } else {
+    let key_bindings = KeyBindings::default();
+    match key_bindings.write(path) {
+      Ok(()) => info!("wrote default key bindings to {}", path.display()),
+      Err(err) => error!("{}", err),
+    }
+    key_bindings
+  };
+
+  commands.insert_resource(key_bindings);
+}
+
+pub fn load_gamepad_bindings(mut commands: Commands) {
+  let path = Path::new(GAMEPAD_BINDINGS_PATH);
+
+  let gamepad_bindings = if path.exists() {
+    GamepadBindings::load(path).unwrap_or_else(|err| {
+      error!("{}; using the default gamepad bindings", err);
+      GamepadBindings::default()
+    })
+  } else {
+    let gamepad_bindings = GamepadBindings::default();
+    match gamepad_bindings.write(path) {
+      Ok(()) => info!("wrote default gamepad bindings to {}", path.display()),
+      Err(err) => error!("{}", err),
+    }
+    gamepad_bindings
+  };
+
+  commands.insert_resource(gamepad_bindings);
+}
+
+/// Gives each newly connected gamepad to whichever faction has fewer of
+/// them, and lets a gamepad be handed to the other faction by pressing its
+/// switch button.
+pub fn assign_gamepads(
+  mut connection_events: EventReader<GamepadConnectionEvent>,
+  buttons: Res<ButtonInput<GamepadButton>>,
+  gamepad_bindings: Res<GamepadBindings>,
+  mut assignments: ResMut<GamepadAssignments>,
+) {
+  for connection_event in connection_events.read() {
+    let gamepad = connection_event.gamepad;
+    if connection_event.connected() {
+      let player1_count = assignments
+        .0
+        .values()
+        .filter(|turn| **turn == TurnState::Player1)
+        .count();
+      let turn = if player1_count * 2 <= assignments.0.len() {
+        TurnState::Player1
+      } else {
+        TurnState::Player2
+      };
+      assignments.assign(gamepad, turn);
+    } else {
+      info!("gamepad {} disconnected", gamepad.id);
+      assignments.0.remove(&gamepad);
+    }
+  }
+
+  let switching = assignments
+    .0
+    .iter()
+    .filter(|(gamepad, _)| {
+      buttons.just_pressed(GamepadButton::new(
+        **gamepad,
+        gamepad_bindings.switch_faction,
+      ))
+    })
+    .map(|(gamepad, turn)| (*gamepad, turn.next()))
+    .collect::<Vec<_>>();
+  for (gamepad, turn) in switching {
+    assignments.assign(gamepad, turn);
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn read_actions(
+  keys: Res<ButtonInput<KeyCode>>,
+  key_bindings: Res<KeyBindings>,
+  gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+  gamepad_axes: Res<Axis<GamepadAxis>>,
+  gamepad_bindings: Res<GamepadBindings>,
+  assignments: Res<GamepadAssignments>,
+  mut stick_directions: Local<HashMap<Gamepad, HashSet<Action>>>,
+  mut actions: ResMut<Actions>,
+) {
+  for turn in [TurnState::Player1, TurnState::Player2] {
+    let binding_set = key_bindings.for_player(turn);
+    let player_actions = actions.player_mut(turn);
+    player_actions.pressed.clear();
+    player_actions.just_pressed.clear();
+
+    for action in Action::ALL {
+      let bound_keys = binding_set.keys(action);
+      if keys.any_pressed(bound_keys.iter().copied()) {
+        player_actions.pressed.insert(action);
+      }
+      if keys.any_just_pressed(bound_keys.iter().copied()) {
+        player_actions.just_pressed.insert(action);
+      }
+    }
+  }
+
+  stick_directions.retain(|gamepad, _| assignments.0.contains_key(gamepad));
+  for (gamepad, turn) in assignments.0.iter() {
+    let player_actions = actions.player_mut(*turn);
+
+    for action in Action::ALL {
+      let bound_buttons = gamepad_bindings
+        .buttons(action)
+        .iter()
+        .map(|button_type| GamepadButton::new(*gamepad, *button_type));
+      if gamepad_buttons.any_pressed(bound_buttons.clone()) {
+        player_actions.pressed.insert(action);
+      }
+      if gamepad_buttons.any_just_pressed(bound_buttons) {
+        player_actions.just_pressed.insert(action);
+      }
+    }
+
+    // the stick acts like a second d-pad, pressing a direction only as it
+    // crosses the deadzone
+    let stick = |axis_type| {
+      gamepad_axes
+        .get(GamepadAxis::new(*gamepad, axis_type))
+        .unwrap_or_default()
+    };
+    let (x, y) = (
+      stick(GamepadAxisType::LeftStickX),
+      stick(GamepadAxisType::LeftStickY),
+    );
+    let deadzone = gamepad_bindings.stick_deadzone;
+    let held = [
+      (Action::Up, y > deadzone),
+      (Action::Down, y < -deadzone),
+      (Action::Left, x < -deadzone),
+      (Action::Right, x > deadzone),
+    ]
+    .into_iter()
+    .filter_map(|(action, held)| held.then_some(action))
+    .collect::<HashSet<_>>();
+
+    let previously_held = stick_directions.entry(*gamepad).or_default();
+    for action in held.difference(previously_held) {
+      player_actions.just_pressed.insert(*action);
+    }
+    player_actions.pressed.extend(held.iter().copied());
+    *previously_held = held;
+  }
+}
 
 #[derive(Event)]
 pub struct MovementInput {
@@ -35,41 +548,158 @@ impl MovementInput {
   }
 }
 
+/// Sends a step for every direction pressed this frame, and keeps sending
+/// them while the direction is held, as configured by [`AutoRepeat`].
 pub fn movement_events(
-  keys: Res<ButtonInput<KeyCode>>,
+  actions: Res<Actions>,
+  key_bindings: Res<KeyBindings>,
+  time: Res<Time>,
   turn_state: Res<State<TurnState>>,
+  mut held_for: Local<HashMap<(TurnState, Action), f32>>,
   mut movement_events: EventWriter<MovementInput>,
 ) {
-  match **turn_state {
-    TurnState::Player1 => {
-      if keys.any_just_pressed([
-        KeyCode::KeyW,
-        KeyCode::KeyA,
-        KeyCode::KeyS,
-        KeyCode::KeyD,
-      ]) {
-        movement_events.send(MovementInput {
-          up: keys.just_pressed(KeyCode::KeyW),
-          down: keys.just_pressed(KeyCode::KeyS),
-          left: keys.just_pressed(KeyCode::KeyA),
-          right: keys.just_pressed(KeyCode::KeyD),
-        });
-      }
+  let turn = **turn_state;
+  let player_actions = actions.player(turn);
+  let delta = time.delta_seconds();
+
+  // the other player's held directions are forgotten, so that a key still
+  // down when the turn passes does not start out already repeating
+  held_for.retain(|(held_turn, _), _| *held_turn == turn);
+
+  let mut step = |action: Action| {
+    if player_actions.just_pressed(action) {
+      held_for.insert((turn, action), 0.0);
+      true
+    } else if player_actions.pressed(action) {
+      let held_for = held_for.entry((turn, action)).or_default();
+      let repeats = key_bindings.auto_repeat.repeats(*held_for, delta);
+      *held_for += delta;
+      repeats
+    } else {
+      held_for.remove(&(turn, action));
+      false
     }
-    TurnState::Player2 => {
-      if keys.any_pressed([
-        KeyCode::KeyI,
-        KeyCode::KeyJ,
-        KeyCode::KeyK,
-        KeyCode::KeyL,
-      ]) {
-        movement_events.send(MovementInput {
-          up: keys.just_pressed(KeyCode::KeyI),
-          down: keys.just_pressed(KeyCode::KeyK),
-          left: keys.just_pressed(KeyCode::KeyJ),
-          right: keys.just_pressed(KeyCode::KeyL),
-        });
+  };
+
+  let movement_input = MovementInput {
+    up: step(Action::Up),
+    down: step(Action::Down),
+    left: step(Action::Left),
+    right: step(Action::Right),
+  };
+
+  if movement_input.up
+    || movement_input.down
+    || movement_input.left
+    || movement_input.right
+  {
+    movement_events.send(movement_input);
+  }
+}
+
+/// Mouse input, already converted from window space to the tile under the
+/// pointer.
+#[derive(Event, Debug, Clone, Copy)]
+pub enum PointerInput {
+  /// The pointer has moved onto a different tile.
+  Hover(GridCoords),
+  Click(GridCoords),
+  Cancel,
+}
+
+/// Where the pointer was last seen in the window, and the tile under it.
+#[derive(Default)]
+pub struct PointerState {
+  position: Option<Vec2>,
+  hovered: Option<GridCoords>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn pointer_events(
+  mut cursor_moved_events: EventReader<CursorMoved>,
+  mouse_buttons: Res<ButtonInput<MouseButton>>,
+  global_camera: Res<GlobalCamera>,
+  cameras: Query<(&Camera, Ref<GlobalTransform>)>,
+  level_size: Res<LevelSize>,
+  cursor: Query<&GridCoords, With<Cursor>>,
+  mut pointer: Local<PointerState>,
+  mut pointer_events: EventWriter<PointerInput>,
+) {
+  let mut cursor_moved = false;
+  if let Some(event) = cursor_moved_events.read().last() {
+    pointer.position = Some(event.position);
+    cursor_moved = true;
+  }
+  if let (Some(position), Ok((camera, camera_transform))) =
+    (pointer.position, cameras.get(**global_camera))
+  {
+    // the tile under a still pointer changes too as the camera scrolls or
+    // follows the cursor
+    if cursor_moved || camera_transform.is_changed() {
+      let tile = camera
+        .viewport_to_world_2d(&camera_transform, position)
+        .map(|translation| {
+          bevy_ecs_ldtk::utils::translation_to_grid_coords(
+            translation,
+            IVec2::splat(16),
+          )
+        })
+        .filter(|grid_coords| level_size.contains(*grid_coords));
+
+      if tile != pointer.hovered {
+        // the camera only takes the cursor along when the pointer is what
+        // last placed it, so that it never snatches the cursor back from the
+        // keyboard
+        let pointer_placed_cursor = cursor
+          .get_single()
+          .is_ok_and(|cursor| Some(*cursor) == pointer.hovered);
+        pointer.hovered = tile;
+        if let Some(tile) =
+          tile.filter(|_| cursor_moved || pointer_placed_cursor)
+        {
+          pointer_events.send(PointerInput::Hover(tile));
+        }
       }
     }
   }
+
+  if mouse_buttons.just_pressed(MouseButton::Left) {
+    if let Some(tile) = pointer.hovered {
+      pointer_events.send(PointerInput::Click(tile));
+    }
+  }
+  if mouse_buttons.just_pressed(MouseButton::Right) {
+    pointer_events.send(PointerInput::Cancel);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const AUTO_REPEAT: AutoRepeat = AutoRepeat {
+    delay: 0.25,
+    rate: 4.0,
+  };
+
+  #[test]
+  fn nothing_repeats_before_the_delay() {
+    assert!(!AUTO_REPEAT.repeats(0.0, 0.1));
+    assert!(!AUTO_REPEAT.repeats(0.1, 0.1));
+  }
+
+  #[test]
+  fn reaching_the_delay_repeats_once() {
+    assert!(AUTO_REPEAT.repeats(0.2, 0.05));
+    assert!(AUTO_REPEAT.repeats(0.2, 0.1));
+  }
+
+  #[test]
+  fn repeats_at_the_rate_once_past_the_delay() {
+    // steps fall at 0.25, 0.5, 0.75, ...
+    assert!(!AUTO_REPEAT.repeats(0.3, 0.1));
+    assert!(AUTO_REPEAT.repeats(0.45, 0.1));
+    assert!(!AUTO_REPEAT.repeats(0.5, 0.1));
+    assert!(AUTO_REPEAT.repeats(0.7, 0.1));
+  }
 }
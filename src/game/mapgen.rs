@@ -0,0 +1,497 @@
+use bevy::ecs::system::Resource;
+use bevy_ecs_ldtk::GridCoords;
+use bevy_ecs_tilemap::{map::TilemapSize, tiles::TileStorage};
+use serde::{Deserialize, Serialize};
+
+use crate::tiles::{MovementClass, TerrainRegistry, TileTypes};
+
+use super::{
+  arrows::reachable_tiles, rng::MatchRng, units::UnitSpawnQueues, TurnState,
+};
+
+/// The smallest level the generator will build, leaving room for both sides'
+/// spawns with some ground between them.
+const MIN_WIDTH: usize = 12;
+const MIN_HEIGHT: usize = 8;
+
+/// The size of a generated level, in tiles. Along with the match's seed, this
+/// is everything needed to build the same level again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeneratedMap {
+  pub width: usize,
+  pub height: usize,
+}
+
+/// The generated levels offered alongside the project's own.
+pub const GENERATED_SIZES: [GeneratedMap; 3] = [
+  GeneratedMap {
+    width: 16,
+    height: 16,
+  },
+  GeneratedMap {
+    width: 32,
+    height: 16,
+  },
+  GeneratedMap {
+    width: 32,
+    height: 32,
+  },
+];
+
+impl GeneratedMap {
+  pub fn name(&self) -> String {
+    format!("Generated {}x{}", self.width, self.height)
+  }
+}
+
+impl std::str::FromStr for GeneratedMap {
+  type Err = String;
+
+  /// Parses a size written as `WIDTHxHEIGHT`, such as `32x16`.
+  fn from_str(size: &str) -> Result<Self, Self::Err> {
+    let (width, height) = size
+      .split_once('x')
+      .ok_or_else(|| format!("{} is not a size such as 32x16", size))?;
+    let map = GeneratedMap {
+      width: width
+        .parse()
+        .map_err(|err| format!("bad width {}: {}", width, err))?,
+      height: height
+        .parse()
+        .map_err(|err| format!("bad height {}: {}", height, err))?,
+    };
+    if map.width < MIN_WIDTH || map.height < MIN_HEIGHT {
+      return Err(format!(
+        "generated levels must be at least {}x{}",
+        MIN_WIDTH, MIN_HEIGHT
+      ));
+    }
+    Ok(map)
+  }
+}
+
+/// Where a generated level places each side's units. Filled from the spawn
+/// queues just like the spawn locations of a level from the project.
+#[derive(Resource, Debug, Clone)]
+pub struct GeneratedSpawns(pub Vec<(TurnState, GridCoords)>);
+
+pub struct GeneratedLevel {
+  pub tile_types: TileTypes,
+  pub spawns: GeneratedSpawns,
+}
+
+/// The terrain the generator paints with, as IntGrid values looked up in the
+/// terrain registry by id.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+  ground: i32,
+  water: i32,
+  mountains: i32,
+  forest: i32,
+}
+
+impl Palette {
+  /// Fails with the id of the first terrain the registry cannot supply. Paths
+  /// between the spawns are carved through the ground, so units on foot must
+  /// be able to cross it.
+  pub fn from_registry(
+    registry: &TerrainRegistry,
+  ) -> Result<Palette, &'static str> {
+    let value = |id: &'static str| {
+      registry.by_id(id).map(|terrain| terrain.value).ok_or(id)
+    };
+    let ground = registry
+      .by_id("grass")
+      .filter(|terrain| terrain.move_cost(MovementClass::Foot).is_some())
+      .ok_or("grass")?;
+    Ok(Palette {
+      ground: ground.value,
+      water: value("water")?,
+      mountains: value("mountains")?,
+      forest: value("forest")?,
+    })
+  }
+}
+
+/// The terrain of a level being generated, as IntGrid values row by row from
+/// the bottom.
+struct Grid<'a> {
+  width: usize,
+  height: usize,
+  cells: Vec<i32>,
+  registry: &'a TerrainRegistry,
+  palette: Palette,
+}
+
+impl Grid<'_> {
+  fn contains(&self, grid_coords: GridCoords) -> bool {
+    (0..self.width as i32).contains(&grid_coords.x)
+      && (0..self.height as i32).contains(&grid_coords.y)
+  }
+
+  fn index(&self, grid_coords: GridCoords) -> usize {
+    grid_coords.y as usize * self.width + grid_coords.x as usize
+  }
+
+  fn get(&self, grid_coords: GridCoords) -> i32 {
+    self.cells[self.index(grid_coords)]
+  }
+
+  /// The cell that `grid_coords` lands on when the level is turned half way
+  /// round. Each side gets the other's ground this way, so neither starts
+  /// with the better of it.
+  fn mirror(&self, grid_coords: GridCoords) -> GridCoords {
+    GridCoords::new(
+      self.width as i32 - 1 - grid_coords.x,
+      self.height as i32 - 1 - grid_coords.y,
+    )
+  }
+
+  fn set_mirrored(&mut self, grid_coords: GridCoords, value: i32) {
+    if !self.contains(grid_coords) {
+      return;
+    }
+    let index = self.index(grid_coords);
+    let mirrored = self.index(self.mirror(grid_coords));
+    self.cells[index] = value;
+    self.cells[mirrored] = value;
+  }
+
+  /// Copies the bottom half of the level, turned round, over the top half.
+  fn symmetrise(&mut self) {
+    let count = self.cells.len();
+    for index in 0..count / 2 {
+      self.cells[count - 1 - index] = self.cells[index];
+    }
+  }
+
+  fn passable(&self, grid_coords: GridCoords) -> bool {
+    self
+      .registry
+      .get(self.get(grid_coords))
+      .and_then(|terrain| terrain.move_cost(MovementClass::Foot))
+      .is_some()
+  }
+
+  fn tile_types(&self) -> TileTypes {
+    let mut tile_types = TileTypes::new(self.width, self.height, self.registry);
+    for (index, value) in self.cells.iter().enumerate() {
+      let grid_coords = GridCoords::new(
+        (index % self.width) as i32,
+        (index / self.width) as i32,
+      );
+      tile_types.set(&grid_coords, *value);
+    }
+    tile_types
+  }
+}
+
+fn unit_float(rng: &mut MatchRng) -> f32 {
+  (rng.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Smooth noise in `0.0..1.0`, one value per cell: random values on a
+/// lattice `scale` cells apart, blended between its points.
+fn value_noise(
+  rng: &mut MatchRng,
+  width: usize,
+  height: usize,
+  scale: usize,
+) -> Vec<f32> {
+  let lattice_width = width / scale + 2;
+  let lattice_height = height / scale + 2;
+  let lattice = (0..lattice_width * lattice_height)
+    .map(|_| unit_float(rng))
+    .collect::<Vec<_>>();
+  let at = |x: usize, y: usize| lattice[y * lattice_width + x];
+  let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+  let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+  let mut noise = Vec::with_capacity(width * height);
+  for y in 0..height {
+    for x in 0..width {
+      let fx = x as f32 / scale as f32;
+      let fy = y as f32 / scale as f32;
+      let (x0, y0) = (fx as usize, fy as usize);
+      let (tx, ty) = (smooth(fx.fract()), smooth(fy.fract()));
+      let bottom = lerp(at(x0, y0), at(x0 + 1, y0), tx);
+      let top = lerp(at(x0, y0 + 1), at(x0 + 1, y0 + 1), tx);
+      noise.push(lerp(bottom, top, ty));
+    }
+  }
+  noise
+}
+
+/// Rolling ground: mountains on the high points, with woods scattered over
+/// the rest.
+fn paint_ground(grid: &mut Grid, rng: &mut MatchRng) {
+  let elevation = value_noise(rng, grid.width, grid.height, 5);
+  let vegetation = value_noise(rng, grid.width, grid.height, 4);
+  let palette = grid.palette;
+  for (index, cell) in grid.cells.iter_mut().enumerate() {
+    *cell = if elevation[index] > 0.72 {
+      palette.mountains
+    } else if vegetation[index] > 0.62 {
+      palette.forest
+    } else {
+      palette.ground
+    };
+  }
+  grid.symmetrise();
+}
+
+fn paint_lakes(grid: &mut Grid, rng: &mut MatchRng) {
+  let count = (grid.width * grid.height / 256).max(1);
+  for _ in 0..count {
+    let centre = GridCoords::new(
+      rng.range(grid.width as i32 / 4, grid.width as i32 - 1),
+      rng.range(0, grid.height as i32 / 2),
+    );
+    let radius = rng.range(1, 2);
+    for dx in -radius - 1..=radius + 1 {
+      for dy in -radius - 1..=radius + 1 {
+        // ragged shores rather than perfect circles
+        let reach = radius * radius + rng.range(0, radius);
+        if dx * dx + dy * dy <= reach {
+          let water = grid.palette.water;
+          grid.set_mirrored(centre + GridCoords::new(dx, dy), water);
+        }
+      }
+    }
+  }
+}
+
+/// Runs rivers from the top of the level towards the bottom, wandering
+/// sideways as they go, until they run off the map or into a lake.
+fn paint_rivers(grid: &mut Grid, rng: &mut MatchRng) {
+  let count = (grid.width / 16).max(1);
+  for _ in 0..count {
+    let mut cell = GridCoords::new(
+      rng.range(grid.width as i32 / 4, grid.width as i32 / 2 - 2),
+      grid.height as i32 - 1,
+    );
+    let water = grid.palette.water;
+    while grid.contains(cell) {
+      grid.set_mirrored(cell, water);
+      if rng.range(0, 2) == 0 {
+        cell.x += if rng.range(0, 1) == 0 { -1 } else { 1 };
+        cell.x = cell.x.clamp(1, grid.width as i32 / 2 - 1);
+        grid.set_mirrored(cell, water);
+      }
+      cell.y -= 1;
+      if grid.contains(cell) && grid.get(cell) == water {
+        break;
+      }
+    }
+  }
+}
+
+/// Picks spawn points for the blue side near the left edge, as many as the
+/// larger of the two queues, and the red side's as their mirror images.
+fn place_spawns(
+  grid: &mut Grid,
+  unit_spawn_queues: &UnitSpawnQueues,
+) -> GeneratedSpawns {
+  let blue_count = unit_spawn_queues.queue(TurnState::Player1).len();
+  let red_count = unit_spawn_queues.queue(TurnState::Player2).len();
+
+  let anchor = GridCoords::new(2, grid.height as i32 / 2);
+  let mut candidates = (0..grid.width as i32 / 3)
+    .flat_map(|x| (0..grid.height as i32).map(move |y| GridCoords::new(x, y)))
+    .collect::<Vec<_>>();
+  candidates.sort_by_key(|cell| {
+    let distance = (cell.x - anchor.x).abs() + (cell.y - anchor.y).abs();
+    (distance, cell.y, cell.x)
+  });
+  candidates.truncate(blue_count.max(red_count));
+
+  for cell in candidates.iter() {
+    grid.set_mirrored(*cell, grid.palette.ground);
+  }
+
+  GeneratedSpawns(
+    candidates
+      .iter()
+      .take(blue_count)
+      .map(|cell| (TurnState::Player1, *cell))
+      .chain(
+        candidates
+          .iter()
+          .take(red_count)
+          .map(|cell| (TurnState::Player2, grid.mirror(*cell))),
+      )
+      .collect(),
+  )
+}
+
+/// Clears a winding path of grass from `from` to `to` through anything that
+/// cannot be crossed, along with its mirror image.
+fn carve_path(
+  grid: &mut Grid,
+  rng: &mut MatchRng,
+  from: GridCoords,
+  to: GridCoords,
+) {
+  let mut cell = from;
+  while cell != to {
+    let (dx, dy) = (to.x - cell.x, to.y - cell.y);
+    // head along whichever axis has further to go, more often than not
+    if rng.range(1, dx.abs() + dy.abs()) <= dx.abs() {
+      cell.x += dx.signum();
+    } else {
+      cell.y += dy.signum();
+    }
+    if !grid.passable(cell) {
+      grid.set_mirrored(cell, grid.palette.ground);
+    }
+  }
+}
+
+/// Carves paths until every spawn can reach every enemy spawn, following the
+/// same movement rules as units do.
+fn connect_sides(
+  grid: &mut Grid,
+  rng: &mut MatchRng,
+  spawns: &GeneratedSpawns,
+) {
+  let no_units = TileStorage::empty(TilemapSize {
+    x: grid.width as u32,
+    y: grid.height as u32,
+  });
+
+  'check: loop {
+    let tile_types = grid.tile_types();
+    for (association, start) in spawns.0.iter() {
+      let reachable = reachable_tiles(
+        &tile_types,
+        &no_units,
+        start,
+        usize::MAX,
+        MovementClass::Foot,
+      );
+      let unreachable = spawns.0.iter().find(|(enemy, target)| {
+        enemy != association && !reachable.contains_key(target)
+      });
+      if let Some((_, target)) = unreachable {
+        carve_path(grid, rng, *start, *target);
+        continue 'check;
+      }
+    }
+    break;
+  }
+}
+
+/// Builds a level of the given size from `seed`, painted with terrain from
+/// `registry`. The same seed and size always build the same level, so only
+/// they need to be shared with a remote player or written into saves and
+/// replays. Fails with the id of any terrain the registry cannot supply.
+pub fn generate(
+  map: GeneratedMap,
+  seed: u64,
+  unit_spawn_queues: &UnitSpawnQueues,
+  registry: &TerrainRegistry,
+) -> Result<GeneratedLevel, &'static str> {
+  let palette = Palette::from_registry(registry)?;
+  // kept apart from the match's own RNG, which starts from the same seed
+  let mut rng = MatchRng::new(seed ^ 0x6d61_7067_656e_0000);
+  let mut grid = Grid {
+    width: map.width,
+    height: map.height,
+    cells: vec![palette.ground; map.width * map.height],
+    registry,
+    palette,
+  };
+
+  paint_ground(&mut grid, &mut rng);
+  paint_lakes(&mut grid, &mut rng);
+  paint_rivers(&mut grid, &mut rng);
+  let spawns = place_spawns(&mut grid, unit_spawn_queues);
+  connect_sides(&mut grid, &mut rng, &spawns);
+
+  Ok(GeneratedLevel {
+    tile_types: grid.tile_types(),
+    spawns,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TERRAIN: &[u8] = include_bytes!("../../assets/holmium.terrain.ron");
+
+  fn values(tile_types: &TileTypes, map: GeneratedMap) -> Vec<i32> {
+    (0..map.height as i32)
+      .flat_map(|y| (0..map.width as i32).map(move |x| GridCoords::new(x, y)))
+      .map(|grid_coords| tile_types.value_at(&grid_coords))
+      .collect()
+  }
+
+  #[test]
+  fn every_spawn_can_reach_every_enemy_spawn() {
+    let registry = TerrainRegistry::from_bytes(TERRAIN).unwrap();
+    let unit_spawn_queues = UnitSpawnQueues::default();
+
+    for map in GENERATED_SIZES {
+      for seed in 0..16 {
+        let level = generate(map, seed, &unit_spawn_queues, &registry).unwrap();
+        let no_units = TileStorage::empty(TilemapSize {
+          x: map.width as u32,
+          y: map.height as u32,
+        });
+
+        for (association, start) in level.spawns.0.iter() {
+          let reachable = reachable_tiles(
+            &level.tile_types,
+            &no_units,
+            start,
+            usize::MAX,
+            MovementClass::Foot,
+          );
+          for (enemy, target) in level.spawns.0.iter() {
+            assert!(
+              enemy == association || reachable.contains_key(target),
+              "{} with seed {}: {:?} cannot reach {:?}",
+              map.name(),
+              seed,
+              start,
+              target
+            );
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn the_same_seed_builds_the_same_level() {
+    let registry = TerrainRegistry::from_bytes(TERRAIN).unwrap();
+    let unit_spawn_queues = UnitSpawnQueues::default();
+    let map = GENERATED_SIZES[1];
+
+    let build = |seed| {
+      let level = generate(map, seed, &unit_spawn_queues, &registry).unwrap();
+      (values(&level.tile_types, map), level.spawns.0)
+    };
+    assert_eq!(build(3), build(3));
+    assert_ne!(build(3).0, build(4).0);
+  }
+
+  #[test]
+  fn each_side_gets_a_spawn_per_queued_unit() {
+    let registry = TerrainRegistry::from_bytes(TERRAIN).unwrap();
+    let unit_spawn_queues = UnitSpawnQueues::default();
+    let level =
+      generate(GENERATED_SIZES[0], 0, &unit_spawn_queues, &registry).unwrap();
+
+    for turn in [TurnState::Player1, TurnState::Player2] {
+      let spawns = level
+        .spawns
+        .0
+        .iter()
+        .filter(|(association, _)| *association == turn)
+        .count();
+      assert_eq!(spawns, unit_spawn_queues.queue(turn).len());
+    }
+  }
+}
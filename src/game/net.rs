@@ -0,0 +1,657 @@
+use bevy::{
+  ecs::{
+    component::Component,
+    entity::Entity,
+    event::{EventReader, EventWriter},
+    query::With,
+    schedule::NextState,
+    system::{Commands, Query, Res, ResMut, Resource},
+  },
+  hierarchy::DespawnRecursiveExt,
+  log::{error, info, warn},
+  ui::{PositionType, Style, Val},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  io::{BufRead, BufReader, ErrorKind, Write},
+  net::{Shutdown, TcpListener, TcpStream},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc, Mutex,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use crate::{
+  assets::LoadingHold,
+  windows::{
+    spawn_list_menu, MenuCancelled, MenuChosen, WindowAssets, WindowFocus,
+  },
+  GlobalState,
+};
+
+use super::{
+  checksum::{TurnChecksum, TurnChecksums},
+  commands::{CommandRequest, GameCommand},
+  replay::ReplayHeader,
+  rng::MatchRng,
+  units::{SpawnLayout, UnitSpawnQueues},
+  MatchConfig, StartMatch, TurnState,
+};
+
+/// How long a dropped peer has to reconnect before the session is abandoned.
+const RECONNECT_WINDOW: Duration = Duration::from_secs(60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the host checks for a new connection, and so how long the port
+/// stays taken after the session is closed.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a new connection has to say hello before the host drops it.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Messages exchanged between host and client, one RON value per line.
+///
+/// The host is the only authority on the order of commands: a client sends
+/// [`NetMessage::Request`]s and only ever applies what comes back as
+/// [`NetMessage::Sequenced`], exactly as the host does, so both boards step
+/// through the same commands in the same order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NetMessage {
+  /// The first message on every connection, naming the client's session so
+  /// that the host only lets the same client back in after a drop.
+  Hello {
+    session: u64,
+  },
+  Welcome {
+    header: ReplayHeader,
+  },
+  Resume {
+    next_sequence: u64,
+  },
+  Request(GameCommand),
+  Sequenced {
+    sequence: u64,
+    command: GameCommand,
+  },
+  Checksum(TurnChecksum),
+  /// Sent as the session is closed, so that the peer does not wait for a
+  /// reconnection that will never come.
+  Quit,
+}
+
+enum LinkEvent {
+  Connected,
+  Message(NetMessage),
+  Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetRole {
+  Host,
+  Client,
+}
+
+/// The port hosted on when none is given, such as for matches set up in
+/// game.
+pub const DEFAULT_PORT: u16 = 7777;
+
+/// Why the last networked match was abandoned, shown on the main menu.
+#[derive(Resource)]
+pub struct ConnectionLost(pub String);
+
+#[derive(Component)]
+pub struct ConnectionLostNotice;
+
+/// The network session asked for on the command line or in the match setup.
+#[derive(Resource, Debug, Clone)]
+pub enum NetConfig {
+  Host { port: u16 },
+  Join { address: String },
+}
+
+#[derive(Resource)]
+pub struct NetSession {
+  role: NetRole,
+  events: Mutex<mpsc::Receiver<LinkEvent>>,
+  /// Messages waiting for the writer thread to send them.
+  outgoing: mpsc::Sender<NetMessage>,
+  /// The current connection, written to by the writer thread.
+  stream: Arc<Mutex<Option<TcpStream>>>,
+  /// Set once the session is dropped, telling its threads to stop.
+  closed: Arc<AtomicBool>,
+  connected: bool,
+  disconnected_since: Option<Instant>,
+  /// Every command sequenced so far (host only), kept so that a client which
+  /// reconnects can be brought back up to date.
+  log: Vec<GameCommand>,
+  /// The sequence number the client expects to apply next.
+  next_sequence: u64,
+  /// The host waits for the client's `Resume` before catching it up.
+  resume_from: Option<u64>,
+  ever_connected: bool,
+  welcomed: bool,
+  /// Whose turn it is according to the sequenced commands, which runs ahead
+  /// of `State<TurnState>` by up to a frame.
+  turn: TurnState,
+  /// Checksums reported by the peer that have not been compared yet.
+  remote_checksums: Vec<TurnChecksum>,
+}
+
+impl NetSession {
+  pub fn local_player(&self) -> TurnState {
+    match self.role {
+      NetRole::Host => TurnState::Player1,
+      NetRole::Client => TurnState::Player2,
+    }
+  }
+
+  /// Whether the local player may act right now.
+  pub fn local_turn(&self) -> bool {
+    self.connected && self.turn == self.local_player()
+  }
+
+  fn new(role: NetRole) -> (Self, mpsc::Sender<LinkEvent>) {
+    let (sender, receiver) = mpsc::channel();
+    let (outgoing, outgoing_receiver) = mpsc::channel();
+    let stream = Arc::new(Mutex::new(None));
+    let slot = stream.clone();
+    thread::spawn(move || write_messages(outgoing_receiver, &slot));
+    (
+      NetSession {
+        role,
+        events: Mutex::new(receiver),
+        outgoing,
+        stream,
+        closed: Arc::new(AtomicBool::new(false)),
+        connected: false,
+        disconnected_since: Some(Instant::now()),
+        log: Vec::new(),
+        next_sequence: 0,
+        resume_from: None,
+        ever_connected: false,
+        welcomed: false,
+        turn: TurnState::Player1,
+        remote_checksums: Vec::new(),
+      },
+      sender,
+    )
+  }
+
+  pub fn send_checksum(&self, turn_checksum: &TurnChecksum) {
+    self.send(NetMessage::Checksum(turn_checksum.clone()));
+  }
+
+  /// Removes and returns the peer's checksums for every phase the local board
+  /// has also reached; the rest are kept until it catches up.
+  pub fn take_comparable_checksums(
+    &mut self,
+    turn_checksums: &TurnChecksums,
+  ) -> Vec<TurnChecksum> {
+    let (comparable, pending): (Vec<_>, Vec<_>) =
+      std::mem::take(&mut self.remote_checksums)
+        .into_iter()
+        .partition(|remote| {
+          turn_checksums
+            .find(remote.turn, remote.turn_number)
+            .is_some()
+        });
+    self.remote_checksums = pending;
+    comparable
+  }
+
+  /// Queues `message` for the writer thread, so that a slow or stalled peer
+  /// never holds up a frame.
+  fn send(&self, message: NetMessage) {
+    // the writer thread only stops once the session is dropped
+    let _ = self.outgoing.send(message);
+  }
+
+  fn sequence(
+    &mut self,
+    command: GameCommand,
+    game_commands: &mut EventWriter<GameCommand>,
+  ) {
+    let sequence = self.log.len() as u64;
+    self.log.push(command.clone());
+    if command == GameCommand::EndTurn {
+      self.turn = self.turn.next();
+    }
+    self.send(NetMessage::Sequenced {
+      sequence,
+      command: command.clone(),
+    });
+    game_commands.send(command);
+  }
+}
+
+impl Drop for NetSession {
+  /// Tells the peer that the match is over. The writer thread shuts the
+  /// connection down once that has gone out, and the host's accepting thread
+  /// lets go of the port on its next poll, so neither holds up the main
+  /// thread.
+  fn drop(&mut self) {
+    self.closed.store(true, Ordering::SeqCst);
+    self.send(NetMessage::Quit);
+  }
+}
+
+/// Sends every queued message down whichever connection is current, dropping
+/// those queued while there is none. Runs until the session is dropped, then
+/// shuts the connection down.
+fn write_messages(
+  outgoing: mpsc::Receiver<NetMessage>,
+  slot: &Mutex<Option<TcpStream>>,
+) {
+  for message in outgoing {
+    let line = match ron::to_string(&message) {
+      Ok(line) => line,
+      Err(err) => {
+        error!("could not serialise {:?}: {}", message, err);
+        continue;
+      }
+    };
+    // written through a clone, so that a blocked write does not keep the
+    // connection threads from swapping the stream out
+    let stream = slot
+      .lock()
+      .unwrap()
+      .as_ref()
+      .and_then(|stream| stream.try_clone().ok());
+    if let Some(mut stream) = stream {
+      // a failed write means the link has dropped, which the reader thread
+      // reports on its own
+      let _ = writeln!(stream, "{}", line);
+    }
+  }
+
+  if let Some(stream) = slot.lock().unwrap().take() {
+    let _ = stream.shutdown(Shutdown::Both);
+  }
+}
+
+fn read_messages(
+  reader: BufReader<TcpStream>,
+  sender: &mpsc::Sender<LinkEvent>,
+) -> bool {
+  for line in reader.lines() {
+    let Ok(line) = line else {
+      break;
+    };
+    match ron::from_str(&line) {
+      Ok(message) => {
+        if sender.send(LinkEvent::Message(message)).is_err() {
+          return false;
+        }
+      }
+      Err(err) => warn!("ignoring malformed message {:?}: {}", line, err),
+    }
+  }
+  true
+}
+
+/// Sends the hello that opens every connection from the client.
+fn say_hello(stream: &mut TcpStream, session: u64) -> std::io::Result<()> {
+  let line = ron::to_string(&NetMessage::Hello { session })
+    .map_err(std::io::Error::other)?;
+  writeln!(stream, "{}", line)
+}
+
+/// Reads the hello from a newly accepted connection, returning the reader to
+/// carry on with if the host should take it. The first client to say hello
+/// names the session; after that, only the same client is let back in.
+fn accept_hello(
+  stream: &TcpStream,
+  session: &mut Option<u64>,
+) -> Option<BufReader<TcpStream>> {
+  // accepted streams may inherit the listener's non-blocking mode
+  stream.set_nonblocking(false).ok()?;
+  stream.set_read_timeout(Some(HELLO_TIMEOUT)).ok()?;
+  let mut reader = BufReader::new(stream.try_clone().ok()?);
+  let mut line = String::new();
+  reader.read_line(&mut line).ok()?;
+  stream.set_read_timeout(None).ok()?;
+
+  let Ok(NetMessage::Hello { session: hello }) =
+    ron::from_str::<NetMessage>(&line)
+  else {
+    return None;
+  };
+  if session.is_some_and(|session| session != hello) {
+    return None;
+  }
+  *session = Some(hello);
+  Some(reader)
+}
+
+/// Runs one connection until it drops, reading through `reader` and handing
+/// `writer` to the writer thread. Returns false once the session has been
+/// closed and the thread should stop.
+fn run_connection(
+  writer: TcpStream,
+  reader: BufReader<TcpStream>,
+  slot: &Mutex<Option<TcpStream>>,
+  sender: &mpsc::Sender<LinkEvent>,
+  closed: &AtomicBool,
+) -> bool {
+  let _ = writer.set_nodelay(true);
+  {
+    // checked under the lock, so a session closing at the same time either
+    // finds this stream to shut down or is seen here
+    let mut slot = slot.lock().unwrap();
+    if closed.load(Ordering::SeqCst) {
+      return false;
+    }
+    *slot = Some(writer);
+  }
+
+  let alive =
+    sender.send(LinkEvent::Connected).is_ok() && read_messages(reader, sender);
+
+  *slot.lock().unwrap() = None;
+  alive
+    && !closed.load(Ordering::SeqCst)
+    && sender.send(LinkEvent::Disconnected).is_ok()
+}
+
+fn host(port: u16) -> std::io::Result<NetSession> {
+  let listener = TcpListener::bind(("0.0.0.0", port))?;
+  // polled rather than left blocking, so that the thread notices the session
+  // closing and drops the listener
+  listener.set_nonblocking(true)?;
+  let (session, sender) = NetSession::new(NetRole::Host);
+  let slot = session.stream.clone();
+  let closed = session.closed.clone();
+
+  // only one client is served at a time; once it drops, it may come back on a
+  // new connection, but anyone else is turned away
+  thread::spawn(move || {
+    let mut client_session = None;
+    while !closed.load(Ordering::SeqCst) {
+      match listener.accept() {
+        Ok((stream, address)) => {
+          let Some(reader) = accept_hello(&stream, &mut client_session) else {
+            warn!("turned away a connection from {}", address);
+            continue;
+          };
+          if !run_connection(stream, reader, &slot, &sender, &closed) {
+            return;
+          }
+        }
+        Err(err) if err.kind() == ErrorKind::WouldBlock => {
+          thread::sleep(ACCEPT_INTERVAL)
+        }
+        Err(err) => {
+          warn!("could not accept a connection: {}", err);
+          thread::sleep(ACCEPT_INTERVAL);
+        }
+      }
+    }
+  });
+
+  info!("hosting on port {}", port);
+  Ok(session)
+}
+
+fn join(address: String) -> NetSession {
+  let (session, sender) = NetSession::new(NetRole::Client);
+  let slot = session.stream.clone();
+  let closed = session.closed.clone();
+  // names this client to the host, which only lets the same session back in
+  // after a dropped connection
+  let client_session = MatchRng::new(
+    std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map(|duration| duration.as_nanos() as u64)
+      .unwrap_or_default()
+      ^ u64::from(std::process::id()),
+  )
+  .next_u64();
+
+  thread::spawn(move || {
+    while !closed.load(Ordering::SeqCst) {
+      let connection = TcpStream::connect(&address).and_then(|mut stream| {
+        say_hello(&mut stream, client_session)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok((stream, reader))
+      });
+      match connection {
+        Ok((stream, reader)) => {
+          if !run_connection(stream, reader, &slot, &sender, &closed) {
+            return;
+          }
+        }
+        Err(_) => thread::sleep(RETRY_INTERVAL),
+      }
+    }
+  });
+
+  session
+}
+
+pub fn start_network_session(mut commands: Commands, config: Res<NetConfig>) {
+  match &*config {
+    NetConfig::Host { port } => match host(*port) {
+      Ok(session) => commands.insert_resource(session),
+      Err(err) => error!("could not host on port {}: {}", port, err),
+    },
+    NetConfig::Join { address } => {
+      info!("joining {}", address);
+      commands.insert_resource(join(address.clone()));
+      // the match can only start once the host has said how to set it up
+      commands.insert_resource(LoadingHold);
+    }
+  }
+}
+
+/// Abandons a networked match for the main menu, where `reason` is shown.
+/// Carrying on locally would hand the missing player's side to whoever is
+/// left.
+fn leave_network_match(
+  commands: &mut Commands,
+  next_global_state: &mut NextState<GlobalState>,
+  reason: &str,
+) {
+  error!("{}", reason);
+  commands.remove_resource::<NetSession>();
+  commands.remove_resource::<NetConfig>();
+  commands.remove_resource::<LoadingHold>();
+  commands.remove_resource::<StartMatch>();
+  commands.insert_resource(ConnectionLost(reason.to_string()));
+  next_global_state.set(GlobalState::MainMenu);
+}
+
+pub fn poll_network(
+  mut commands: Commands,
+  mut session: ResMut<NetSession>,
+  match_config: Res<MatchConfig>,
+  spawn_layout: Option<Res<SpawnLayout>>,
+  unit_spawn_queues: Option<Res<UnitSpawnQueues>>,
+  mut game_commands: EventWriter<GameCommand>,
+  mut next_global_state: ResMut<NextState<GlobalState>>,
+) {
+  let events = session
+    .events
+    .lock()
+    .unwrap()
+    .try_iter()
+    .collect::<Vec<_>>();
+
+  for event in events {
+    match event {
+      LinkEvent::Connected => {
+        info!("peer connected");
+        session.connected = true;
+        session.ever_connected = true;
+        session.disconnected_since = None;
+        if session.role == NetRole::Client {
+          let next_sequence = session.next_sequence;
+          session.send(NetMessage::Resume { next_sequence });
+        }
+      }
+      LinkEvent::Disconnected => {
+        warn!(
+          "peer disconnected; waiting {}s for it to come back",
+          RECONNECT_WINDOW.as_secs()
+        );
+        session.connected = false;
+        session.disconnected_since = Some(Instant::now());
+      }
+      LinkEvent::Message(message) => match (session.role, message) {
+        (NetRole::Host, NetMessage::Resume { next_sequence }) => {
+          session.resume_from = Some(next_sequence);
+        }
+        (NetRole::Host, NetMessage::Request(command)) => {
+          if !command.changes_board() {
+            warn!("ignoring request for local-only command {:?}", command);
+          } else if session.turn != TurnState::Player2 {
+            warn!("ignoring {:?} sent outside the client's turn", command);
+          } else {
+            session.sequence(command, &mut game_commands);
+          }
+        }
+        (NetRole::Client, NetMessage::Welcome { header }) => {
+          if !session.welcomed {
+            info!("joined match on level {}", header.level);
+            session.welcomed = true;
+            commands.insert_resource(MatchConfig {
+              level: header.level,
+              seed: header.seed,
+              generated: header.generated,
+            });
+            commands.insert_resource(header.spawns);
+            commands.remove_resource::<LoadingHold>();
+          }
+        }
+        (NetRole::Client, NetMessage::Sequenced { sequence, command }) => {
+          if sequence == session.next_sequence {
+            session.next_sequence += 1;
+            if command == GameCommand::EndTurn {
+              session.turn = session.turn.next();
+            }
+            game_commands.send(command);
+          } else if sequence > session.next_sequence {
+            warn!(
+              "missed commands {}..{}; asking the host to resend",
+              session.next_sequence, sequence
+            );
+            let next_sequence = session.next_sequence;
+            session.send(NetMessage::Resume { next_sequence });
+          }
+        }
+        (_, NetMessage::Checksum(turn_checksum)) => {
+          session.remote_checksums.push(turn_checksum);
+        }
+        (_, NetMessage::Quit) => {
+          leave_network_match(
+            &mut commands,
+            &mut next_global_state,
+            "The other player left the match.",
+          );
+          return;
+        }
+        (_, message) => warn!("unexpected message {:?}", message),
+      },
+    }
+  }
+
+  // the host can only describe the match once its own level has spawned
+  if let (Some(next_sequence), Some(spawn_layout)) =
+    (session.resume_from, spawn_layout)
+  {
+    session.resume_from = None;
+    session.send(NetMessage::Welcome {
+      header: ReplayHeader {
+        level: match_config.level,
+        seed: match_config.seed,
+        generated: match_config.generated,
+        spawns: (*spawn_layout).clone(),
+        roster: unit_spawn_queues
+          .map(|queues| (*queues).clone())
+          .unwrap_or_default(),
+        start: None,
+      },
+    });
+    for (sequence, command) in
+      session.log.iter().enumerate().skip(next_sequence as usize)
+    {
+      session.send(NetMessage::Sequenced {
+        sequence: sequence as u64,
+        command: command.clone(),
+      });
+    }
+  }
+
+  if let Some(disconnected_since) = session.disconnected_since {
+    // either side may wait indefinitely for the other to first appear
+    if session.ever_connected && disconnected_since.elapsed() > RECONNECT_WINDOW
+    {
+      leave_network_match(
+        &mut commands,
+        &mut next_global_state,
+        "The connection to the other player was lost.",
+      );
+    }
+  }
+}
+
+pub fn route_command_requests(
+  mut session: ResMut<NetSession>,
+  mut command_requests: EventReader<CommandRequest>,
+  mut game_commands: EventWriter<GameCommand>,
+) {
+  for CommandRequest(command) in command_requests.read() {
+    if !command.changes_board() {
+      game_commands.send(command.clone());
+    } else if !session.local_turn() {
+      warn!("ignoring {:?} outside of the local player's turn", command);
+    } else {
+      match session.role {
+        NetRole::Host => session.sequence(command.clone(), &mut game_commands),
+        NetRole::Client => session.send(NetMessage::Request(command.clone())),
+      }
+    }
+  }
+}
+
+pub fn show_connection_lost(
+  mut commands: Commands,
+  window_assets: Res<WindowAssets>,
+  mut window_focus: ResMut<WindowFocus>,
+  connection_lost: Res<ConnectionLost>,
+) {
+  spawn_list_menu(
+    &mut commands,
+    &window_assets,
+    &mut window_focus,
+    Style {
+      position_type: PositionType::Absolute,
+      left: Val::Px(16.0),
+      top: Val::Px(16.0),
+      ..Default::default()
+    },
+    Some(&connection_lost.0),
+    &["Back".to_string()],
+    ConnectionLostNotice,
+  );
+}
+
+pub fn answer_connection_lost(
+  mut commands: Commands,
+  mut chosen_events: EventReader<MenuChosen>,
+  mut cancelled_events: EventReader<MenuCancelled>,
+  notices: Query<Entity, With<ConnectionLostNotice>>,
+) {
+  let Ok(notice) = notices.get_single() else {
+    return;
+  };
+  let chosen = chosen_events.read().any(|chosen| chosen.menu == notice);
+  let cancelled = cancelled_events
+    .read()
+    .any(|cancelled| cancelled.menu == notice);
+  if !chosen && !cancelled {
+    return;
+  }
+
+  commands.entity(notice).despawn_recursive();
+  commands.remove_resource::<ConnectionLost>();
+}
@@ -0,0 +1,128 @@
+use bevy::{
+  ecs::{
+    component::Component,
+    entity::Entity,
+    event::EventReader,
+    query::With,
+    system::{Commands, Query, Res},
+  },
+  hierarchy::{BuildChildren, DespawnRecursiveExt},
+  time::Time,
+  ui::{
+    node_bundles::NodeBundle, AlignItems, FlexDirection, PositionType, Style,
+    UiRect, Val, ZIndex,
+  },
+};
+
+use crate::windows::{text, WindowAssets};
+
+use super::{
+  commands::PhaseStarted,
+  units::{Unit, UnitAssociation},
+  GameEntity,
+};
+
+const SLIDE_SECONDS: f32 = 0.35;
+const HOLD_SECONDS: f32 = 1.0;
+
+/// Announces a new phase by sliding across the screen, holding up player
+/// input until it is gone.
+///
+/// Below the turn number it says how many units the side has ready. Nothing in
+/// the game brings in reinforcements or ticks status effects at the start of a
+/// phase yet; once something does, it belongs on the banner as well.
+#[derive(Component)]
+pub struct PhaseBanner {
+  elapsed: f32,
+}
+
+pub fn show_phase_banner(
+  mut commands: Commands,
+  mut phase_started_events: EventReader<PhaseStarted>,
+  window_assets: Res<WindowAssets>,
+  units: Query<(&Unit, &UnitAssociation)>,
+  banners: Query<Entity, With<PhaseBanner>>,
+) {
+  let Some(phase_started) = phase_started_events.read().last().copied() else {
+    return;
+  };
+  // a replay stepping quickly may start phases faster than they can be shown
+  for banner in banners.iter() {
+    commands.entity(banner).despawn_recursive();
+  }
+
+  let ready = units
+    .iter()
+    .filter(|(unit, association)| {
+      association.turn == phase_started.turn && !unit.moved
+    })
+    .count();
+
+  commands
+    .spawn((
+      NodeBundle {
+        style: Style {
+          position_type: PositionType::Absolute,
+          left: Val::Percent(100.0),
+          top: Val::Percent(35.0),
+          width: Val::Percent(100.0),
+          flex_direction: FlexDirection::Column,
+          align_items: AlignItems::Center,
+          padding: UiRect::vertical(Val::Px(8.0)),
+          ..Default::default()
+        },
+        background_color: phase_started.turn.faction_color().with_a(0.9).into(),
+        z_index: ZIndex::Global(8),
+        ..Default::default()
+      },
+      PhaseBanner { elapsed: 0.0 },
+      GameEntity,
+    ))
+    .with_children(|banner| {
+      banner.spawn(text(
+        &window_assets,
+        format!("{} Phase", phase_started.turn.faction_name()),
+        48.0,
+      ));
+      banner.spawn(text(
+        &window_assets,
+        format!("Turn {}", phase_started.turn_number),
+        24.0,
+      ));
+      banner.spawn(text(
+        &window_assets,
+        match ready {
+          1 => "1 unit ready".to_string(),
+          _ => format!("{} units ready", ready),
+        },
+        20.0,
+      ));
+    });
+}
+
+/// Slides each banner in from the right, holds it, then slides it out to the
+/// left and despawns it.
+pub fn animate_phase_banner(
+  mut commands: Commands,
+  time: Res<Time>,
+  mut banners: Query<(Entity, &mut PhaseBanner, &mut Style)>,
+) {
+  let ease = |progress: f32| progress * progress * (3.0 - 2.0 * progress);
+
+  for (entity, mut banner, mut style) in banners.iter_mut() {
+    banner.elapsed += time.delta_seconds();
+
+    let elapsed = banner.elapsed;
+    let left = if elapsed < SLIDE_SECONDS {
+      100.0 * (1.0 - ease(elapsed / SLIDE_SECONDS))
+    } else if elapsed < SLIDE_SECONDS + HOLD_SECONDS {
+      0.0
+    } else if elapsed < 2.0 * SLIDE_SECONDS + HOLD_SECONDS {
+      -100.0 * ease((elapsed - SLIDE_SECONDS - HOLD_SECONDS) / SLIDE_SECONDS)
+    } else {
+      commands.entity(entity).despawn_recursive();
+      continue;
+    };
+    style.left = Val::Percent(left);
+  }
+}
@@ -0,0 +1,472 @@
+use bevy::{
+  ecs::{
+    entity::Entity,
+    event::{EventReader, EventWriter},
+    query::With,
+    schedule::NextState,
+    system::{Commands, Query, Res, ResMut, Resource},
+  },
+  hierarchy::DespawnRecursiveExt,
+  log::{error, info},
+  time::{Time, Timer, TimerMode},
+};
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::{
+  checksum::{TurnChecksum, TurnChecksums},
+  commands::{AppliedGameCommand, GameCommand, MatchOver},
+  input::{Action, Actions},
+  mapgen::GeneratedMap,
+  rng::MatchRng,
+  save::{spawn_saved_units, PendingLoad, SaveGame},
+  unit_defs::UnitRoster,
+  units::{spawn_unit, SpawnLayout, Unit, UnitAssociations, UnitSpawnQueues},
+  BackdropMap, MatchConfig, TurnNumber, TurnState, UnitMap,
+};
+
+const REPLAY_DIRECTORY: &str = "replays";
+const BASE_STEP_SECONDS: f32 = 0.75;
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 8.0;
+
+/// Everything needed to rebuild the board as it was before the first command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+  pub level: usize,
+  pub seed: u64,
+  #[serde(default)]
+  pub generated: Option<GeneratedMap>,
+  pub spawns: SpawnLayout,
+  pub roster: UnitSpawnQueues,
+  /// The board the recording started from when the match was resumed from a
+  /// save; `spawns` only describes the start of the level.
+  #[serde(default)]
+  pub start: Option<SaveGame>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+  pub header: ReplayHeader,
+  pub commands: Vec<GameCommand>,
+  /// The board checksum at the start of each phase, checked while watching
+  /// to catch a replay that no longer plays out the way it was recorded.
+  #[serde(default)]
+  pub checksums: Vec<TurnChecksum>,
+}
+
+impl Replay {
+  pub fn load(path: &Path) -> Result<Replay, String> {
+    let contents = std::fs::read_to_string(path)
+      .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    ron::from_str(&contents)
+      .map_err(|err| format!("could not parse {}: {}", path.display(), err))
+  }
+
+  pub fn write(&self, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent).map_err(|err| {
+        format!("could not create {}: {}", parent.display(), err)
+      })?;
+    }
+    let contents =
+      ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+        .map_err(|err| format!("could not serialise replay: {}", err))?;
+    std::fs::write(path, contents)
+      .map_err(|err| format!("could not write {}: {}", path.display(), err))
+  }
+}
+
+/// The replay file requested on the command line with `--replay <path>`.
+#[derive(Resource)]
+pub struct ReplayPath(pub PathBuf);
+
+/// Collects every applied command of the match being played.
+#[derive(Resource)]
+pub struct ReplayRecorder {
+  header: ReplayHeader,
+  commands: Vec<GameCommand>,
+}
+
+impl ReplayRecorder {
+  /// Throws away what was recorded so far and carries on from `start`, such
+  /// as a save that was just restored.
+  pub fn restart_from(&mut self, start: SaveGame) {
+    self.header.start = Some(start);
+    self.commands.clear();
+  }
+}
+
+/// Present only while watching a replay; its existence disables player input.
+#[derive(Resource)]
+pub struct ReplayViewer {
+  replay: Replay,
+  position: usize,
+  playing: bool,
+  speed: f32,
+  timer: Timer,
+  pending_rebuild: bool,
+  catching_up: bool,
+}
+
+impl ReplayViewer {
+  pub fn new(replay: Replay) -> Self {
+    ReplayViewer {
+      replay,
+      position: 0,
+      playing: false,
+      speed: 1.0,
+      timer: Timer::from_seconds(BASE_STEP_SECONDS, TimerMode::Repeating),
+      pending_rebuild: false,
+      catching_up: false,
+    }
+  }
+
+  /// Moves past the next recorded command and returns it to be applied.
+  pub fn step_forward(&mut self) -> Option<GameCommand> {
+    let game_command = self.replay.commands.get(self.position).cloned()?;
+    self.position += 1;
+    Some(game_command)
+  }
+
+  /// Moves back one command, which rebuilds the board from the start and
+  /// plays it forward again to the new position.
+  pub fn step_back(&mut self) {
+    if self.position > 0 {
+      self.position -= 1;
+      self.pending_rebuild = true;
+      self.catching_up = true;
+    }
+  }
+
+  /// Whether the board is still being brought forward after stepping back.
+  /// The phases it passes through on the way were already reported, and it
+  /// may well land in the middle of one.
+  pub fn catching_up(&self) -> bool {
+    self.catching_up
+  }
+
+  /// Called once the commands sent to catch up have been applied.
+  pub fn caught_up(&mut self) {
+    // still waiting for the rebuilt board to have them sent
+    self.catching_up = self.pending_rebuild;
+  }
+
+  pub fn recorded_checksum(
+    &self,
+    turn: TurnState,
+    turn_number: u32,
+  ) -> Option<&TurnChecksum> {
+    self.replay.checksums.iter().find(|turn_checksum| {
+      turn_checksum.turn == turn && turn_checksum.turn_number == turn_number
+    })
+  }
+
+  pub fn pause(&mut self) {
+    self.playing = false;
+  }
+}
+
+pub fn load_replay(
+  mut commands: Commands,
+  replay_path: Option<Res<ReplayPath>>,
+) {
+  let Some(replay_path) = replay_path else {
+    return;
+  };
+
+  match Replay::load(&replay_path.0) {
+    Ok(replay) => {
+      info!(
+        "watching replay {} ({} commands)",
+        replay_path.0.display(),
+        replay.commands.len()
+      );
+      commands.insert_resource(MatchConfig {
+        level: replay.header.level,
+        seed: replay.header.seed,
+        generated: replay.header.generated,
+      });
+      commands.insert_resource(replay.header.spawns.clone());
+      if let Some(start) = &replay.header.start {
+        commands.insert_resource(PendingLoad(start.clone()));
+      }
+      commands.insert_resource(ReplayViewer::new(replay));
+    }
+    Err(err) => error!("{}; starting a normal match instead", err),
+  }
+}
+
+pub fn start_recording(
+  mut commands: Commands,
+  match_config: Res<MatchConfig>,
+  unit_spawn_queues: Res<UnitSpawnQueues>,
+  viewer: Option<Res<ReplayViewer>>,
+) {
+  if viewer.is_some() {
+    return;
+  }
+
+  commands.insert_resource(ReplayRecorder {
+    header: ReplayHeader {
+      level: match_config.level,
+      seed: match_config.seed,
+      generated: match_config.generated,
+      spawns: SpawnLayout::default(),
+      roster: unit_spawn_queues.clone(),
+      start: None,
+    },
+    commands: Vec::new(),
+  });
+}
+
+pub fn record_spawn_layout(
+  spawn_layout: Res<SpawnLayout>,
+  mut recorder: ResMut<ReplayRecorder>,
+) {
+  recorder.header.spawns = spawn_layout.clone();
+}
+
+pub fn record_applied_commands(
+  mut applied_game_commands: EventReader<AppliedGameCommand>,
+  mut recorder: ResMut<ReplayRecorder>,
+) {
+  for AppliedGameCommand(game_command) in applied_game_commands.read() {
+    if game_command.changes_board() {
+      recorder.commands.push(game_command.clone());
+    }
+  }
+}
+
+pub fn write_replay_on_match_over(
+  mut match_over_events: EventReader<MatchOver>,
+  recorder: Res<ReplayRecorder>,
+  turn_checksums: Res<TurnChecksums>,
+) {
+  for _ in match_over_events.read() {
+    let timestamp = std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map(|duration| duration.as_secs())
+      .unwrap_or_default();
+    let path =
+      PathBuf::from(REPLAY_DIRECTORY).join(format!("match-{}.ron", timestamp));
+
+    let replay = Replay {
+      header: recorder.header.clone(),
+      commands: recorder.commands.clone(),
+      checksums: turn_checksums.0.clone(),
+    };
+    match replay.write(&path) {
+      Ok(()) => info!("replay written to {}", path.display()),
+      Err(err) => error!("{}", err),
+    }
+  }
+}
+
+pub fn replay_controls(
+  actions: Res<Actions>,
+  time: Res<Time>,
+  mut viewer: ResMut<ReplayViewer>,
+  mut game_commands: EventWriter<GameCommand>,
+) {
+  if viewer.pending_rebuild {
+    // the board was rebuilt last frame, so bring it forward to the current
+    // position in one go
+    viewer.pending_rebuild = false;
+    let position = viewer.position;
+    game_commands.send_batch(viewer.replay.commands[..position].to_vec());
+    return;
+  }
+
+  if actions.any_just_pressed(Action::Confirm) {
+    viewer.playing = !viewer.playing;
+  }
+  if actions.any_just_pressed(Action::Up) {
+    viewer.speed = (viewer.speed * 2.0).min(MAX_SPEED);
+    info!("replay speed x{}", viewer.speed);
+  }
+  if actions.any_just_pressed(Action::Down) {
+    viewer.speed = (viewer.speed / 2.0).max(MIN_SPEED);
+    info!("replay speed x{}", viewer.speed);
+  }
+
+  let step_back = actions.any_just_pressed(Action::Left);
+  let mut step_forward = actions.any_just_pressed(Action::Right);
+  if step_forward || step_back {
+    viewer.playing = false;
+  }
+
+  if viewer.playing {
+    let delta = time.delta().mul_f32(viewer.speed);
+    step_forward |= viewer.timer.tick(delta).just_finished();
+  }
+
+  if step_forward {
+    match viewer.step_forward() {
+      Some(game_command) => {
+        game_commands.send(game_command);
+      }
+      None => viewer.playing = false,
+    }
+  } else if step_back {
+    viewer.step_back();
+  }
+}
+
+/// Puts the board back to how it was at the start of the replay, which is the
+/// restored save for a recording of a resumed match. The commands
+/// up to the current position are sent again by [`replay_controls`] on the
+/// following frame, once the new units exist.
+#[allow(clippy::too_many_arguments)]
+pub fn rebuild_replay_board(
+  mut commands: Commands,
+  viewer: Res<ReplayViewer>,
+  unit_roster: UnitRoster,
+  units: Query<(Entity, &Unit, &TilePos)>,
+  mut unit_map: Query<(Entity, &mut TileStorage), With<UnitMap>>,
+  backdrop_map: Query<Entity, With<BackdropMap>>,
+  mut unit_associations: ResMut<UnitAssociations>,
+  mut turn_number: ResMut<TurnNumber>,
+  mut rng: ResMut<MatchRng>,
+  mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+  if !viewer.pending_rebuild {
+    return;
+  }
+
+  let (unit_map, mut unit_storage) = unit_map.single_mut();
+  for (entity, unit, tile_pos) in units.iter() {
+    unit_storage.remove(tile_pos);
+    commands.entity(unit.backdrop).despawn_recursive();
+    commands.entity(entity).despawn_recursive();
+  }
+
+  *unit_associations = UnitAssociations::default();
+  let header = &viewer.replay.header;
+  match &header.start {
+    Some(start) => {
+      spawn_saved_units(
+        &mut commands,
+        &unit_roster,
+        &start.units,
+        unit_map,
+        &mut unit_storage,
+        backdrop_map.single(),
+      );
+      *turn_number = TurnNumber(start.turn_number);
+      *rng = start.rng;
+      next_turn_state.set(start.turn);
+    }
+    None => {
+      for unit_spawn in header.spawns.iter() {
+        spawn_unit(
+          &mut commands,
+          &unit_roster,
+          unit_spawn,
+          unit_map,
+          &mut unit_storage,
+          backdrop_map.single(),
+        );
+      }
+      *turn_number = TurnNumber::default();
+      *rng = MatchRng::new(header.seed);
+      next_turn_state.set(TurnState::Player1);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bevy::{
+    app::{App, Update},
+    ecs::{event::Events, schedule::IntoSystemConfigs},
+  };
+  use bevy_ecs_ldtk::GridCoords;
+
+  use super::*;
+  use crate::game::{
+    commands::{apply_game_commands, detect_phase_start, PhaseStarted},
+    fixtures::{board, unit},
+  };
+
+  #[test]
+  fn stepping_a_replay_back_starts_no_phase() {
+    let (mut app, _) = board(vec![
+      (GridCoords::new(0, 0), TurnState::Player1, unit()),
+      (GridCoords::new(5, 5), TurnState::Player2, unit()),
+    ]);
+    app
+      .add_event::<PhaseStarted>()
+      .init_resource::<MatchConfig>()
+      .init_resource::<Actions>()
+      .init_resource::<Time>()
+      .insert_resource(ReplayViewer::new(Replay {
+        header: ReplayHeader {
+          level: 0,
+          seed: 0,
+          generated: None,
+          spawns: SpawnLayout::default(),
+          roster: UnitSpawnQueues::default(),
+          start: None,
+        },
+        commands: vec![GameCommand::EndTurn],
+        checksums: Vec::new(),
+      }))
+      .add_systems(
+        Update,
+        (
+          replay_controls.before(apply_game_commands),
+          detect_phase_start.after(apply_game_commands),
+        ),
+      );
+
+    let mut reader = app.world.resource::<Events<PhaseStarted>>().get_reader();
+    let mut phases = Vec::new();
+    let mut update =
+      |app: &mut App| {
+        app.update();
+        let events = app.world.resource::<Events<PhaseStarted>>();
+        phases.extend(reader.read(events).map(|phase_started| {
+          (phase_started.turn, phase_started.turn_number)
+        }));
+      };
+    let step_forward = |app: &mut App| {
+      let game_command = app
+        .world
+        .resource_mut::<ReplayViewer>()
+        .step_forward()
+        .unwrap();
+      app.world.send_event(game_command);
+    };
+
+    update(&mut app);
+    step_forward(&mut app);
+    update(&mut app);
+    update(&mut app);
+
+    // back across the end of turn, putting the board back the way
+    // rebuild_replay_board does
+    app.world.resource_mut::<ReplayViewer>().step_back();
+    app
+      .world
+      .resource_mut::<NextState<TurnState>>()
+      .set(TurnState::Player1);
+    update(&mut app);
+    update(&mut app);
+
+    // and forward again, which is a real start of the phase
+    step_forward(&mut app);
+    update(&mut app);
+    update(&mut app);
+
+    assert_eq!(
+      phases,
+      vec![
+        (TurnState::Player1, 1),
+        (TurnState::Player2, 1),
+        (TurnState::Player2, 1)
+      ]
+    );
+  }
+}
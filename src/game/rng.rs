@@ -0,0 +1,68 @@
+use bevy::ecs::system::Resource;
+use serde::{Deserialize, Serialize};
+
+/// The single source of randomness for a match.
+///
+/// This is a SplitMix64 generator, small enough that its whole state can be
+/// written into replays and save files and restored exactly.
+#[derive(
+  Resource, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct MatchRng {
+  state: u64,
+}
+
+impl MatchRng {
+  pub fn new(seed: u64) -> Self {
+    MatchRng { state: seed }
+  }
+
+  pub fn state(&self) -> u64 {
+    self.state
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  /// Returns a value in `min..=max`.
+  pub fn range(&mut self, min: i32, max: i32) -> i32 {
+    let span = (max - min) as u64 + 1;
+    min + (self.next_u64() % span) as i32
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn the_same_seed_gives_the_same_rolls() {
+    let mut first = MatchRng::new(42);
+    let mut second = MatchRng::new(42);
+    for _ in 0..100 {
+      assert_eq!(first.next_u64(), second.next_u64());
+    }
+    assert_eq!(first, second);
+  }
+
+  #[test]
+  fn ranges_include_both_ends_and_nothing_else() {
+    let mut rng = MatchRng::new(7);
+    let rolls = (0..1000).map(|_| rng.range(-2, 2)).collect::<Vec<_>>();
+    assert!(rolls.iter().all(|roll| (-2..=2).contains(roll)));
+    for value in -2..=2 {
+      assert!(rolls.contains(&value));
+    }
+  }
+
+  #[test]
+  fn a_range_of_one_value_always_gives_it() {
+    let mut rng = MatchRng::new(0);
+    assert!((0..10).all(|_| rng.range(3, 3) == 3));
+  }
+}
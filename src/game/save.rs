@@ -0,0 +1,253 @@
+use bevy::{
+  ecs::{
+    entity::Entity,
+    query::With,
+    schedule::{NextState, State},
+    system::{Commands, Query, Res, ResMut, Resource},
+    world::EntityWorldMut,
+  },
+  hierarchy::DespawnRecursiveExt,
+  log::{error, info},
+};
+use bevy_ecs_ldtk::GridCoords;
+use bevy_ecs_tilemap::tiles::{TilePos, TileStorage};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::GlobalState;
+
+use super::{
+  input::{Action, Actions},
+  mapgen::GeneratedMap,
+  replay::ReplayRecorder,
+  rng::MatchRng,
+  unit_defs::UnitRoster,
+  units::{
+    spawn_unit, Unit, UnitAssociation, UnitAssociations, UnitKind, UnitSpawn,
+  },
+  BackdropMap, MatchConfig, StartMatch, TurnNumber, TurnState, UnitMap,
+};
+
+const QUICKSAVE_PATH: &str = "saves/quicksave.ron";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedUnit {
+  pub unit_type: UnitKind,
+  pub association: TurnState,
+  #[serde(with = "crate::util::serde_grid_coords")]
+  pub grid_coords: GridCoords,
+  pub health: i32,
+  pub moved: bool,
+  pub acted: bool,
+}
+
+/// A complete snapshot of a match in progress.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveGame {
+  pub level: usize,
+  pub seed: u64,
+  #[serde(default)]
+  pub generated: Option<GeneratedMap>,
+  pub turn: TurnState,
+  pub turn_number: u32,
+  pub rng: MatchRng,
+  pub units: Vec<SavedUnit>,
+}
+
+impl SaveGame {
+  pub fn load(path: &Path) -> Result<SaveGame, String> {
+    let contents = std::fs::read_to_string(path)
+      .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    ron::from_str(&contents)
+      .map_err(|err| format!("could not parse {}: {}", path.display(), err))
+  }
+
+  pub fn write(&self, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent).map_err(|err| {
+        format!("could not create {}: {}", parent.display(), err)
+      })?;
+    }
+    let contents =
+      ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+        .map_err(|err| format!("could not serialise save: {}", err))?;
+    std::fs::write(path, contents)
+      .map_err(|err| format!("could not write {}: {}", path.display(), err))
+  }
+}
+
+/// A save waiting to be restored once the world for its level has spawned.
+#[derive(Resource)]
+pub struct PendingLoad(pub SaveGame);
+
+pub fn capture_save_game(
+  match_config: &MatchConfig,
+  turn_state: TurnState,
+  turn_number: &TurnNumber,
+  rng: &MatchRng,
+  units: &Query<(&UnitKind, &UnitAssociation, &TilePos, &Unit)>,
+) -> SaveGame {
+  SaveGame {
+    level: match_config.level,
+    seed: match_config.seed,
+    generated: match_config.generated,
+    turn: turn_state,
+    turn_number: **turn_number,
+    rng: *rng,
+    units: units
+      .iter()
+      .map(|(unit_type, association, tile_pos, unit)| SavedUnit {
+        unit_type: unit_type.clone(),
+        association: association.turn,
+        grid_coords: crate::util::tile_to_grid(*tile_pos),
+        health: unit.health,
+        moved: unit.moved,
+        acted: unit.acted,
+      })
+      .collect(),
+  }
+}
+
+/// Requests that `save_game` replaces the current match. The whole world is
+/// rebuilt by passing back through [`GlobalState::Loading`], which also
+/// covers saves made on a different level.
+pub fn request_load(
+  commands: &mut Commands,
+  save_game: SaveGame,
+  next_global_state: &mut NextState<GlobalState>,
+) {
+  commands.insert_resource(MatchConfig {
+    level: save_game.level,
+    seed: save_game.seed,
+    generated: save_game.generated,
+  });
+  commands.insert_resource(PendingLoad(save_game));
+  commands.insert_resource(StartMatch);
+  next_global_state.set(GlobalState::Loading);
+}
+
+pub fn quicksave(save_game: &SaveGame) {
+  let path = PathBuf::from(QUICKSAVE_PATH);
+  match save_game.write(&path) {
+    Ok(()) => info!("game saved to {}", path.display()),
+    Err(err) => error!("{}", err),
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn quicksave_controls(
+  mut commands: Commands,
+  actions: Res<Actions>,
+  match_config: Res<MatchConfig>,
+  turn_state: Res<State<TurnState>>,
+  turn_number: Res<TurnNumber>,
+  rng: Res<MatchRng>,
+  units: Query<(&UnitKind, &UnitAssociation, &TilePos, &Unit)>,
+  mut next_global_state: ResMut<NextState<GlobalState>>,
+) {
+  if actions.any_just_pressed(Action::QuickSave) {
+    quicksave(&capture_save_game(
+      &match_config,
+      *turn_state.get(),
+      &turn_number,
+      &rng,
+      &units,
+    ));
+  }
+
+  if actions.any_just_pressed(Action::QuickLoad) {
+    let path = PathBuf::from(QUICKSAVE_PATH);
+    match SaveGame::load(&path) {
+      Ok(save_game) => {
+        info!("loading {}", path.display());
+        request_load(&mut commands, save_game, &mut next_global_state);
+      }
+      Err(err) => error!("{}", err),
+    }
+  }
+}
+
+/// Spawns `saved_units` with the health and turn progress they were saved
+/// with. The caller is expected to have cleared the board first.
+pub fn spawn_saved_units(
+  commands: &mut Commands,
+  unit_roster: &UnitRoster,
+  saved_units: &[SavedUnit],
+  unit_map: Entity,
+  unit_storage: &mut TileStorage,
+  backdrop_map: Entity,
+) {
+  for saved_unit in saved_units.iter() {
+    let Some(unit) = spawn_unit(
+      commands,
+      unit_roster,
+      &UnitSpawn {
+        unit_type: saved_unit.unit_type.clone(),
+        association: saved_unit.association,
+        grid_coords: saved_unit.grid_coords,
+      },
+      unit_map,
+      unit_storage,
+      backdrop_map,
+    ) else {
+      continue;
+    };
+
+    let (health, moved, acted) =
+      (saved_unit.health, saved_unit.moved, saved_unit.acted);
+    commands
+      .entity(unit)
+      .add(move |mut entity_world: EntityWorldMut| {
+        let mut unit = entity_world.get_mut::<Unit>().unwrap();
+        unit.health = health;
+        unit.moved = moved;
+        unit.acted = acted;
+      });
+  }
+}
+
+/// Replaces the units placed from the level's spawn locations with the ones
+/// from the pending save, and restores the turn and RNG state alongside them.
+#[allow(clippy::too_many_arguments)]
+pub fn restore_pending_load(
+  mut commands: Commands,
+  pending_load: Res<PendingLoad>,
+  recorder: Option<ResMut<ReplayRecorder>>,
+  unit_roster: UnitRoster,
+  units: Query<(Entity, &Unit, &TilePos)>,
+  mut unit_map: Query<(Entity, &mut TileStorage), With<UnitMap>>,
+  backdrop_map: Query<Entity, With<BackdropMap>>,
+  mut unit_associations: ResMut<UnitAssociations>,
+  mut next_turn_state: ResMut<NextState<TurnState>>,
+) {
+  let save_game = &pending_load.0;
+  info!("restoring saved match on level {}", save_game.level);
+
+  let (unit_map, mut unit_storage) = unit_map.single_mut();
+  for (entity, unit, tile_pos) in units.iter() {
+    unit_storage.remove(tile_pos);
+    commands.entity(unit.backdrop).despawn_recursive();
+    commands.entity(entity).despawn_recursive();
+  }
+
+  *unit_associations = UnitAssociations::default();
+  spawn_saved_units(
+    &mut commands,
+    &unit_roster,
+    &save_game.units,
+    unit_map,
+    &mut unit_storage,
+    backdrop_map.single(),
+  );
+
+  commands.insert_resource(TurnNumber(save_game.turn_number));
+  commands.insert_resource(save_game.rng);
+  next_turn_state.set(save_game.turn);
+
+  // the recording started with this world would replay the level's spawns,
+  // so start it over from the restored board instead
+  if let Some(mut recorder) = recorder {
+    recorder.restart_from(save_game.clone());
+  }
+  commands.remove_resource::<PendingLoad>();
+}
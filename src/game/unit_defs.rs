@@ -0,0 +1,170 @@
+use bevy::{
+  asset::{
+    io::Reader, Asset, AssetLoader, Assets, AsyncReadExt, Handle, LoadContext,
+  },
+  ecs::system::{Res, Resource, SystemParam},
+  reflect::TypePath,
+  utils::{BoxedFuture, HashSet},
+};
+use serde::Deserialize;
+
+use crate::{sprites::Sprites, tiles::MovementClass};
+
+use super::units::UnitKind;
+
+/// Where the unit definitions live, relative to the assets directory.
+pub const UNIT_DEFINITIONS_PATH: &str = "holmium.units.ron";
+
+fn default_health() -> i32 {
+  30
+}
+
+fn default_attack_range() -> usize {
+  1
+}
+
+/// Something a unit can do besides moving and attacking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Ability {
+  /// Restores this much health to a friendly unit in attack range.
+  Heal(i32),
+}
+
+/// One kind of unit, as written in the unit definitions asset.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UnitDefinition {
+  /// What saves, replays and the spawn queues call this kind of unit.
+  pub id: String,
+  /// The name shown to players.
+  pub name: String,
+  /// The name of the unit's sprite in the sprite manifest.
+  pub sprite: String,
+  #[serde(default = "default_health")]
+  pub health: i32,
+  pub max_move_cost: usize,
+  #[serde(default)]
+  pub movement: MovementClass,
+  /// How many tiles away, counted along the grid, the unit can attack or
+  /// heal.
+  #[serde(default = "default_attack_range")]
+  pub attack_range: usize,
+  pub attack: i32,
+  #[serde(default)]
+  pub abilities: Vec<Ability>,
+}
+
+impl UnitDefinition {
+  /// How much the unit heals, or 0 if it cannot.
+  pub fn heal(&self) -> i32 {
+    self
+      .abilities
+      .iter()
+      .map(|ability| match ability {
+        Ability::Heal(amount) => *amount,
+      })
+      .max()
+      .unwrap_or(0)
+  }
+}
+
+/// Every kind of unit that can be put on the board.
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct UnitDefinitions {
+  pub units: Vec<UnitDefinition>,
+}
+
+impl UnitDefinitions {
+  pub fn get(&self, kind: &UnitKind) -> Option<&UnitDefinition> {
+    self.units.iter().find(|definition| definition.id == kind.0)
+  }
+}
+
+#[derive(Resource)]
+pub struct UnitDefinitionsHandle(pub Handle<UnitDefinitions>);
+
+/// The loaded unit definitions, for systems that spawn or describe units.
+/// Reading them through the asset rather than a copy means an edited file
+/// takes effect as soon as it is reloaded.
+#[derive(SystemParam)]
+pub struct UnitRoster<'w> {
+  handle: Res<'w, UnitDefinitionsHandle>,
+  definitions: Res<'w, Assets<UnitDefinitions>>,
+  pub sprites: Sprites<'w>,
+}
+
+impl UnitRoster<'_> {
+  pub fn get(&self, kind: &UnitKind) -> Option<&UnitDefinition> {
+    self
+      .definitions
+      .get(&self.handle.0)
+      .and_then(|definitions| definitions.get(kind))
+  }
+}
+
+#[derive(Debug)]
+pub enum UnitDefinitionsError {
+  Io(std::io::Error),
+  Parse(ron::error::SpannedError),
+  DuplicateId(String),
+}
+
+impl std::fmt::Display for UnitDefinitionsError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      UnitDefinitionsError::Io(err) => write!(f, "could not read: {}", err),
+      UnitDefinitionsError::Parse(err) => write!(f, "could not parse: {}", err),
+      UnitDefinitionsError::DuplicateId(id) => {
+        write!(f, "unit {} is defined more than once", id)
+      }
+    }
+  }
+}
+
+impl std::error::Error for UnitDefinitionsError {}
+
+impl From<std::io::Error> for UnitDefinitionsError {
+  fn from(err: std::io::Error) -> Self {
+    UnitDefinitionsError::Io(err)
+  }
+}
+
+impl From<ron::error::SpannedError> for UnitDefinitionsError {
+  fn from(err: ron::error::SpannedError) -> Self {
+    UnitDefinitionsError::Parse(err)
+  }
+}
+
+/// Reads `.units.ron` files into [`UnitDefinitions`].
+#[derive(Default)]
+pub struct UnitDefinitionsLoader;
+
+impl AssetLoader for UnitDefinitionsLoader {
+  type Asset = UnitDefinitions;
+  type Settings = ();
+  type Error = UnitDefinitionsError;
+
+  fn load<'a>(
+    &'a self,
+    reader: &'a mut Reader,
+    _settings: &'a (),
+    _load_context: &'a mut LoadContext,
+  ) -> BoxedFuture<'a, Result<UnitDefinitions, UnitDefinitionsError>> {
+    Box::pin(async move {
+      let mut bytes = Vec::new();
+      reader.read_to_end(&mut bytes).await?;
+      let definitions: UnitDefinitions = ron::de::from_bytes(&bytes)?;
+
+      let mut ids = HashSet::new();
+      for definition in definitions.units.iter() {
+        if !ids.insert(definition.id.as_str()) {
+          return Err(UnitDefinitionsError::DuplicateId(definition.id.clone()));
+        }
+      }
+      Ok(definitions)
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["units.ron"]
+  }
+}
@@ -5,10 +5,11 @@ use bevy::{
     entity::Entity,
     event::EventReader,
     query::{Added, Changed, With},
-    system::{Commands, EntityCommands, Query, ResMut, Resource},
+    system::{Commands, Query, Res, ResMut, Resource},
     world::EntityWorldMut,
   },
   hierarchy::DespawnRecursiveExt,
+  log::{error, info, warn},
   utils::HashSet,
 };
 use bevy_ecs_ldtk::{EntityInstance, GridCoords, LdtkEntity, LevelEvent};
@@ -16,19 +17,31 @@ use bevy_ecs_tilemap::{
   map::TilemapId,
   tiles::{TileBundle, TilePos, TileStorage, TileTextureIndex},
 };
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
-use crate::tiles::{
-  BACKDROP_BLUE, BACKDROP_RED, UNIT_ARCHER, UNIT_BARBARIAN, UNIT_CLERIC,
-  UNIT_KNIGHT, UNIT_WIZARD,
-};
+use crate::tiles::{MovementClass, TileTypes};
 
-use super::{BackdropMap, GameEntity, TurnState, UnitMap};
+use super::{
+  arrows::tile_move_cost,
+  mapgen::GeneratedSpawns,
+  unit_defs::{UnitDefinition, UnitRoster},
+  BackdropMap, GameEntity, LevelSize, TurnState, UnitMap,
+};
 
 #[derive(Component)]
 pub struct Unit {
   pub health: i32,
+  pub max_health: i32,
   pub max_move_cost: usize,
+  pub movement: MovementClass,
+  /// How many tiles away, counted along the grid, the unit can attack or
+  /// heal.
+  pub attack_range: usize,
+  pub attack: i32,
+  pub heal: i32,
   pub moved: bool,
+  pub acted: bool,
   pub backdrop: Entity,
 }
 
@@ -45,26 +58,25 @@ pub struct Backdrop;
 #[derive(Bundle)]
 pub struct BackdropBundle {
   backdrop: Backdrop,
+  game_entity: GameEntity,
   pub tile_bundle: TileBundle,
 }
 
 impl BackdropBundle {
   pub fn new(
-    turn_state: TurnState,
+    texture_index: usize,
     grid_coords: GridCoords,
     backdrop_map: Entity,
   ) -> Self {
     BackdropBundle {
       backdrop: Backdrop,
+      game_entity: GameEntity,
       tile_bundle: TileBundle {
         position: TilePos {
           x: grid_coords.x as u32,
           y: grid_coords.y as u32,
         },
-        texture_index: TileTextureIndex(match turn_state {
-          TurnState::Player1 => BACKDROP_BLUE as u32,
-          TurnState::Player2 => BACKDROP_RED as u32,
-        }),
+        texture_index: TileTextureIndex(texture_index as u32),
         tilemap_id: TilemapId(backdrop_map),
         ..Default::default()
       },
@@ -72,73 +84,29 @@ impl BackdropBundle {
   }
 }
 
-pub trait UnitType: Default + Bundle {
-  fn get_texture_index() -> usize;
-  fn get_max_move_cost() -> usize {
-    6
-  }
+/// Which of the unit definitions a unit was spawned from, by id.
+#[derive(
+  Component, Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct UnitKind(pub String);
 
-  fn new(
-    grid_coords: GridCoords,
-    association: TurnState,
-    unit_map: Entity,
-    backdrop: Entity,
-  ) -> impl Bundle {
-    create_unit::<Self>(grid_coords, association, unit_map, backdrop)
+impl From<&str> for UnitKind {
+  fn from(id: &str) -> Self {
+    UnitKind(id.to_string())
   }
 }
 
-#[derive(Default, Component)]
-pub struct Wizard;
-impl UnitType for Wizard {
-  #[inline]
-  fn get_texture_index() -> usize {
-    UNIT_WIZARD
+impl std::fmt::Display for UnitKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.0)
   }
 }
 
-#[derive(Default, Component)]
-pub struct Archer;
-impl UnitType for Archer {
-  #[inline]
-  fn get_texture_index() -> usize {
-    UNIT_ARCHER
-  }
-
-  #[inline]
-  fn get_max_move_cost() -> usize {
-    10
-  }
-}
-
-#[derive(Default, Component)]
-pub struct Barbarian;
-impl UnitType for Barbarian {
-  #[inline]
-  fn get_texture_index() -> usize {
-    UNIT_BARBARIAN
-  }
-}
-
-#[derive(Default, Component)]
-pub struct Knight;
-impl UnitType for Knight {
-  #[inline]
-  fn get_texture_index() -> usize {
-    UNIT_KNIGHT
-  }
-}
-
-#[derive(Default, Component)]
-pub struct Cleric;
-impl UnitType for Cleric {
-  #[inline]
-  fn get_texture_index() -> usize {
-    UNIT_CLERIC
-  }
-}
-
-pub fn create_unit<U: UnitType>(
+pub fn create_unit(
+  kind: &UnitKind,
+  definition: &UnitDefinition,
+  texture_index: usize,
   grid_coords: GridCoords,
   association: TurnState,
   unit_map: Entity,
@@ -147,9 +115,15 @@ pub fn create_unit<U: UnitType>(
   (
     UnitBundle {
       unit: Unit {
-        health: 30,
-        max_move_cost: U::get_max_move_cost(),
+        health: definition.health,
+        max_health: definition.health,
+        max_move_cost: definition.max_move_cost,
+        movement: definition.movement,
+        attack_range: definition.attack_range,
+        attack: definition.attack,
+        heal: definition.heal(),
         moved: false,
+        acted: false,
         backdrop,
       },
       tile_bundle: TileBundle {
@@ -157,13 +131,13 @@ pub fn create_unit<U: UnitType>(
           x: grid_coords.x as u32,
           y: grid_coords.y as u32,
         },
-        texture_index: TileTextureIndex(U::get_texture_index() as u32),
+        texture_index: TileTextureIndex(texture_index as u32),
         tilemap_id: TilemapId(unit_map),
         ..Default::default()
       },
       association: UnitAssociation { turn: association },
     },
-    U::default(),
+    kind.clone(),
     GameEntity,
   )
 }
@@ -185,90 +159,56 @@ pub struct UnitSpawnLocationBundle {
   pub grid_coords: GridCoords,
 }
 
+/// The LDtk entities that mark where each side's units start.
+pub const BLUE_SPAWN: &str = "BLUE_SPAWN";
+pub const RED_SPAWN: &str = "RED_SPAWN";
+
+/// Which side a spawn entity in the LDtk project belongs to, if `identifier`
+/// is a spawn at all.
+pub fn spawn_association(identifier: &str) -> Option<TurnState> {
+  match identifier {
+    BLUE_SPAWN => Some(TurnState::Player1),
+    RED_SPAWN => Some(TurnState::Player2),
+    _ => None,
+  }
+}
+
 fn association_from_ldtk_instance(
   instance: &EntityInstance,
 ) -> UnitAssociation {
   UnitAssociation {
-    turn: match &instance.identifier[..1] {
-      "B" => TurnState::Player1,
-      "R" => TurnState::Player2,
-      _ => unreachable!(),
-    },
+    // only spawns are registered, and levels with anything else are turned
+    // away before a match starts on them
+    turn: spawn_association(&instance.identifier).unwrap_or_else(|| {
+      error!("{} is not a spawn entity", instance.identifier);
+      TurnState::default()
+    }),
   }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum UnitSpawnTypes {
-  Wizard,
-  Archer,
-  Knight,
-  Barbarian,
-  Cleric,
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct UnitSpawnQueues {
+  player1: Vec<UnitKind>,
+  player2: Vec<UnitKind>,
 }
 
-impl UnitSpawnTypes {
-  fn create_bundle<'c>(
-    &self,
-    commands: &'c mut Commands,
-    grid_coords: GridCoords,
-    association: TurnState,
-    unit_map: Entity,
-    backdrop_map: Entity,
-  ) -> EntityCommands<'c> {
-    let backdrop = commands
-      .spawn(BackdropBundle::new(association, grid_coords, backdrop_map))
-      .id();
-
-    match self {
-      Self::Wizard => commands.spawn(Wizard::new(
-        grid_coords,
-        association,
-        unit_map,
-        backdrop,
-      )),
-      Self::Archer => commands.spawn(Archer::new(
-        grid_coords,
-        association,
-        unit_map,
-        backdrop,
-      )),
-      Self::Knight => commands.spawn(Knight::new(
-        grid_coords,
-        association,
-        unit_map,
-        backdrop,
-      )),
-      Self::Barbarian => commands.spawn(Barbarian::new(
-        grid_coords,
-        association,
-        unit_map,
-        backdrop,
-      )),
-      Self::Cleric => commands.spawn(Cleric::new(
-        grid_coords,
-        association,
-        unit_map,
-        backdrop,
-      )),
+impl UnitSpawnQueues {
+  /// The units still waiting to be placed for `turn_state`'s side.
+  pub fn queue(&self, turn_state: TurnState) -> &[UnitKind] {
+    match turn_state {
+      TurnState::Player1 => &self.player1,
+      TurnState::Player2 => &self.player2,
     }
   }
 }
 
-#[derive(Resource)]
-pub struct UnitSpawnQueues {
-  player1: Vec<UnitSpawnTypes>,
-  player2: Vec<UnitSpawnTypes>,
-}
-
 impl Default for UnitSpawnQueues {
   fn default() -> Self {
-    let default_queue = vec![
-      UnitSpawnTypes::Knight,
-      UnitSpawnTypes::Knight,
-      UnitSpawnTypes::Barbarian,
-      UnitSpawnTypes::Archer,
-      UnitSpawnTypes::Archer,
-    ];
+    let default_queue: Vec<UnitKind> =
+      ["knight", "knight", "barbarian", "archer", "archer"]
+        .into_iter()
+        .map(UnitKind::from)
+        .collect();
 
     UnitSpawnQueues {
       player1: default_queue.clone(),
@@ -277,51 +217,208 @@ impl Default for UnitSpawnQueues {
   }
 }
 
+/// A single unit placed on the board at the start of a match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitSpawn {
+  pub unit_type: UnitKind,
+  pub association: TurnState,
+  #[serde(with = "crate::util::serde_grid_coords")]
+  pub grid_coords: GridCoords,
+}
+
+/// Where every unit started the match. If this resource is already present
+/// when the level spawns (i.e. when watching a replay), it is used verbatim
+/// instead of filling the level's spawn locations from the queues.
+#[derive(Resource, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpawnLayout(pub Vec<UnitSpawn>);
+
+impl std::ops::Deref for SpawnLayout {
+  type Target = Vec<UnitSpawn>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+/// Spawns a unit and its backdrop as described by its kind's definition, or
+/// nothing if no unit of that kind is defined.
+pub fn spawn_unit(
+  commands: &mut Commands,
+  unit_roster: &UnitRoster,
+  unit_spawn: &UnitSpawn,
+  unit_map: Entity,
+  unit_storage: &mut TileStorage,
+  backdrop_map: Entity,
+) -> Option<Entity> {
+  let Some(definition) = unit_roster.get(&unit_spawn.unit_type) else {
+    warn!(
+      "no unit is defined as {}, so none is placed at ({}, {})",
+      unit_spawn.unit_type, unit_spawn.grid_coords.x, unit_spawn.grid_coords.y
+    );
+    return None;
+  };
+
+  let backdrop = commands
+    .spawn(BackdropBundle::new(
+      unit_roster
+        .sprites
+        .faction_index("backdrop", unit_spawn.association),
+      unit_spawn.grid_coords,
+      backdrop_map,
+    ))
+    .id();
+  let unit_tile = commands
+    .spawn(create_unit(
+      &unit_spawn.unit_type,
+      definition,
+      unit_roster.sprites.index(&definition.sprite),
+      unit_spawn.grid_coords,
+      unit_spawn.association,
+      unit_map,
+      backdrop,
+    ))
+    .id();
+
+  unit_storage.set(
+    &crate::util::grid_to_tile(unit_spawn.grid_coords),
+    unit_tile,
+  );
+
+  Some(unit_tile)
+}
+
+/// Pairs each spawn point with the next unit from its side's queue. Spawn
+/// points left over once a queue runs out stay empty.
+fn layout_from_queues(
+  unit_spawn_queues: &mut UnitSpawnQueues,
+  spawn_points: impl Iterator<Item = (TurnState, GridCoords)>,
+) -> SpawnLayout {
+  SpawnLayout(
+    spawn_points
+      .filter_map(|(association, grid_coords)| {
+        let Some(unit_type) = match association {
+          TurnState::Player1 => &mut unit_spawn_queues.player1,
+          TurnState::Player2 => &mut unit_spawn_queues.player2,
+        }
+        .pop() else {
+          warn!(
+            "no unit left to place at ({}, {}) for {}",
+            grid_coords.x,
+            grid_coords.y,
+            association.faction_name()
+          );
+          return None;
+        };
+        Some(UnitSpawn {
+          unit_type,
+          association,
+          grid_coords,
+        })
+      })
+      .collect(),
+  )
+}
+
+fn spawn_units(
+  commands: &mut Commands,
+  unit_roster: &UnitRoster,
+  spawn_layout: SpawnLayout,
+  unit_map: &mut Query<(Entity, &mut TileStorage), With<UnitMap>>,
+  backdrop_map: &Query<Entity, With<BackdropMap>>,
+) {
+  let (unit_map, mut unit_storage) = unit_map.single_mut();
+  for unit_spawn in spawn_layout.iter() {
+    spawn_unit(
+      commands,
+      unit_roster,
+      unit_spawn,
+      unit_map,
+      &mut unit_storage,
+      backdrop_map.single(),
+    );
+  }
+
+  commands.insert_resource(spawn_layout);
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn fill_unit_spawn_locations(
   mut commands: Commands,
   mut level_events: EventReader<LevelEvent>,
+  unit_roster: UnitRoster,
   mut unit_spawn_queues: ResMut<UnitSpawnQueues>,
+  spawn_layout: Option<Res<SpawnLayout>>,
   unit_spawn_locations: Query<
     (Entity, &UnitAssociation, &GridCoords),
     With<UnitSpawnLocation>,
   >,
+  existing_units: Query<(), With<Unit>>,
   mut unit_map: Query<(Entity, &mut TileStorage), With<UnitMap>>,
   backdrop_map: Query<Entity, With<BackdropMap>>,
 ) {
   for level_event in level_events.read() {
     if let LevelEvent::Spawned(_) = level_event {
-      for (unit_spawn_location, association, grid_coords) in
-        unit_spawn_locations.iter()
-      {
-        let unit_tile = match association.turn {
-          TurnState::Player1 => &mut unit_spawn_queues.player1,
-          TurnState::Player2 => &mut unit_spawn_queues.player2,
-        }
-        .pop()
-        .unwrap()
-        .create_bundle(
-          &mut commands,
-          grid_coords.clone(),
-          association.turn,
-          unit_map.single().0,
-          backdrop_map.single(),
-        )
-        .id();
-
-        unit_map.single_mut().1.set(
-          &TilePos {
-            x: grid_coords.x as u32,
-            y: grid_coords.y as u32,
-          },
-          unit_tile,
-        );
-
+      for (unit_spawn_location, _, _) in unit_spawn_locations.iter() {
         commands.entity(unit_spawn_location).despawn_recursive();
       }
+
+      // the project was reloaded mid-match and the level respawned under
+      // units that are already on the board, which stay where they are
+      if !existing_units.is_empty() {
+        continue;
+      }
+
+      let layout = match &spawn_layout {
+        Some(spawn_layout) => (**spawn_layout).clone(),
+        None => layout_from_queues(
+          &mut unit_spawn_queues,
+          unit_spawn_locations
+            .iter()
+            .map(|(_, association, grid_coords)| {
+              (association.turn, *grid_coords)
+            }),
+        ),
+      };
+
+      spawn_units(
+        &mut commands,
+        &unit_roster,
+        layout,
+        &mut unit_map,
+        &backdrop_map,
+      );
     }
   }
 }
 
+/// Does for a generated level what [`fill_unit_spawn_locations`] does for
+/// one from the project, once its tilemaps are in place.
+pub fn fill_generated_spawns(
+  mut commands: Commands,
+  generated_spawns: Res<GeneratedSpawns>,
+  unit_roster: UnitRoster,
+  mut unit_spawn_queues: ResMut<UnitSpawnQueues>,
+  spawn_layout: Option<Res<SpawnLayout>>,
+  mut unit_map: Query<(Entity, &mut TileStorage), With<UnitMap>>,
+  backdrop_map: Query<Entity, With<BackdropMap>>,
+) {
+  let layout = match &spawn_layout {
+    Some(spawn_layout) => (**spawn_layout).clone(),
+    None => layout_from_queues(
+      &mut unit_spawn_queues,
+      generated_spawns.0.iter().copied(),
+    ),
+  };
+
+  spawn_units(
+    &mut commands,
+    &unit_roster,
+    layout,
+    &mut unit_map,
+    &backdrop_map,
+  );
+}
+
 pub fn update_backdrop_positions(
   mut commands: Commands,
   units: Query<(&Unit, &TilePos), Changed<TilePos>>,
@@ -351,6 +448,14 @@ impl UnitAssociations {
     .contains(&entity)
   }
 
+  pub fn remaining(&self, turn_state: TurnState) -> usize {
+    match turn_state {
+      TurnState::Player1 => &self.player1,
+      TurnState::Player2 => &self.player2,
+    }
+    .len()
+  }
+
   fn insert(&mut self, entity: Entity, turn_state: TurnState) {
     match turn_state {
       TurnState::Player1 => &mut self.player1,
@@ -358,6 +463,11 @@ impl UnitAssociations {
     }
     .insert(entity);
   }
+
+  pub fn remove(&mut self, entity: Entity) {
+    self.player1.remove(&entity);
+    self.player2.remove(&entity);
+  }
 }
 
 pub fn update_unit_associations_resource(
@@ -369,10 +479,59 @@ pub fn update_unit_associations_resource(
   }
 }
 
-pub fn refresh_units(
-  mut units: Query<&mut Unit>,
+/// Moves any unit left on terrain it cannot stand on, after the level has
+/// been edited under it, to the nearest free tile it can. Every other unit
+/// keeps its place.
+pub fn relocate_stranded_units(
+  tile_types: Res<TileTypes>,
+  level_size: Res<LevelSize>,
+  mut unit_map: Query<&mut TileStorage, With<UnitMap>>,
+  mut units: Query<(Entity, &Unit, &mut TilePos)>,
 ) {
-  for mut unit in units.iter_mut() {
-    unit.moved = true;
+  let Ok(mut unit_storage) = unit_map.get_single_mut() else {
+    return;
+  };
+
+  for (entity, unit, mut tile_pos) in units.iter_mut() {
+    let start = crate::util::tile_to_grid(*tile_pos);
+    if tile_move_cost(&tile_types, &start, unit.movement).is_some() {
+      continue;
+    }
+
+    // search outwards across any terrain, since the unit may be walled in
+    let mut visited = HashSet::from_iter([start]);
+    let mut frontier = VecDeque::from([start]);
+    let mut destination = None;
+    while let Some(node) = frontier.pop_front() {
+      if tile_move_cost(&tile_types, &node, unit.movement).is_some()
+        && unit_storage
+          .checked_get(&crate::util::grid_to_tile(node))
+          .is_none()
+      {
+        destination = Some(node);
+        break;
+      }
+      for neighbour in crate::util::neighbours(&node) {
+        if level_size.contains(neighbour) && visited.insert(neighbour) {
+          frontier.push_back(neighbour);
+        }
+      }
+    }
+
+    match destination {
+      Some(destination) => {
+        info!(
+          "moving unit from ({}, {}) to ({}, {}) after the level changed",
+          start.x, start.y, destination.x, destination.y
+        );
+        unit_storage.remove(&tile_pos);
+        *tile_pos = crate::util::grid_to_tile(destination);
+        unit_storage.set(&tile_pos, entity);
+      }
+      None => warn!(
+        "nowhere left to stand for the unit at ({}, {})",
+        start.x, start.y
+      ),
+    }
   }
 }
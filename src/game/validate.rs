@@ -0,0 +1,547 @@
+use bevy::{
+  asset::{Asset, AssetEvent, Assets},
+  ecs::{
+    component::Component,
+    entity::Entity,
+    event::EventReader,
+    query::With,
+    system::{Commands, Query, Res, ResMut, Resource},
+  },
+  hierarchy::DespawnRecursiveExt,
+  log::error,
+  ui::{PositionType, Style, Val},
+};
+use bevy_ecs_ldtk::{
+  assets::LdtkProject,
+  ldtk::{Level, Type},
+  utils::ldtk_grid_coords_to_grid_coords,
+  GridCoords,
+};
+use bevy_ecs_tilemap::{map::TilemapSize, tiles::TileStorage};
+use std::collections::BTreeMap;
+
+use crate::{
+  assets::LdtkWorldHandle,
+  tiles::{LoadedTerrain, MovementClass, TerrainRegistry, TileTypes},
+  windows::{
+    spawn_list_menu, MenuCancelled, MenuChosen, WindowAssets, WindowFocus,
+  },
+};
+
+use super::{
+  arrows::{reachable_tiles, tile_move_cost},
+  mapgen::Palette,
+  units::{spawn_association, UnitSpawnQueues, BLUE_SPAWN, RED_SPAWN},
+  MatchConfig, TurnState,
+};
+
+/// The only grid size the tilemaps and the atlas are laid out for.
+const GRID_SIZE: i32 = 16;
+
+/// Something about a level that would stop a match on it from playing out
+/// properly, or from starting at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelProblem {
+  MissingLevel {
+    index: usize,
+  },
+  GridSize {
+    layer: String,
+    grid_size: i32,
+  },
+  NoTerrain,
+  UnknownTerrain {
+    value: i32,
+    cells: usize,
+  },
+  MissingTerrain {
+    id: &'static str,
+  },
+  UnknownEntity {
+    identifier: String,
+    grid_coords: GridCoords,
+  },
+  SpawnCount {
+    side: TurnState,
+    spawns: usize,
+    queued: usize,
+  },
+  ImpassableSpawn {
+    side: TurnState,
+    grid_coords: GridCoords,
+    terrain: Option<String>,
+  },
+  UnreachableSpawn {
+    side: TurnState,
+    grid_coords: GridCoords,
+  },
+}
+
+impl std::fmt::Display for LevelProblem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      LevelProblem::MissingLevel { index } => {
+        write!(f, "the project has no level {}", index + 1)
+      }
+      LevelProblem::GridSize { layer, grid_size } => write!(
+        f,
+        "layer {} uses a {}px grid; every layer must use {}px",
+        layer, grid_size, GRID_SIZE
+      ),
+      LevelProblem::NoTerrain => {
+        write!(f, "there is no IntGrid layer painting the terrain")
+      }
+      LevelProblem::UnknownTerrain { value, cells } => write!(
+        f,
+        "{} cells are painted with IntGrid value {}, which no terrain has",
+        cells, value
+      ),
+      LevelProblem::MissingTerrain { id } => write!(
+        f,
+        "the terrain registry has no usable {} to build generated levels from",
+        id
+      ),
+      LevelProblem::UnknownEntity {
+        identifier,
+        grid_coords,
+      } => write!(
+        f,
+        "entity {} at ({}, {}) is unknown; only {} and {} can be placed",
+        identifier, grid_coords.x, grid_coords.y, BLUE_SPAWN, RED_SPAWN
+      ),
+      LevelProblem::SpawnCount {
+        side,
+        spawns,
+        queued,
+      } => write!(
+        f,
+        "{} has {} spawns for {} queued units; place exactly {}",
+        side.faction_name(),
+        spawns,
+        queued,
+        queued
+      ),
+      LevelProblem::ImpassableSpawn {
+        side,
+        grid_coords,
+        terrain,
+      } => write!(
+        f,
+        "{} spawn at ({}, {}) is on {}, which units cannot stand on",
+        side.faction_name(),
+        grid_coords.x,
+        grid_coords.y,
+        terrain.as_deref().unwrap_or("no terrain at all")
+      ),
+      LevelProblem::UnreachableSpawn { side, grid_coords } => write!(
+        f,
+        "{} spawn at ({}, {}) has no path to any {} spawn",
+        side.faction_name(),
+        grid_coords.x,
+        grid_coords.y,
+        side.next().faction_name()
+      ),
+    }
+  }
+}
+
+/// Why the match that was about to start could not, shown on the main menu.
+#[derive(Resource)]
+pub struct LevelProblems {
+  pub level: String,
+  pub problems: Vec<LevelProblem>,
+}
+
+#[derive(Default, Component)]
+pub struct LevelProblemsReport;
+
+/// The level's terrain, read straight from its IntGrid layer rather than from
+/// spawned tiles, so that it can be checked before the level is spawned.
+fn level_tile_types(
+  level: &Level,
+  registry: &TerrainRegistry,
+) -> Option<TileTypes> {
+  let layer = level
+    .layer_instances
+    .as_ref()?
+    .iter()
+    .find(|layer| layer.layer_instance_type == Type::IntGrid)?;
+
+  let mut tile_types =
+    TileTypes::new(layer.c_wid as usize, layer.c_hei as usize, registry);
+  for (index, value) in layer.int_grid_csv.iter().enumerate() {
+    let ldtk_coords = bevy::math::IVec2::new(
+      index as i32 % layer.c_wid,
+      index as i32 / layer.c_wid,
+    );
+    let grid_coords = ldtk_grid_coords_to_grid_coords(ldtk_coords, layer.c_hei);
+    tile_types.set(&grid_coords, *value);
+  }
+  Some(tile_types)
+}
+
+/// Checks everything about `level` that a match relies on, returning every
+/// problem found rather than stopping at the first.
+pub fn validate_level(
+  level: &Level,
+  unit_spawn_queues: &UnitSpawnQueues,
+  registry: &TerrainRegistry,
+) -> Vec<LevelProblem> {
+  let mut problems = Vec::new();
+  let layers = level.layer_instances.as_deref().unwrap_or_default();
+
+  let mut spawns = Vec::new();
+  for layer in layers {
+    if layer.grid_size != GRID_SIZE {
+      problems.push(LevelProblem::GridSize {
+        layer: layer.identifier.clone(),
+        grid_size: layer.grid_size,
+      });
+    }
+
+    for entity in layer.entity_instances.iter() {
+      let grid_coords =
+        ldtk_grid_coords_to_grid_coords(entity.grid, layer.c_hei);
+      match spawn_association(&entity.identifier) {
+        Some(side) => spawns.push((side, grid_coords)),
+        None => problems.push(LevelProblem::UnknownEntity {
+          identifier: entity.identifier.clone(),
+          grid_coords,
+        }),
+      }
+    }
+  }
+
+  for side in [TurnState::Player1, TurnState::Player2] {
+    let spawn_count = spawns.iter().filter(|(spawn, _)| *spawn == side).count();
+    let queued = unit_spawn_queues.queue(side).len();
+    if spawn_count != queued {
+      problems.push(LevelProblem::SpawnCount {
+        side,
+        spawns: spawn_count,
+        queued,
+      });
+    }
+  }
+
+  let Some(tile_types) = level_tile_types(level, registry) else {
+    problems.push(LevelProblem::NoTerrain);
+    return problems;
+  };
+
+  let mut unknown_values = BTreeMap::new();
+  for x in 0..level.px_wid / GRID_SIZE {
+    for y in 0..level.px_hei / GRID_SIZE {
+      let grid_coords = GridCoords::new(x, y);
+      let value = tile_types.value_at(&grid_coords);
+      if value != 0 && tile_types.terrain_at(&grid_coords).is_none() {
+        *unknown_values.entry(value).or_insert(0) += 1;
+      }
+    }
+  }
+  for (value, cells) in unknown_values {
+    problems.push(LevelProblem::UnknownTerrain { value, cells });
+  }
+
+  let no_units = TileStorage::empty(TilemapSize {
+    x: (level.px_wid / GRID_SIZE) as u32,
+    y: (level.px_hei / GRID_SIZE) as u32,
+  });
+  for (side, grid_coords) in spawns.iter() {
+    if tile_move_cost(&tile_types, grid_coords, MovementClass::Foot).is_none() {
+      problems.push(LevelProblem::ImpassableSpawn {
+        side: *side,
+        grid_coords: *grid_coords,
+        terrain: tile_types
+          .terrain_at(grid_coords)
+          .map(|terrain| terrain.name.clone()),
+      });
+      continue;
+    }
+
+    let reachable = reachable_tiles(
+      &tile_types,
+      &no_units,
+      grid_coords,
+      usize::MAX,
+      MovementClass::Foot,
+    );
+    let reaches_enemy = spawns
+      .iter()
+      .any(|(enemy, target)| enemy != side && reachable.contains_key(target));
+    if !reaches_enemy {
+      problems.push(LevelProblem::UnreachableSpawn {
+        side: *side,
+        grid_coords: *grid_coords,
+      });
+    }
+  }
+
+  problems
+}
+
+/// Checks the level the next match is set up for. Generated levels are built
+/// to be playable, so for them it is only the terrain they are built from
+/// that is checked.
+pub fn check_match_level(
+  project: &LdtkProject,
+  registry: &TerrainRegistry,
+  match_config: &MatchConfig,
+) -> Option<LevelProblems> {
+  if let Some(map) = match_config.generated {
+    return Palette::from_registry(registry)
+      .err()
+      .map(|id| LevelProblems {
+        level: map.name(),
+        problems: vec![LevelProblem::MissingTerrain { id }],
+      });
+  }
+
+  let Some(level) = project.json_data().levels.get(match_config.level) else {
+    return Some(LevelProblems {
+      level: format!("Level {}", match_config.level + 1),
+      problems: vec![LevelProblem::MissingLevel {
+        index: match_config.level,
+      }],
+    });
+  };
+  let problems = validate_level(level, &UnitSpawnQueues::default(), registry);
+  (!problems.is_empty()).then(|| LevelProblems {
+    level: level.identifier.clone(),
+    problems,
+  })
+}
+
+fn is_loaded<A: Asset>(event: &AssetEvent<A>) -> bool {
+  matches!(
+    event,
+    AssetEvent::LoadedWithDependencies { .. } | AssetEvent::Modified { .. }
+  )
+}
+
+/// Logs the problems with every level in the project each time it or the
+/// terrain registry loads, so that they turn up while the level is being
+/// made rather than when it is first played.
+pub fn log_level_problems(
+  mut project_events: EventReader<AssetEvent<LdtkProject>>,
+  mut registry_events: EventReader<AssetEvent<TerrainRegistry>>,
+  ldtk_handle: Res<LdtkWorldHandle>,
+  projects: Res<Assets<LdtkProject>>,
+  loaded_terrain: LoadedTerrain,
+) {
+  let project_loaded = project_events.read().any(is_loaded);
+  let registry_loaded = registry_events.read().any(is_loaded);
+  if !project_loaded && !registry_loaded {
+    return;
+  }
+  let (Some(project), Some(registry)) =
+    (projects.get(&**ldtk_handle), loaded_terrain.get())
+  else {
+    return;
+  };
+
+  for level in project.json_data().levels.iter() {
+    for problem in validate_level(level, &UnitSpawnQueues::default(), registry)
+    {
+      error!("{}: {}", level.identifier, problem);
+    }
+  }
+}
+
+pub fn show_level_problems(
+  mut commands: Commands,
+  window_assets: Res<WindowAssets>,
+  mut window_focus: ResMut<WindowFocus>,
+  level_problems: Res<LevelProblems>,
+) {
+  let mut title = format!("{} cannot be played:", level_problems.level);
+  for problem in level_problems.problems.iter() {
+    title.push_str(&format!("\n- {}", problem));
+  }
+
+  spawn_list_menu(
+    &mut commands,
+    &window_assets,
+    &mut window_focus,
+    Style {
+      position_type: PositionType::Absolute,
+      left: Val::Px(16.0),
+      top: Val::Px(16.0),
+      ..Default::default()
+    },
+    Some(&title),
+    &["Back".to_string()],
+    LevelProblemsReport,
+  );
+}
+
+pub fn answer_level_problems(
+  mut commands: Commands,
+  mut chosen_events: EventReader<MenuChosen>,
+  mut cancelled_events: EventReader<MenuCancelled>,
+  reports: Query<Entity, With<LevelProblemsReport>>,
+) {
+  let Ok(report) = reports.get_single() else {
+    return;
+  };
+  let chosen = chosen_events.read().any(|chosen| chosen.menu == report);
+  let cancelled = cancelled_events
+    .read()
+    .any(|cancelled| cancelled.menu == report);
+  if !chosen && !cancelled {
+    return;
+  }
+
+  commands.entity(report).despawn_recursive();
+  commands.remove_resource::<LevelProblems>();
+}
+
+#[cfg(test)]
+mod tests {
+  use bevy::math::IVec2;
+  use bevy_ecs_ldtk::ldtk::{EntityInstance, LayerInstance};
+
+  use super::*;
+
+  const TERRAIN: &[u8] = include_bytes!("../../assets/holmium.terrain.ron");
+
+  /// Builds a level from `rows`, written top row first: `.` for grass, `M`
+  /// for mountains, `?` for an IntGrid value no terrain has, `b` and `r` for
+  /// blue and red spawns on grass, `B` for a blue spawn on mountains and `x`
+  /// for an entity the game does not know.
+  fn level(rows: &[&str], grid_size: i32) -> Level {
+    let registry = TerrainRegistry::from_bytes(TERRAIN).unwrap();
+    let (width, height) = (rows[0].len() as i32, rows.len() as i32);
+
+    let mut int_grid_csv = Vec::new();
+    let mut entity_instances = Vec::new();
+    for (y, row) in rows.iter().enumerate() {
+      for (x, cell) in row.chars().enumerate() {
+        int_grid_csv.push(match cell {
+          'M' | 'B' => registry.by_id("mountains").unwrap().value,
+          '?' => 99,
+          _ => registry.by_id("grass").unwrap().value,
+        });
+        let identifier = match cell {
+          'b' | 'B' => BLUE_SPAWN,
+          'r' => RED_SPAWN,
+          'x' => "TREASURE",
+          _ => continue,
+        };
+        entity_instances.push(EntityInstance {
+          identifier: identifier.to_string(),
+          grid: IVec2::new(x as i32, y as i32),
+          ..Default::default()
+        });
+      }
+    }
+
+    Level {
+      px_wid: width * GRID_SIZE,
+      px_hei: height * GRID_SIZE,
+      layer_instances: Some(vec![
+        LayerInstance {
+          identifier: "Entities".to_string(),
+          layer_instance_type: Type::Entities,
+          c_wid: width,
+          c_hei: height,
+          grid_size,
+          entity_instances,
+          ..Default::default()
+        },
+        LayerInstance {
+          identifier: "Terrain".to_string(),
+          layer_instance_type: Type::IntGrid,
+          c_wid: width,
+          c_hei: height,
+          grid_size: GRID_SIZE,
+          int_grid_csv,
+          ..Default::default()
+        },
+      ]),
+      ..Default::default()
+    }
+  }
+
+  fn validate(level: &Level) -> Vec<LevelProblem> {
+    validate_level(
+      level,
+      &UnitSpawnQueues::default(),
+      &TerrainRegistry::from_bytes(TERRAIN).unwrap(),
+    )
+  }
+
+  #[test]
+  fn a_playable_level_has_no_problems() {
+    let level = level(&["bb...rr", "b.....r", "bb...rr"], GRID_SIZE);
+    assert_eq!(validate(&level), Vec::new());
+  }
+
+  #[test]
+  fn spawns_cut_off_from_the_enemy_are_reported() {
+    let level = level(&["bb.M.rr", "b..M..r", "bb.M.rr"], GRID_SIZE);
+    let problems = validate(&level);
+    assert_eq!(problems.len(), 10);
+    assert!(problems
+      .iter()
+      .all(|problem| matches!(problem, LevelProblem::UnreachableSpawn { .. })));
+  }
+
+  #[test]
+  fn spawns_on_impassable_terrain_are_reported() {
+    let level = level(&["Bb...rr", "b.....r", "bb...rr"], GRID_SIZE);
+    assert_eq!(
+      validate(&level),
+      vec![LevelProblem::ImpassableSpawn {
+        side: TurnState::Player1,
+        grid_coords: GridCoords::new(0, 2),
+        terrain: Some(
+          TerrainRegistry::from_bytes(TERRAIN)
+            .unwrap()
+            .by_id("mountains")
+            .unwrap()
+            .name
+            .clone()
+        ),
+      }]
+    );
+  }
+
+  #[test]
+  fn spawn_counts_must_match_the_queues() {
+    let level = level(&["bb...rr", "......r", "bb...rr"], GRID_SIZE);
+    assert_eq!(
+      validate(&level),
+      vec![LevelProblem::SpawnCount {
+        side: TurnState::Player1,
+        spawns: 4,
+        queued: 5,
+      }]
+    );
+  }
+
+  #[test]
+  fn unknown_entities_terrain_and_grid_sizes_are_reported() {
+    let level = level(&["bb..?rr", "b..x..r", "bb..?rr"], 8);
+    let problems = validate(&level);
+    assert!(problems.contains(&LevelProblem::GridSize {
+      layer: "Entities".to_string(),
+      grid_size: 8,
+    }));
+    assert!(problems.contains(&LevelProblem::UnknownEntity {
+      identifier: "TREASURE".to_string(),
+      grid_coords: GridCoords::new(3, 1),
+    }));
+    assert!(problems.contains(&LevelProblem::UnknownTerrain {
+      value: 99,
+      cells: 2
+    }));
+  }
+
+  #[test]
+  fn a_level_without_terrain_is_reported() {
+    let mut level = level(&["bb...rr", "b.....r", "bb...rr"], GRID_SIZE);
+    level.layer_instances.as_mut().unwrap().pop();
+    assert_eq!(validate(&level), vec![LevelProblem::NoTerrain]);
+  }
+}
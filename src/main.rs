@@ -1,11 +1,13 @@
 use bevy::prelude::*;
 use bevy_ecs_ldtk::LdtkPlugin;
 use bevy_ecs_tilemap::TilemapPlugin;
+#[cfg(not(feature = "dev"))]
 use bevy_embedded_assets::{EmbeddedAssetPlugin, PluginMode};
-use bevy_titan::SpriteSheetLoaderPlugin;
 
 mod assets;
 mod game;
+mod menus;
+mod sprites;
 mod tiles;
 mod util;
 mod windows;
@@ -14,17 +16,29 @@ mod windows;
 pub enum GlobalState {
   #[default]
   Loading,
+  MainMenu,
+  MatchSetup,
   Game,
+  Paused,
+  LoadFailed,
 }
 
 fn main() {
-  App::new()
+  let mut app = App::new();
+  // the dev feature reads assets from the assets directory instead, and
+  // reloads them as they are saved
+  #[cfg(not(feature = "dev"))]
+  app.add_plugins(EmbeddedAssetPlugin {
+    mode: PluginMode::ReplaceDefault,
+  });
+  app
     .add_plugins((
-      EmbeddedAssetPlugin {
-        mode: PluginMode::ReplaceDefault,
-      },
-      DefaultPlugins.set(ImagePlugin::default_nearest()),
-      SpriteSheetLoaderPlugin,
+      DefaultPlugins
+        .set(ImagePlugin::default_nearest())
+        .set(AssetPlugin {
+          watch_for_changes_override: Some(cfg!(feature = "dev")),
+          ..default()
+        }),
       TilemapPlugin,
       LdtkPlugin,
     ))
@@ -32,8 +46,77 @@ fn main() {
     .init_state::<GlobalState>()
     .add_plugins((
       assets::LoadAssetsPlugin,
+      sprites::SpritesPlugin,
       tiles::TilesPlugin,
       game::GamePlugin,
-    ))
-    .run();
+      windows::WindowsPlugin,
+      menus::MenusPlugin,
+    ));
+
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    if arg == "--replay" {
+      match args.next() {
+        Some(path) => {
+          app.insert_resource(game::replay::ReplayPath(path.into()));
+          app.insert_resource(game::StartMatch);
+        }
+        None => warn!("--replay expects a path to a replay file"),
+      }
+    } else if arg == "--load" {
+      match args
+        .next()
+        .map(|path| game::save::SaveGame::load(path.as_ref()))
+      {
+        Some(Ok(save_game)) => {
+          app.insert_resource(game::MatchConfig {
+            level: save_game.level,
+            seed: save_game.seed,
+            generated: save_game.generated,
+          });
+          app.insert_resource(game::save::PendingLoad(save_game));
+          app.insert_resource(game::StartMatch);
+        }
+        Some(Err(err)) => error!("{}", err),
+        None => warn!("--load expects a path to a save file"),
+      }
+    } else if arg == "--generate" {
+      match args
+        .next()
+        .map(|size| size.parse::<game::mapgen::GeneratedMap>())
+      {
+        Some(Ok(map)) => {
+          app.world.resource_mut::<game::MatchConfig>().generated = Some(map);
+          app.insert_resource(game::StartMatch);
+        }
+        Some(Err(err)) => error!("{}", err),
+        None => warn!("--generate expects a size such as 32x16"),
+      }
+    } else if arg == "--seed" {
+      match args.next().map(|seed| seed.parse::<u64>()) {
+        Some(Ok(seed)) => {
+          app.world.resource_mut::<game::MatchConfig>().seed = seed;
+        }
+        _ => warn!("--seed expects a number"),
+      }
+    } else if arg == "--host" {
+      match args.next().map(|port| port.parse::<u16>()) {
+        Some(Ok(port)) => {
+          app.insert_resource(game::net::NetConfig::Host { port });
+          app.insert_resource(game::StartMatch);
+        }
+        _ => warn!("--host expects a port number"),
+      }
+    } else if arg == "--join" {
+      match args.next() {
+        Some(address) => {
+          app.insert_resource(game::net::NetConfig::Join { address });
+          app.insert_resource(game::StartMatch);
+        }
+        None => warn!("--join expects an address such as 127.0.0.1:7777"),
+      }
+    }
+  }
+
+  app.run();
 }
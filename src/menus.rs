@@ -0,0 +1,509 @@
+use bevy::{app::AppExit, prelude::*};
+use bevy_ecs_ldtk::assets::LdtkProject;
+use bevy_ecs_tilemap::tiles::TilePos;
+
+use crate::{
+  assets::LdtkWorldHandle,
+  game::{
+    autosave::{self, AutosaveSettings, RecoveryOffer},
+    input::{Action, Actions},
+    mapgen::{GeneratedMap, GENERATED_SIZES},
+    net::{self, ConnectionLost, NetConfig, NetSession, DEFAULT_PORT},
+    replay::ReplayViewer,
+    rng::MatchRng,
+    save::{capture_save_game, quicksave},
+    units::{Unit, UnitAssociation, UnitKind},
+    validate::{self, LevelProblems},
+    MatchConfig, StartMatch, TurnNumber, TurnState,
+  },
+  windows::{
+    navigate_list_menus, spawn_list_menu, ListMenu, MenuCancelled, MenuChosen,
+    WindowAssets, WindowFocus,
+  },
+  GlobalState,
+};
+
+/// How many autosaves the match setup lets the player keep.
+const AUTOSAVE_LIMITS: [usize; 4] = [4, 8, 16, 32];
+
+pub struct MenusPlugin;
+
+impl Plugin for MenusPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<SetupChoices>()
+      .add_systems(
+        OnEnter(GlobalState::MainMenu),
+        (
+          spawn_menu_camera,
+          show_main_menu,
+          // opened last, so that it takes focus ahead of the main menu
+          autosave::show_recovery_prompt
+            .run_if(resource_exists::<RecoveryOffer>),
+          validate::show_level_problems
+            .run_if(resource_exists::<LevelProblems>),
+          net::show_connection_lost.run_if(resource_exists::<ConnectionLost>),
+        )
+          .chain(),
+      )
+      .add_systems(OnExit(GlobalState::MainMenu), despawn_menu_screen)
+      .add_systems(
+        OnEnter(GlobalState::MatchSetup),
+        (spawn_menu_camera, show_match_setup).chain(),
+      )
+      .add_systems(OnExit(GlobalState::MatchSetup), despawn_menu_screen)
+      .add_systems(OnEnter(GlobalState::Paused), show_pause_menu)
+      .add_systems(OnExit(GlobalState::Paused), despawn_menu_screen)
+      .add_systems(
+        Update,
+        (
+          (
+            answer_main_menu,
+            autosave::answer_recovery_prompt
+              .run_if(resource_exists::<RecoveryOffer>),
+            validate::answer_level_problems
+              .run_if(resource_exists::<LevelProblems>),
+            net::answer_connection_lost
+              .run_if(resource_exists::<ConnectionLost>),
+          )
+            .run_if(in_state(GlobalState::MainMenu)),
+          answer_match_setup.run_if(in_state(GlobalState::MatchSetup)),
+          pause_match.run_if(in_state(GlobalState::Game)),
+          answer_pause_menu.run_if(in_state(GlobalState::Paused)),
+        )
+          .after(navigate_list_menus),
+      );
+  }
+}
+
+/// Marks everything spawned for a menu screen, so that it can all be
+/// despawned when the screen is left.
+#[derive(Default, Component)]
+struct MenuScreen;
+
+#[derive(Default, Component)]
+struct MainMenu;
+
+#[derive(Default, Component)]
+struct MatchSetupMenu;
+
+/// The pause menu, holding what each of its lines does.
+#[derive(Component)]
+struct PauseMenu(Vec<PauseOption>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseOption {
+  Resume,
+  Restart,
+  Save,
+  Quit,
+}
+
+impl PauseOption {
+  fn label(&self) -> &'static str {
+    match self {
+      PauseOption::Resume => "Resume",
+      PauseOption::Restart => "Restart match",
+      PauseOption::Save => "Save",
+      PauseOption::Quit => "Quit to main menu",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opponent {
+  Local,
+  Online,
+}
+
+/// What the match setup screen has chosen so far, kept between visits.
+#[derive(Resource)]
+struct SetupChoices {
+  /// Either one of the project's levels, or past the end of them, one of
+  /// the [`GENERATED_SIZES`].
+  level: usize,
+  opponent: Opponent,
+}
+
+impl Default for SetupChoices {
+  fn default() -> Self {
+    SetupChoices {
+      level: 0,
+      opponent: Opponent::Local,
+    }
+  }
+}
+
+// lines of the match setup menu
+const SETUP_MAP: usize = 0;
+const SETUP_OPPONENT: usize = 1;
+const SETUP_AUTOSAVES: usize = 2;
+const SETUP_START: usize = 3;
+const SETUP_BACK: usize = 4;
+
+fn setup_labels(
+  choices: &SetupChoices,
+  level_names: &[String],
+  autosave_settings: &AutosaveSettings,
+) -> Vec<String> {
+  let map = match generated_map(choices, level_names) {
+    Some(generated) => generated.name(),
+    None => level_names
+      .get(choices.level)
+      .cloned()
+      .unwrap_or_else(|| format!("Level {}", choices.level + 1)),
+  };
+  let opponent = match choices.opponent {
+    Opponent::Local => "Red: local player".to_string(),
+    Opponent::Online => {
+      format!("Red: online (hosting on port {})", DEFAULT_PORT)
+    }
+  };
+
+  vec![
+    format!("Map: {}", map),
+    opponent,
+    format!("Autosaved turns: {}", autosave_settings.limit),
+    "Start match".to_string(),
+    "Back".to_string(),
+  ]
+}
+
+/// The generated level chosen, if the choice is past the project's levels.
+fn generated_map(
+  choices: &SetupChoices,
+  level_names: &[String],
+) -> Option<GeneratedMap> {
+  choices
+    .level
+    .checked_sub(level_names.len())
+    .and_then(|index| GENERATED_SIZES.get(index).copied())
+}
+
+fn level_names(
+  ldtk_handle: &LdtkWorldHandle,
+  projects: &Assets<LdtkProject>,
+) -> Vec<String> {
+  projects
+    .get(&**ldtk_handle)
+    .map(|project| {
+      project
+        .json_data()
+        .levels
+        .iter()
+        .map(|level| level.identifier.clone())
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn menu_style() -> Style {
+  Style {
+    position_type: PositionType::Absolute,
+    left: Val::Px(32.0),
+    top: Val::Px(32.0),
+    ..default()
+  }
+}
+
+fn spawn_menu_camera(mut commands: Commands) {
+  commands.spawn((Camera2dBundle::default(), MenuScreen));
+}
+
+fn despawn_menu_screen(
+  mut commands: Commands,
+  menu_entities: Query<Entity, With<MenuScreen>>,
+) {
+  for menu_entity in menu_entities.iter() {
+    commands.entity(menu_entity).despawn_recursive();
+  }
+}
+
+fn show_main_menu(
+  mut commands: Commands,
+  window_assets: Res<WindowAssets>,
+  mut window_focus: ResMut<WindowFocus>,
+) {
+  spawn_list_menu(
+    &mut commands,
+    &window_assets,
+    &mut window_focus,
+    menu_style(),
+    Some("Holmium"),
+    &["New match".to_string(), "Quit".to_string()],
+    (MainMenu, MenuScreen),
+  );
+}
+
+fn answer_main_menu(
+  mut chosen_events: EventReader<MenuChosen>,
+  main_menus: Query<Entity, With<MainMenu>>,
+  mut app_exits: EventWriter<AppExit>,
+  mut next_global_state: ResMut<NextState<GlobalState>>,
+) {
+  let Ok(main_menu) = main_menus.get_single() else {
+    return;
+  };
+
+  for chosen in chosen_events.read() {
+    if chosen.menu != main_menu {
+      continue;
+    }
+    match chosen.index {
+      0 => next_global_state.set(GlobalState::MatchSetup),
+      _ => {
+        app_exits.send(AppExit);
+      }
+    }
+  }
+}
+
+fn show_match_setup(
+  mut commands: Commands,
+  window_assets: Res<WindowAssets>,
+  mut window_focus: ResMut<WindowFocus>,
+  choices: Res<SetupChoices>,
+  autosave_settings: Res<AutosaveSettings>,
+  ldtk_handle: Res<LdtkWorldHandle>,
+  projects: Res<Assets<LdtkProject>>,
+) {
+  spawn_list_menu(
+    &mut commands,
+    &window_assets,
+    &mut window_focus,
+    menu_style(),
+    Some("New match"),
+    &setup_labels(
+      &choices,
+      &level_names(&ldtk_handle, &projects),
+      &autosave_settings,
+    ),
+    (MatchSetupMenu, MenuScreen),
+  );
+}
+
+/// Steps the selected setting with left and right (or forwards when it is
+/// chosen), and starts the match once the player is done.
+#[allow(clippy::too_many_arguments)]
+fn answer_match_setup(
+  mut commands: Commands,
+  actions: Res<Actions>,
+  window_focus: Res<WindowFocus>,
+  mut chosen_events: EventReader<MenuChosen>,
+  mut cancelled_events: EventReader<MenuCancelled>,
+  mut setup_menus: Query<(Entity, &mut ListMenu), With<MatchSetupMenu>>,
+  mut choices: ResMut<SetupChoices>,
+  mut autosave_settings: ResMut<AutosaveSettings>,
+  ldtk_handle: Res<LdtkWorldHandle>,
+  projects: Res<Assets<LdtkProject>>,
+  mut next_global_state: ResMut<NextState<GlobalState>>,
+) {
+  let Ok((setup_menu, mut menu)) = setup_menus.get_single_mut() else {
+    return;
+  };
+  if window_focus.top() != Some(setup_menu) {
+    return;
+  }
+
+  if cancelled_events
+    .read()
+    .any(|cancelled| cancelled.menu == setup_menu)
+  {
+    next_global_state.set(GlobalState::MainMenu);
+    return;
+  }
+
+  let mut step = 0;
+  if actions.any_just_pressed(Action::Left) {
+    step -= 1;
+  }
+  if actions.any_just_pressed(Action::Right) {
+    step += 1;
+  }
+  let chosen = chosen_events
+    .read()
+    .filter(|chosen| chosen.menu == setup_menu)
+    .last()
+    .map(|chosen| chosen.index);
+  match chosen {
+    Some(SETUP_START) => {
+      let level_names = level_names(&ldtk_handle, &projects);
+      commands.insert_resource(match generated_map(&choices, &level_names) {
+        Some(generated) => MatchConfig {
+          generated: Some(generated),
+          ..default()
+        },
+        None => MatchConfig {
+          level: choices.level,
+          ..default()
+        },
+      });
+      if choices.opponent == Opponent::Online {
+        commands.insert_resource(NetConfig::Host { port: DEFAULT_PORT });
+      }
+      commands.insert_resource(StartMatch);
+      next_global_state.set(GlobalState::Loading);
+      return;
+    }
+    Some(SETUP_BACK) => {
+      next_global_state.set(GlobalState::MainMenu);
+      return;
+    }
+    Some(_) => step += 1,
+    None => {}
+  }
+  if step == 0 {
+    return;
+  }
+
+  let level_names = level_names(&ldtk_handle, &projects);
+  match menu.selected {
+    SETUP_MAP => {
+      let level_count = (level_names.len() + GENERATED_SIZES.len()) as isize;
+      choices.level =
+        (choices.level as isize + step).rem_euclid(level_count) as usize;
+    }
+    SETUP_OPPONENT => {
+      choices.opponent = match choices.opponent {
+        Opponent::Local => Opponent::Online,
+        Opponent::Online => Opponent::Local,
+      };
+    }
+    SETUP_AUTOSAVES => {
+      let current = AUTOSAVE_LIMITS
+        .iter()
+        .position(|limit| *limit == autosave_settings.limit)
+        .unwrap_or(0) as isize;
+      let next = (current + step).rem_euclid(AUTOSAVE_LIMITS.len() as isize);
+      autosave_settings.limit = AUTOSAVE_LIMITS[next as usize];
+    }
+    _ => return,
+  }
+
+  for (index, label) in setup_labels(&choices, &level_names, &autosave_settings)
+    .into_iter()
+    .enumerate()
+  {
+    menu.set_item(index, label);
+  }
+}
+
+fn pause_match(
+  actions: Res<Actions>,
+  window_focus: Res<WindowFocus>,
+  mut next_global_state: ResMut<NextState<GlobalState>>,
+) {
+  if window_focus.is_empty() && actions.any_just_pressed(Action::Pause) {
+    next_global_state.set(GlobalState::Paused);
+  }
+}
+
+fn show_pause_menu(
+  mut commands: Commands,
+  window_assets: Res<WindowAssets>,
+  mut window_focus: ResMut<WindowFocus>,
+  replay_viewer: Option<Res<ReplayViewer>>,
+  net_session: Option<Res<NetSession>>,
+) {
+  // neither a replay nor a networked match can be rewound or saved locally
+  let local_match = replay_viewer.is_none() && net_session.is_none();
+  let options = [
+    Some(PauseOption::Resume),
+    local_match.then_some(PauseOption::Restart),
+    local_match.then_some(PauseOption::Save),
+    Some(PauseOption::Quit),
+  ]
+  .into_iter()
+  .flatten()
+  .collect::<Vec<_>>();
+
+  // dims the board behind the menu
+  commands.spawn((
+    NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        width: Val::Percent(100.0),
+        height: Val::Percent(100.0),
+        ..default()
+      },
+      background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+      z_index: ZIndex::Global(5),
+      ..default()
+    },
+    MenuScreen,
+  ));
+
+  let labels = options
+    .iter()
+    .map(|option| option.label().to_string())
+    .collect::<Vec<_>>();
+  spawn_list_menu(
+    &mut commands,
+    &window_assets,
+    &mut window_focus,
+    menu_style(),
+    Some("Paused"),
+    &labels,
+    (PauseMenu(options), MenuScreen),
+  );
+}
+
+#[allow(clippy::too_many_arguments)]
+fn answer_pause_menu(
+  mut commands: Commands,
+  actions: Res<Actions>,
+  mut chosen_events: EventReader<MenuChosen>,
+  mut cancelled_events: EventReader<MenuCancelled>,
+  pause_menus: Query<(Entity, &PauseMenu)>,
+  match_config: Res<MatchConfig>,
+  turn_state: Res<State<TurnState>>,
+  turn_number: Res<TurnNumber>,
+  rng: Res<MatchRng>,
+  units: Query<(&UnitKind, &UnitAssociation, &TilePos, &Unit)>,
+  mut next_global_state: ResMut<NextState<GlobalState>>,
+) {
+  let Ok((pause_menu_entity, pause_menu)) = pause_menus.get_single() else {
+    return;
+  };
+
+  let chosen = chosen_events
+    .read()
+    .filter(|chosen| chosen.menu == pause_menu_entity)
+    .last()
+    .and_then(|chosen| pause_menu.0.get(chosen.index).copied());
+  let resumed = actions.any_just_pressed(Action::Pause)
+    || cancelled_events
+      .read()
+      .any(|cancelled| cancelled.menu == pause_menu_entity);
+
+  match chosen {
+    Some(PauseOption::Resume) => next_global_state.set(GlobalState::Game),
+    Some(PauseOption::Restart) => {
+      // the match config is left as it is, so the same level and seed are
+      // played again
+      info!("restarting match");
+      commands.insert_resource(StartMatch);
+      next_global_state.set(GlobalState::Loading);
+    }
+    Some(PauseOption::Save) => {
+      quicksave(&capture_save_game(
+        &match_config,
+        *turn_state.get(),
+        &turn_number,
+        &rng,
+        &units,
+      ));
+      next_global_state.set(GlobalState::Game);
+    }
+    Some(PauseOption::Quit) => {
+      // the world is torn down on entering the main menu, but the network
+      // session belongs to the whole match rather than to one world.
+      // Dropping it tells the peer and frees the port for the next match.
+      commands.remove_resource::<NetSession>();
+      commands.remove_resource::<NetConfig>();
+      next_global_state.set(GlobalState::MainMenu);
+    }
+    None if resumed => next_global_state.set(GlobalState::Game),
+    None => {}
+  }
+}
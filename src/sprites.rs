@@ -0,0 +1,218 @@
+use bevy::{
+  app::{App, Plugin, Update},
+  asset::{
+    io::Reader, Asset, AssetApp, AssetEvent, AssetLoader, Assets, AsyncReadExt,
+    Handle, LoadContext,
+  },
+  ecs::{
+    event::EventReader,
+    system::{Res, ResMut, Resource, SystemParam},
+  },
+  log::warn,
+  math::{Rect, Vec2},
+  reflect::TypePath,
+  sprite::TextureAtlasLayout,
+  utils::{BoxedFuture, HashMap},
+};
+use serde::Deserialize;
+
+use crate::{assets::AtlasInfo, game::TurnState};
+
+/// Where the sprite manifest lives, relative to the assets directory.
+pub const SPRITE_MANIFEST_PATH: &str = "holmium.sprites.ron";
+
+/// Drawn in place of any sprite the manifest does not name.
+const MISSING_SPRITE: &str = "empty";
+
+pub struct SpritesPlugin;
+
+impl Plugin for SpritesPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_asset::<SpriteManifest>()
+      .init_asset_loader::<SpriteManifestLoader>()
+      .add_systems(Update, update_atlas_layout);
+  }
+}
+
+/// Names every sprite in `tilemap.png`, so that the game never depends on
+/// where in the sheet a sprite happens to be.
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct SpriteManifest {
+  /// The width and height of every tile in the sheet, in pixels.
+  pub tile_size: f32,
+  pub columns: usize,
+  pub rows: usize,
+  /// Each sprite's column and row in the sheet, counted from the top left.
+  /// Sprites drawn differently for each side are named with the faction
+  /// after an `@`, such as `arrow/head_left@red`.
+  sprites: HashMap<String, (usize, usize)>,
+}
+
+impl SpriteManifest {
+  /// The atlas index of the sprite called `name`.
+  pub fn get(&self, name: &str) -> Option<usize> {
+    let (column, row) = self.sprites.get(name)?;
+    Some(row * self.columns + column)
+  }
+
+  /// The area of the sheet the sprite called `name` covers, in pixels.
+  pub fn rect(&self, name: &str) -> Option<Rect> {
+    let (column, row) = self.sprites.get(name)?;
+    let min = Vec2::new(*column as f32, *row as f32) * self.tile_size;
+    Some(Rect::from_corners(min, min + Vec2::splat(self.tile_size)))
+  }
+
+  /// The size of the whole sheet, in pixels.
+  pub fn size(&self) -> Vec2 {
+    Vec2::new(self.columns as f32, self.rows as f32) * self.tile_size
+  }
+}
+
+#[derive(Resource)]
+pub struct SpriteManifestHandle(pub Handle<SpriteManifest>);
+
+/// Looks sprites up by name in the loaded manifest.
+#[derive(SystemParam)]
+pub struct Sprites<'w> {
+  handle: Res<'w, SpriteManifestHandle>,
+  manifests: Res<'w, Assets<SpriteManifest>>,
+}
+
+impl Sprites<'_> {
+  /// The atlas index of the sprite called `name`. A missing sprite is drawn
+  /// as an empty tile rather than stopping the game.
+  pub fn index(&self, name: &str) -> usize {
+    let Some(manifest) = self.manifests.get(&self.handle.0) else {
+      return 0;
+    };
+    manifest.get(name).unwrap_or_else(|| {
+      warn!("no sprite is named {}", name);
+      manifest.get(MISSING_SPRITE).unwrap_or(0)
+    })
+  }
+
+  /// The atlas index of `name` as drawn for `turn_state`'s side, falling
+  /// back to the plain sprite for sides without one of their own.
+  pub fn faction_index(&self, name: &str, turn_state: TurnState) -> usize {
+    let faction_name = format!(
+      "{}@{}",
+      name,
+      turn_state.faction_name().to_ascii_lowercase()
+    );
+    match self
+      .manifests
+      .get(&self.handle.0)
+      .and_then(|manifest| manifest.get(&faction_name))
+    {
+      Some(index) => index,
+      None => self.index(name),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum SpriteManifestError {
+  Io(std::io::Error),
+  Parse(ron::error::SpannedError),
+  OutOfBounds(String, usize, usize),
+}
+
+impl std::fmt::Display for SpriteManifestError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SpriteManifestError::Io(err) => write!(f, "could not read: {}", err),
+      SpriteManifestError::Parse(err) => {
+        write!(f, "could not parse: {}", err)
+      }
+      SpriteManifestError::OutOfBounds(name, column, row) => write!(
+        f,
+        "sprite {} at column {}, row {} lies outside the sheet",
+        name, column, row
+      ),
+    }
+  }
+}
+
+impl std::error::Error for SpriteManifestError {}
+
+impl From<std::io::Error> for SpriteManifestError {
+  fn from(err: std::io::Error) -> Self {
+    SpriteManifestError::Io(err)
+  }
+}
+
+impl From<ron::error::SpannedError> for SpriteManifestError {
+  fn from(err: ron::error::SpannedError) -> Self {
+    SpriteManifestError::Parse(err)
+  }
+}
+
+/// Reads `.sprites.ron` files into a [`SpriteManifest`].
+#[derive(Default)]
+pub struct SpriteManifestLoader;
+
+impl AssetLoader for SpriteManifestLoader {
+  type Asset = SpriteManifest;
+  type Settings = ();
+  type Error = SpriteManifestError;
+
+  fn load<'a>(
+    &'a self,
+    reader: &'a mut Reader,
+    _settings: &'a (),
+    _load_context: &'a mut LoadContext,
+  ) -> BoxedFuture<'a, Result<SpriteManifest, SpriteManifestError>> {
+    Box::pin(async move {
+      let mut bytes = Vec::new();
+      reader.read_to_end(&mut bytes).await?;
+      let manifest: SpriteManifest = ron::de::from_bytes(&bytes)?;
+
+      for (name, (column, row)) in manifest.sprites.iter() {
+        if *column >= manifest.columns || *row >= manifest.rows {
+          return Err(SpriteManifestError::OutOfBounds(
+            name.clone(),
+            *column,
+            *row,
+          ));
+        }
+      }
+      Ok(manifest)
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["sprites.ron"]
+  }
+}
+
+/// Lays the shared atlas out over the sheet as the manifest describes it,
+/// whenever the manifest loads.
+fn update_atlas_layout(
+  mut manifest_events: EventReader<AssetEvent<SpriteManifest>>,
+  handle: Res<SpriteManifestHandle>,
+  manifests: Res<Assets<SpriteManifest>>,
+  atlas_info: Res<AtlasInfo>,
+  mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+  let loaded = manifest_events.read().any(|event| {
+    matches!(
+      event,
+      AssetEvent::LoadedWithDependencies { .. } | AssetEvent::Modified { .. }
+    )
+  });
+  let Some(manifest) = manifests.get(&handle.0).filter(|_| loaded) else {
+    return;
+  };
+
+  layouts.insert(
+    &atlas_info.layout,
+    TextureAtlasLayout::from_grid(
+      Vec2::splat(manifest.tile_size),
+      manifest.columns,
+      manifest.rows,
+      None,
+      None,
+    ),
+  );
+}
@@ -1,160 +1,326 @@
 use bevy::{
-  app::{App, Plugin},
+  app::{App, Plugin, Update},
+  asset::{
+    io::Reader, Asset, AssetApp, AssetEvent, AssetLoader, Assets, AsyncReadExt,
+    Handle, LoadContext,
+  },
   ecs::{
-    bundle::Bundle,
-    component::Component,
     event::EventReader,
-    query::With,
-    system::{Commands, Query, Resource},
+    schedule::{common_conditions::resource_exists, IntoSystemConfigs},
+    system::{Commands, Query, Res, ResMut, Resource, SystemParam},
   },
   log::info,
-  utils::HashSet,
-};
-use bevy_ecs_ldtk::{
-  app::LdtkIntCellAppExt, GridCoords, LdtkIntCell, LevelEvent,
+  reflect::TypePath,
+  utils::{BoxedFuture, HashMap, HashSet},
 };
+use bevy_ecs_ldtk::{GridCoords, IntGridCell, LevelEvent};
+use serde::Deserialize;
 
-pub const TILESET_WIDTH: usize = 12;
-pub const TILESET_HEIGHT: usize = 6;
-
-pub const EMPTY: usize = index(9, 5);
-
-// grass variations
-pub const GRASS: usize = index(0, 0);
-pub const GRASS_TUFTS: usize = index(1, 0);
-pub const GRASS_FLOWERS: usize = index(2, 0);
-
-// water variations
-pub const WATER_LAKE: usize = index(1, 2);
-pub const WATER_LAKE_L: usize = index(0, 2);
-pub const WATER_LAKE_R: usize = index(2, 2);
-pub const WATER_LAKE_U: usize = index(1, 1);
-pub const WATER_LAKE_D: usize = index(1, 3);
-pub const WATER_LAKE_UL: usize = index(0, 1);
-pub const WATER_LAKE_UR: usize = index(2, 1);
-pub const WATER_LAKE_DL: usize = index(0, 3);
-pub const WATER_LAKE_DR: usize = index(2, 3);
-pub const WATER_LAKE_CORNER_UL: usize = index(0, 4);
-pub const WATER_LAKE_CORNER_UR: usize = index(1, 4);
-pub const WATER_LAKE_CORNER_DL: usize = index(2, 4);
-pub const WATER_LAKE_CORNER_DR: usize = index(3, 4);
-pub const WATER_RIVER_V: usize = index(3, 2);
-pub const WATER_RIVER_H: usize = index(4, 2);
-pub const WATER_RIVER_MOUTH_L: usize = index(5, 2);
-pub const WATER_RIVER_MOUTH_R: usize = index(5, 3);
-pub const WATER_RIVER_MOUTH_U: usize = index(5, 0);
-pub const WATER_RIVER_MOUTH_D: usize = index(5, 1);
-pub const WATER_RIVER_UL: usize = index(3, 0);
-pub const WATER_RIVER_UR: usize = index(4, 0);
-pub const WATER_RIVER_DL: usize = index(3, 1);
-pub const WATER_RIVER_DR: usize = index(4, 1);
-
-// arrow variations
-pub const ARROW_BODY_V: usize = index(7, 4);
-pub const ARROW_BODY_H: usize = index(6, 4);
-pub const ARROW_BODY_UL: usize = index(6, 2);
-pub const ARROW_BODY_UR: usize = index(7, 2);
-pub const ARROW_BODY_DL: usize = index(6, 3);
-pub const ARROW_BODY_DR: usize = index(7, 3);
-pub const ARROW_HEAD_L: usize = index(6, 1);
-pub const ARROW_HEAD_R: usize = index(7, 1);
-pub const ARROW_HEAD_U: usize = index(6, 0);
-pub const ARROW_HEAD_D: usize = index(7, 0);
-pub const ARROW_RED_OFFSET: usize = index(2, 0);
-
-// highlight/zone variations
-pub const CURSOR: usize = index(0, 5);
-pub const CURSOR_BLUE_OFFSET: usize = index(1, 0);
-pub const CURSOR_RED_OFFSET: usize = index(2, 0);
-pub const ZONE_MOVE: usize = index(3, 5);
-pub const ZONE_MELEE: usize = index(4, 5);
-
-// terrain variations
-pub const FOREST: usize = index(4, 4);
-pub const MOUNTAINS: usize = index(5, 4);
-
-// turn association backdrops
-pub const BACKDROP_BLUE: usize = index(7, 5);
-pub const BACKDROP_RED: usize = index(8, 5);
-
-// units
-pub const UNIT_WIZARD: usize = index(10, 1);
-pub const UNIT_ARCHER: usize = index(11, 0);
-pub const UNIT_BARBARIAN: usize = index(11, 1);
-pub const UNIT_KNIGHT: usize = index(10, 0);
-pub const UNIT_CLERIC: usize = index(10, 2);
-
-#[inline]
-const fn index(col: usize, row: usize) -> usize {
-  row * TILESET_WIDTH + col
-}
-
-#[derive(Default, Component)]
-pub struct Watery;
-#[derive(Default, Bundle, LdtkIntCell)]
-pub struct WateryBundle {
-  watery: Watery,
-}
-
-#[derive(Default, Component)]
-pub struct Grassy;
-#[derive(Default, Bundle, LdtkIntCell)]
-pub struct GrassyBundle {
-  grassy: Grassy,
-}
-
-#[derive(Default, Component)]
-pub struct Mountainous;
-#[derive(Default, Bundle, LdtkIntCell)]
-pub struct MountainousBundle {
-  mountainous: Mountainous,
-}
-
-#[derive(Default, Component)]
-pub struct Forested;
-#[derive(Default, Bundle, LdtkIntCell)]
-pub struct ForestedBundle {
-  forested: Forested,
-}
+use crate::game::LevelSize;
+
+/// Where the terrain registry lives, relative to the assets directory.
+pub const TERRAIN_REGISTRY_PATH: &str = "holmium.terrain.ron";
 
 pub struct TilesPlugin;
 
 impl Plugin for TilesPlugin {
   fn build(&self, app: &mut App) {
     app
-      .register_ldtk_int_cell::<WateryBundle>(1)
-      .register_ldtk_int_cell::<GrassyBundle>(2)
-      .register_ldtk_int_cell::<MountainousBundle>(3)
-      .register_ldtk_int_cell::<ForestedBundle>(4);
+      .init_asset::<TerrainRegistry>()
+      .init_asset_loader::<TerrainRegistryLoader>()
+      .add_systems(
+        Update,
+        refresh_tile_types.run_if(resource_exists::<TileTypes>),
+      );
+  }
+}
+
+/// How a unit gets about, which decides what each terrain costs it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+pub enum MovementClass {
+  #[default]
+  Foot,
+  Mounted,
+}
+
+impl MovementClass {
+  pub const ALL: [MovementClass; 2] =
+    [MovementClass::Foot, MovementClass::Mounted];
+}
+
+/// How a terrain is drawn on the terrain layer.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub enum TerrainLook {
+  /// The same sprite everywhere.
+  Tile(String),
+  /// One of several sprites, each picked in proportion to its weight.
+  Scattered(Vec<(String, u32)>),
+  /// Lake and river tiles, joined up with the water around them.
+  Water,
+}
+
+/// One kind of terrain, as written in the terrain registry.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Terrain {
+  /// The IntGrid value that paints this terrain in a level.
+  pub value: i32,
+  /// What the level generator calls this terrain.
+  pub id: String,
+  /// The name shown to players.
+  pub name: String,
+  /// What it costs each movement class to step onto this terrain. Classes
+  /// left out cannot cross it at all.
+  #[serde(default)]
+  pub move_costs: HashMap<MovementClass, usize>,
+  /// How much damage is taken off attacks on a unit standing here.
+  #[serde(default)]
+  pub defence: i32,
+  /// Whether units cannot see past this terrain.
+  #[serde(default)]
+  pub blocks_sight: bool,
+  /// How much further a unit standing here sees.
+  #[serde(default)]
+  pub sight_bonus: i32,
+  pub look: TerrainLook,
+}
+
+impl Terrain {
+  /// What it costs a unit moving by `movement` to step onto this terrain,
+  /// or `None` if it cannot cross it at all.
+  pub fn move_cost(&self, movement: MovementClass) -> Option<usize> {
+    self.move_costs.get(&movement).copied()
+  }
+}
+
+/// Every kind of terrain a level's IntGrid can paint, keyed by IntGrid value.
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct TerrainRegistry {
+  terrain: Vec<Terrain>,
+  /// Indices into `terrain`, by IntGrid value.
+  #[serde(skip)]
+  by_value: Vec<Option<usize>>,
+}
+
+impl TerrainRegistry {
+  /// The terrain painted by `value` in a level's IntGrid.
+  pub fn get(&self, value: i32) -> Option<&Terrain> {
+    let index = (*self.by_value.get(usize::try_from(value).ok()?)?)?;
+    self.terrain.get(index)
+  }
+
+  pub fn by_id(&self, id: &str) -> Option<&Terrain> {
+    self.terrain.iter().find(|terrain| terrain.id == id)
+  }
+
+  /// Reads a registry from the contents of a `.terrain.ron` file, making sure
+  /// no two terrains share an IntGrid value or an id.
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self, TerrainRegistryError> {
+    let mut registry: TerrainRegistry = ron::de::from_bytes(bytes)?;
+
+    let mut ids = HashSet::new();
+    for (index, terrain) in registry.terrain.iter().enumerate() {
+      if !ids.insert(terrain.id.as_str()) {
+        return Err(TerrainRegistryError::DuplicateId(terrain.id.clone()));
+      }
+      // 0 is what LDtk leaves in cells that have not been painted
+      let value = match usize::try_from(terrain.value) {
+        Ok(value) if value > 0 => value,
+        _ => {
+          return Err(TerrainRegistryError::BadValue(
+            terrain.id.clone(),
+            terrain.value,
+          ))
+        }
+      };
+      if registry.by_value.len() <= value {
+        registry.by_value.resize(value + 1, None);
+      }
+      if registry.by_value[value].replace(index).is_some() {
+        return Err(TerrainRegistryError::DuplicateValue(terrain.value));
+      }
+    }
+    Ok(registry)
+  }
+}
+
+#[derive(Resource)]
+pub struct TerrainRegistryHandle(pub Handle<TerrainRegistry>);
+
+/// The loaded terrain registry, read through the asset so that an edited
+/// file takes effect as soon as it is reloaded.
+#[derive(SystemParam)]
+pub struct LoadedTerrain<'w> {
+  handle: Res<'w, TerrainRegistryHandle>,
+  registries: Res<'w, Assets<TerrainRegistry>>,
+}
+
+impl LoadedTerrain<'_> {
+  pub fn get(&self) -> Option<&TerrainRegistry> {
+    self.registries.get(&self.handle.0)
+  }
+}
+
+#[derive(Debug)]
+pub enum TerrainRegistryError {
+  Io(std::io::Error),
+  Parse(ron::error::SpannedError),
+  BadValue(String, i32),
+  DuplicateValue(i32),
+  DuplicateId(String),
+}
+
+impl std::fmt::Display for TerrainRegistryError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      TerrainRegistryError::Io(err) => write!(f, "could not read: {}", err),
+      TerrainRegistryError::Parse(err) => {
+        write!(f, "could not parse: {}", err)
+      }
+      TerrainRegistryError::BadValue(id, value) => write!(
+        f,
+        "terrain {} has IntGrid value {}; values start from 1",
+        id, value
+      ),
+      TerrainRegistryError::DuplicateValue(value) => {
+        write!(
+          f,
+          "IntGrid value {} is given to more than one terrain",
+          value
+        )
+      }
+      TerrainRegistryError::DuplicateId(id) => {
+        write!(f, "terrain {} is defined more than once", id)
+      }
+    }
+  }
+}
+
+impl std::error::Error for TerrainRegistryError {}
+
+impl From<std::io::Error> for TerrainRegistryError {
+  fn from(err: std::io::Error) -> Self {
+    TerrainRegistryError::Io(err)
+  }
+}
+
+impl From<ron::error::SpannedError> for TerrainRegistryError {
+  fn from(err: ron::error::SpannedError) -> Self {
+    TerrainRegistryError::Parse(err)
+  }
+}
+
+/// Reads `.terrain.ron` files into a [`TerrainRegistry`].
+#[derive(Default)]
+pub struct TerrainRegistryLoader;
+
+impl AssetLoader for TerrainRegistryLoader {
+  type Asset = TerrainRegistry;
+  type Settings = ();
+  type Error = TerrainRegistryError;
+
+  fn load<'a>(
+    &'a self,
+    reader: &'a mut Reader,
+    _settings: &'a (),
+    _load_context: &'a mut LoadContext,
+  ) -> BoxedFuture<'a, Result<TerrainRegistry, TerrainRegistryError>> {
+    Box::pin(async move {
+      let mut bytes = Vec::new();
+      reader.read_to_end(&mut bytes).await?;
+      TerrainRegistry::from_bytes(&bytes)
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["terrain.ron"]
   }
 }
 
-#[derive(Debug, Resource)]
+/// The terrain of every cell of the current level, as IntGrid values laid
+/// out row by row from the bottom, along with the registry that gives them
+/// meaning.
+#[derive(Debug, Clone, Resource)]
 pub struct TileTypes {
-  pub watery: HashSet<GridCoords>,
-  pub grassy: HashSet<GridCoords>,
-  pub mountainous: HashSet<GridCoords>,
-  pub forested: HashSet<GridCoords>,
+  width: usize,
+  height: usize,
+  values: Vec<i32>,
+  registry: TerrainRegistry,
+}
+
+impl TileTypes {
+  /// A level of the given size with nothing painted on it.
+  pub fn new(width: usize, height: usize, registry: &TerrainRegistry) -> Self {
+    TileTypes {
+      width,
+      height,
+      values: vec![0; width * height],
+      registry: registry.clone(),
+    }
+  }
+
+  fn index(&self, grid_coords: &GridCoords) -> Option<usize> {
+    let x = usize::try_from(grid_coords.x).ok()?;
+    let y = usize::try_from(grid_coords.y).ok()?;
+    (x < self.width && y < self.height).then_some(y * self.width + x)
+  }
+
+  pub fn set(&mut self, grid_coords: &GridCoords, value: i32) {
+    if let Some(index) = self.index(grid_coords) {
+      self.values[index] = value;
+    }
+  }
+
+  /// The IntGrid value painted at `grid_coords`, or 0 if there is none.
+  pub fn value_at(&self, grid_coords: &GridCoords) -> i32 {
+    self
+      .index(grid_coords)
+      .map_or(0, |index| self.values[index])
+  }
+
+  pub fn terrain_at(&self, grid_coords: &GridCoords) -> Option<&Terrain> {
+    self.registry.get(self.value_at(grid_coords))
+  }
 }
 
 pub fn cache_tile_types(
   mut commands: Commands,
   mut level_events: EventReader<LevelEvent>,
-  watery_tiles: Query<&GridCoords, With<Watery>>,
-  grassy_tiles: Query<&GridCoords, With<Grassy>>,
-  mountainous_tiles: Query<&GridCoords, With<Mountainous>>,
-  forested_tiles: Query<&GridCoords, With<Forested>>,
+  loaded_terrain: LoadedTerrain,
+  level_size: Option<Res<LevelSize>>,
+  int_grid_cells: Query<(&GridCoords, &IntGridCell)>,
 ) {
   for level_event in level_events.read() {
     if let LevelEvent::Spawned(_) = level_event {
-      let tile_types = TileTypes {
-        watery: HashSet::from_iter(watery_tiles.iter().copied()),
-        grassy: HashSet::from_iter(grassy_tiles.iter().copied()),
-        mountainous: HashSet::from_iter(mountainous_tiles.iter().copied()),
-        forested: HashSet::from_iter(forested_tiles.iter().copied()),
+      let (Some(registry), Some(level_size)) =
+        (loaded_terrain.get(), level_size.as_ref())
+      else {
+        continue;
       };
+      let size = level_size.as_tilemap_size();
+      let mut tile_types =
+        TileTypes::new(size.x as usize, size.y as usize, registry);
+      for (grid_coords, cell) in int_grid_cells.iter() {
+        tile_types.set(grid_coords, cell.value);
+      }
 
       commands.insert_resource(tile_types);
     }
   }
 }
+
+/// Brings the current level's terrain up to date with an edited registry.
+/// The cells keep their values, which the new registry may now read
+/// differently.
+fn refresh_tile_types(
+  mut registry_events: EventReader<AssetEvent<TerrainRegistry>>,
+  loaded_terrain: LoadedTerrain,
+  mut tile_types: ResMut<TileTypes>,
+) {
+  let modified = registry_events
+    .read()
+    .any(|event| matches!(event, AssetEvent::Modified { .. }));
+  if let (true, Some(registry)) = (modified, loaded_terrain.get()) {
+    info!("terrain registry changed, updating the level's terrain");
+    tile_types.registry = registry.clone();
+  }
+}
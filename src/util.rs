@@ -1,7 +1,9 @@
 use bevy_ecs_ldtk::GridCoords;
 use bevy_ecs_tilemap::tiles::TilePos;
 
-pub fn neighbours(grid_coord: &GridCoords) -> impl IntoIterator<Item=GridCoords> {
+pub fn neighbours(
+  grid_coord: &GridCoords,
+) -> impl IntoIterator<Item = GridCoords> {
   [
     GridCoords {
       x: grid_coord.x + 1,
@@ -28,3 +30,58 @@ pub fn grid_to_tile(grid_coords: GridCoords) -> TilePos {
     y: grid_coords.y as u32,
   }
 }
+
+pub fn tile_to_grid(tile_pos: TilePos) -> GridCoords {
+  GridCoords {
+    x: tile_pos.x as i32,
+    y: tile_pos.y as i32,
+  }
+}
+
+/// Serde adapters for [`GridCoords`], which does not implement the serde
+/// traits itself. Coordinates are written as plain `(x, y)` tuples.
+pub mod serde_grid_coords {
+  use bevy_ecs_ldtk::GridCoords;
+  use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+  pub fn serialize<S: Serializer>(
+    grid_coords: &GridCoords,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    (grid_coords.x, grid_coords.y).serialize(serializer)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<GridCoords, D::Error> {
+    let (x, y) = <(i32, i32)>::deserialize(deserializer)?;
+    Ok(GridCoords { x, y })
+  }
+
+  pub mod vec {
+    use bevy_ecs_ldtk::GridCoords;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+      path: &[GridCoords],
+      serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+      path
+        .iter()
+        .map(|grid_coords| (grid_coords.x, grid_coords.y))
+        .collect::<Vec<_>>()
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+      deserializer: D,
+    ) -> Result<Vec<GridCoords>, D::Error> {
+      Ok(
+        Vec::<(i32, i32)>::deserialize(deserializer)?
+          .into_iter()
+          .map(|(x, y)| GridCoords { x, y })
+          .collect(),
+      )
+    }
+  }
+}
@@ -1,5 +1,365 @@
 use bevy::prelude::*;
 
-pub fn create_window(mut commands: Commands) -> Entity {
-  commands.spawn(NodeBundle { ..default() }).id()
+use crate::{
+  assets::{RequiredAssets, ATLAS_INFO},
+  game::input::{Action, Actions},
+  sprites::{SpriteManifest, SpriteManifestHandle},
+};
+
+pub const FONT_PATH: &str = "JacquardaBastarda9-Regular.ttf";
+const WINDOW_FRAME: &str = "window/frame";
+
+/// Width of the frame's border within its atlas tile, in pixels.
+const FRAME_BORDER: f32 = 4.0;
+/// How many screen pixels each pixel of a frame covers.
+const FRAME_SCALE: f32 = 3.0;
+const TEXT_COLOR: Color = Color::rgb(0.969, 0.953, 0.718);
+const SELECTED_COLOR: Color = Color::rgb(0.953, 0.659, 0.2);
+
+pub struct WindowsPlugin;
+
+impl Plugin for WindowsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<WindowFocus>()
+      .add_event::<MenuChosen>()
+      .add_event::<MenuCancelled>()
+      .add_systems(Startup, init_window_assets)
+      .add_systems(
+        Update,
+        (
+          update_frame_layout,
+          prune_window_focus,
+          navigate_list_menus,
+          highlight_list_menus,
+        )
+          .chain(),
+      );
+  }
+}
+
+/// The frame slices and font shared by every window.
+#[derive(Resource, Clone)]
+pub struct WindowAssets {
+  pub font: Handle<Font>,
+  frame_layout: Handle<TextureAtlasLayout>,
+}
+
+/// Windows that capture input, most recently opened last. While any is open,
+/// input goes to the top one rather than to the board.
+#[derive(Resource, Default)]
+pub struct WindowFocus(Vec<Entity>);
+
+impl WindowFocus {
+  pub fn push(&mut self, window: Entity) {
+    self.0.push(window);
+  }
+
+  pub fn top(&self) -> Option<Entity> {
+    self.0.last().copied()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+}
+
+#[derive(Default, Component)]
+pub struct GameWindow;
+
+/// A window whose lines can be stepped through and chosen.
+#[derive(Component)]
+pub struct ListMenu {
+  pub selected: usize,
+  items: Vec<String>,
+}
+
+impl ListMenu {
+  /// Relabels an item in place, for menus whose lines show a setting.
+  pub fn set_item(&mut self, index: usize, label: impl Into<String>) {
+    if let Some(item) = self.items.get_mut(index) {
+      *item = label.into();
+    }
+  }
+}
+
+/// One line of a [`ListMenu`], spawned in its window's contents.
+#[derive(Component)]
+struct ListMenuItem(usize);
+
+/// Sent when an item of a focused [`ListMenu`] is chosen. The menu stays
+/// open until whoever opened it closes it.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MenuChosen {
+  pub menu: Entity,
+  pub index: usize,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct MenuCancelled {
+  pub menu: Entity,
+}
+
+/// The frame's slices are cut by [`update_frame_layout`] once the sprite
+/// manifest has loaded.
+pub fn init_window_assets(
+  mut commands: Commands,
+  server: Res<AssetServer>,
+  mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+  mut required_assets: ResMut<RequiredAssets>,
+) {
+  let font = server.load(FONT_PATH);
+  required_assets.add(&font);
+  commands.insert_resource(WindowAssets {
+    font,
+    frame_layout: layouts.add(TextureAtlasLayout::new_empty(Vec2::ZERO)),
+  });
+}
+
+/// Splits the frame tile into its nine slices: the corners, the edges
+/// between them, and the middle, in reading order.
+fn update_frame_layout(
+  mut manifest_events: EventReader<AssetEvent<SpriteManifest>>,
+  handle: Res<SpriteManifestHandle>,
+  manifests: Res<Assets<SpriteManifest>>,
+  window_assets: Res<WindowAssets>,
+  mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+  let loaded = manifest_events.read().any(|event| {
+    matches!(
+      event,
+      AssetEvent::LoadedWithDependencies { .. } | AssetEvent::Modified { .. }
+    )
+  });
+  let Some(manifest) = manifests.get(&handle.0).filter(|_| loaded) else {
+    return;
+  };
+  let Some(tile) = manifest.rect(WINDOW_FRAME) else {
+    warn!("no sprite is named {}", WINDOW_FRAME);
+    return;
+  };
+  let cuts = [
+    0.0,
+    FRAME_BORDER,
+    manifest.tile_size - FRAME_BORDER,
+    manifest.tile_size,
+  ];
+
+  let mut frame_layout = TextureAtlasLayout::new_empty(manifest.size());
+  for row in 0..3 {
+    for column in 0..3 {
+      frame_layout.add_texture(Rect::new(
+        tile.min.x + cuts[column],
+        tile.min.y + cuts[row],
+        tile.min.x + cuts[column + 1],
+        tile.min.y + cuts[row + 1],
+      ));
+    }
+  }
+  layouts.insert(&window_assets.frame_layout, frame_layout);
+}
+
+pub fn text(
+  window_assets: &WindowAssets,
+  value: impl Into<String>,
+  font_size: f32,
+) -> TextBundle {
+  TextBundle::from_section(
+    value,
+    TextStyle {
+      font: window_assets.font.clone(),
+      font_size,
+      color: TEXT_COLOR,
+    },
+  )
+}
+
+/// Spawns a framed window laid out by `style`, filling it with whatever
+/// `contents` spawns.
+pub fn spawn_window(
+  commands: &mut Commands,
+  window_assets: &WindowAssets,
+  style: Style,
+  attachments: impl Bundle,
+  contents: impl FnOnce(&mut ChildBuilder),
+) -> Entity {
+  let border = FRAME_BORDER * FRAME_SCALE;
+  let image = ATLAS_INFO.get().unwrap().image.clone();
+
+  commands
+    .spawn((
+      NodeBundle {
+        style: Style {
+          display: Display::Grid,
+          grid_template_columns: vec![
+            GridTrack::px(border),
+            GridTrack::auto(),
+            GridTrack::px(border),
+          ],
+          grid_template_rows: vec![
+            GridTrack::px(border),
+            GridTrack::auto(),
+            GridTrack::px(border),
+          ],
+          ..style
+        },
+        z_index: ZIndex::Global(10),
+        ..default()
+      },
+      GameWindow,
+      attachments,
+    ))
+    .with_children(|window| {
+      for slice in 0..9 {
+        window.spawn(AtlasImageBundle {
+          style: Style {
+            grid_row: GridPlacement::start(slice as i16 / 3 + 1),
+            grid_column: GridPlacement::start(slice as i16 % 3 + 1),
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            ..default()
+          },
+          image: UiImage::new(image.clone()),
+          texture_atlas: TextureAtlas {
+            layout: window_assets.frame_layout.clone(),
+            index: slice,
+          },
+          ..default()
+        });
+      }
+
+      // laid over the middle slice, so that the frame's fill shows behind
+      window
+        .spawn(NodeBundle {
+          style: Style {
+            grid_row: GridPlacement::start(2),
+            grid_column: GridPlacement::start(2),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.0),
+            padding: UiRect::all(Val::Px(4.0)),
+            ..default()
+          },
+          ..default()
+        })
+        .with_children(contents);
+    })
+    .id()
+}
+
+/// Spawns a window listing `items`, and gives it focus so that it takes the
+/// player's input until it is closed.
+pub fn spawn_list_menu(
+  commands: &mut Commands,
+  window_assets: &WindowAssets,
+  window_focus: &mut WindowFocus,
+  style: Style,
+  title: Option<&str>,
+  items: &[String],
+  attachments: impl Bundle,
+) -> Entity {
+  let window = spawn_window(
+    commands,
+    window_assets,
+    style,
+    (
+      ListMenu {
+        selected: 0,
+        items: items.to_vec(),
+      },
+      attachments,
+    ),
+    |contents| {
+      if let Some(title) = title {
+        contents.spawn(text(window_assets, title, 28.0));
+      }
+      for (index, item) in items.iter().enumerate() {
+        contents.spawn((
+          text(window_assets, item.clone(), 24.0),
+          ListMenuItem(index),
+        ));
+      }
+    },
+  );
+
+  window_focus.push(window);
+  window
+}
+
+/// Drops windows from the focus stack once they have been despawned, so that
+/// closing a window is only a matter of despawning it.
+pub fn prune_window_focus(
+  mut window_focus: ResMut<WindowFocus>,
+  mut closed_windows: RemovedComponents<GameWindow>,
+) {
+  for closed_window in closed_windows.read() {
+    window_focus.0.retain(|window| *window != closed_window);
+  }
+}
+
+pub fn navigate_list_menus(
+  actions: Res<Actions>,
+  window_focus: Res<WindowFocus>,
+  mut menus: Query<&mut ListMenu>,
+  mut chosen_events: EventWriter<MenuChosen>,
+  mut cancelled_events: EventWriter<MenuCancelled>,
+) {
+  let Some(focused) = window_focus.top() else {
+    return;
+  };
+  let Ok(mut menu) = menus.get_mut(focused) else {
+    return;
+  };
+  let len = menu.items.len();
+  if len == 0 {
+    return;
+  }
+
+  if actions.any_just_pressed(Action::Up) {
+    menu.selected = (menu.selected + len - 1) % len;
+  }
+  if actions.any_just_pressed(Action::Down) {
+    menu.selected = (menu.selected + 1) % len;
+  }
+
+  if actions.any_just_pressed(Action::Confirm) {
+    chosen_events.send(MenuChosen {
+      menu: focused,
+      index: menu.selected,
+    });
+  } else if actions.any_just_pressed(Action::Cancel) {
+    cancelled_events.send(MenuCancelled { menu: focused });
+  }
+}
+
+/// Marks the selected line of every menu, and keeps each line's text in step
+/// with its label.
+pub fn highlight_list_menus(
+  menus: Query<&ListMenu>,
+  parents: Query<&Parent>,
+  mut items: Query<(&ListMenuItem, &Parent, &mut Text)>,
+) {
+  for (ListMenuItem(index), contents, mut text) in items.iter_mut() {
+    // items sit in the window's contents, one level below the window itself
+    let Some(menu) = parents
+      .get(contents.get())
+      .ok()
+      .and_then(|window| menus.get(window.get()).ok())
+    else {
+      continue;
+    };
+    let color = if *index == menu.selected {
+      SELECTED_COLOR
+    } else {
+      TEXT_COLOR
+    };
+    let label = menu.items.get(*index).map_or("", |item| item.as_str());
+    for section in text.sections.iter_mut() {
+      if section.style.color != color {
+        section.style.color = color;
+      }
+      if section.value != label {
+        section.value = label.to_string();
+      }
+    }
+  }
 }